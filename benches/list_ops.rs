@@ -0,0 +1,117 @@
+//! Head and tail operations on RedisList against the Vec layout it used to have
+//!
+//! cargo bench --bench list_ops [-- ELEMENTS], 100000 elements by default
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use codecrafters_redis::redis_list::RedisList;
+
+/// The old storage, LPUSH was a splice at 0 and LPOP a remove(0)
+#[derive(Default)]
+struct VecList {
+    values: Vec<Vec<u8>>,
+}
+
+impl VecList {
+    fn push(&mut self, value: Vec<u8>, left: bool) {
+        if left {
+            self.values.splice(0..0, [value]);
+        } else {
+            self.values.push(value);
+        }
+    }
+
+    fn pop_front(&mut self) -> Vec<u8> {
+        self.values.remove(0)
+    }
+}
+
+fn element(i: usize) -> Vec<u8> {
+    format!("job:{i}").into_bytes()
+}
+
+fn time(f: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn report(name: &str, n: usize, deque: Duration, vec: Duration) {
+    println!(
+        "{name:<12} {n:>9} ops   deque {:>10.2?}   vec {:>10.2?}   {:>8.1}x",
+        deque,
+        vec,
+        vec.as_secs_f64() / deque.as_secs_f64().max(f64::EPSILON)
+    );
+}
+
+fn main() {
+    let n = env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(100_000);
+
+    // LPUSH n elements
+    let mut list = RedisList::new();
+    let deque = time(|| (0..n).for_each(|i| list.push(element(i), true)));
+    let mut old = VecList::default();
+    let vec = time(|| (0..n).for_each(|i| old.push(element(i), true)));
+    report("LPUSH", n, deque, vec);
+
+    // LINDEX across the list
+    let deque = time(|| {
+        (0..n as i64).for_each(|i| {
+            black_box(list.get(i));
+        })
+    });
+    let vec = time(|| {
+        (0..n).for_each(|i| {
+            black_box(old.values.get(i));
+        })
+    });
+    report("LINDEX", n, deque, vec);
+
+    // LRANGE of 100 elements from the middle
+    let mid = n as i64 / 2;
+    let deque = time(|| {
+        (0..1000).for_each(|_| {
+            black_box(list.range(mid, mid + 99).map(Vec::len).sum::<usize>());
+        })
+    });
+    let vec = time(|| {
+        (0..1000).for_each(|_| {
+            let end = (n / 2 + 100).min(n);
+            black_box(old.values[n / 2..end].iter().map(Vec::len).sum::<usize>());
+        })
+    });
+    report("LRANGE 100", 1000, deque, vec);
+
+    // LPOP until empty, as a queue consumer would
+    let deque = time(|| {
+        (0..n).for_each(|_| {
+            black_box(list.pop(1, true));
+        })
+    });
+    let vec = time(|| {
+        (0..n).for_each(|_| {
+            black_box(old.pop_front());
+        })
+    });
+    report("LPOP", n, deque, vec);
+
+    // RPUSH then LPOP, a FIFO queue
+    let deque = time(|| {
+        (0..n).for_each(|i| list.push(element(i), false));
+        (0..n).for_each(|_| {
+            black_box(list.pop(1, true));
+        })
+    });
+    let vec = time(|| {
+        (0..n).for_each(|i| old.push(element(i), false));
+        (0..n).for_each(|_| {
+            black_box(old.pop_front());
+        })
+    });
+    report("RPUSH+LPOP", n, deque, vec);
+}
@@ -10,6 +10,7 @@
 //pub const PSYNC: &str = "PSYNC";
 //pub const FULLRESYNC: &str = "FULLRESYNC";
 //pub const DEFAULT_PORT: &str = "6379";
+pub const REDIS_VERSION: &str = "7.2.0";
 //pub const NOT_INT_ERROR: &[u8; 46] = b"-ERR value is not an integer or out of range\r\n";
 //pub const EXEC_WITHOUT_MULTI: &[u8; 25] = b"-ERR EXEC without MULTI\r\n";
 //pub const QUEUED_RESP: &[u8; 9] = b"+QUEUED\r\n";
@@ -34,20 +35,38 @@ pub const LISTENING_PORT: &str = "listening-port";
 pub const PSYNC: &str = "PSYNC";
 pub const FULLRESYNC: &str = "FULLRESYNC";
 pub const DEFAULT_PORT: &str = "6379";
-pub const NOT_INT_ERROR: &str = "-ERR value is not an integer or out of range\r\n";
-pub const EXEC_WITHOUT_MULTI: &str = "-ERR EXEC without MULTI\r\n";
-pub const QUEUED_RESP: &str = "+QUEUED\r\n";
-pub const PONG_RESPONSE: &str = "+PONG\r\n";
-pub const RESP_OK: &str = "+OK\r\n";
-pub const RESP_NULL: &str = "$-1\r\n";
-pub const ZERO_INT: &str = ":0\r\n";
-pub const STRING: &str = "+string\r\n";
-pub const NONE_TYPE: &str = "+none\r\n";
-pub const ZERO_ERROR: &str = "-ERR The ID specified in XADD must be greater than 0-0\r\n";
+pub const NOT_INT_ERROR: &str = "ERR value is not an integer or out of range";
+pub const EXEC_WITHOUT_MULTI: &str = "ERR EXEC without MULTI";
+pub const EXECABORT_ERROR: &str = "EXECABORT Transaction discarded because of previous errors.";
+pub const ZERO_ERROR: &str = "ERR The ID specified in XADD must be greater than 0-0";
 pub const SMALLER_ERROR: &str =
-    "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n";
-pub const EMPTY_ARRAY: &str = "*0\r\n";
-pub const SUBCRIBED_ERROR:&str= "-ERR Can't execute 'echo': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n";
+    "ERR The ID specified in XADD is equal or smaller than the target stream top item";
+pub const TIMEOUT_NOT_INT_ERROR: &str = "ERR timeout is not an integer or out of range";
+pub const TIMEOUT_NOT_FLOAT_ERROR: &str = "ERR timeout is not a float or out of range";
+pub const TIMEOUT_OUT_OF_RANGE_ERROR: &str = "ERR timeout is out of range";
+pub const OUT_OF_RANGE_ERROR: &str = "ERR value is out of range";
+pub const NOT_POSITIVE_ERROR: &str = "ERR value is out of range, must be positive";
+pub const NOT_FLOAT_ERROR: &str = "ERR value is not a valid float";
+pub const OVERFLOW_ERROR: &str = "ERR increment or decrement would overflow";
+pub const NAN_OR_INF_ERROR: &str = "ERR increment would produce NaN or Infinity";
+pub const HASH_NOT_INT_ERROR: &str = "ERR hash value is not an integer";
+pub const HASH_NOT_FLOAT_ERROR: &str = "ERR hash value is not a float";
+pub const OFFSET_OUT_OF_RANGE_ERROR: &str = "ERR offset is out of range";
+pub const STRING_TOO_LONG_ERROR: &str =
+    "ERR string exceeds maximum allowed size (proto-max-bulk-len)";
+pub const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+/// Aggregates a RESP value may nest, deeper input is a protocol error rather than a stack overflow
+pub const MAX_NESTING: usize = 32;
+pub const NO_SUCH_KEY_ERROR: &str = "ERR no such key";
+pub const INDEX_OUT_OF_RANGE_ERROR: &str = "ERR index out of range";
+pub const SYNTAX_ERROR: &str = "ERR syntax error";
+pub const WRONGTYPE_ERROR: &str =
+    "WRONGTYPE Operation against a key holding the wrong kind of value";
+pub const NOPROTO_ERROR: &str = "NOPROTO unsupported protocol version";
+pub const WRONGPASS_ERROR: &str = "WRONGPASS invalid username-password pair or user is disabled.";
+pub const CLIENT_NAME_ERROR: &str =
+    "ERR Client names cannot contain spaces, newlines or special characters.";
+pub const SUBCRIBED_ERROR:&str= "ERR Can't execute 'echo': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context";
 pub const ALLOWED_SUB_COMMANDS: [&str; 6] = [
     "SUBSCRIBE",
     "UNSUBSCRIBE",
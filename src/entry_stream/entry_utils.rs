@@ -1,15 +1,23 @@
-use crate::constants::*;
-use crate::entry_stream::RedisEntry;
-use crate::utils::get_bulk_string;
+use crate::entry_stream::StreamRead;
+use crate::resp::RespValue;
+use crate::utils::arg_str;
 
 // Get stream name and seq id pairs
-pub fn get_all_stream_names(lines: &[String]) -> Vec<(String, String)> {
+pub fn get_all_stream_names(lines: &[Vec<u8>]) -> Vec<(Vec<u8>, String)> {
     let mut use_vec = Vec::new();
     for (i, element) in lines.iter().enumerate() {
         if i == lines.len() - 1 {
             break;
         }
-        if element.contains("-") && element.split('-').nth(0).unwrap().parse::<usize>().is_ok() {
+        let element_str = arg_str(element);
+        if element_str.contains("-")
+            && element_str
+                .split('-')
+                .next()
+                .unwrap()
+                .parse::<usize>()
+                .is_ok()
+        {
             break;
         }
         use_vec.push(element);
@@ -18,16 +26,16 @@ pub fn get_all_stream_names(lines: &[String]) -> Vec<(String, String)> {
     let use_vec = use_vec
         .iter()
         .enumerate()
-        .map(|(i, e)| (e.to_string(), lines[i + num_names].clone()))
+        .map(|(i, e)| (e.to_vec(), arg_str(&lines[i + num_names]).to_string()))
         .collect();
     use_vec
 }
 
 //pub fn get_xread_resp_array(v: &Vec<(String, Vec<(String, RedisEntry)>)>) -> Vec<u8> {
-pub fn get_xread_resp_array(v: &Vec<(String, Vec<(String, RedisEntry)>)>) -> String {
+pub fn get_xread_resp_array(v: &[StreamRead]) -> RespValue {
     if v.is_empty() {
         //eprintln!("getting resp arr for empty");
-        return RESP_NULL.into();
+        return RespValue::Null;
     }
 
     //let mut resp = b"*1\r\n".to_vec();
@@ -42,16 +50,20 @@ pub fn get_xread_resp_array(v: &Vec<(String, Vec<(String, RedisEntry)>)>) -> Str
     //         resp.extend(ent.entry_resp_array());
     //     });
     // }
-    let mut resp = format!("*{}\r\n", v.len());
-    for (stream_name, id_entry) in v {
-        resp.push_str("*2\r\n");
-        resp.push_str(&get_bulk_string(stream_name));
-        resp.push_str("*1\r\n");
-        id_entry.iter().for_each(|(entry_id, ent)| {
-            resp.push_str("*2\r\n");
-            resp.push_str(&get_bulk_string(&entry_id));
-            resp.push_str(&ent.entry_resp_array());
-        });
-    }
-    resp
+    let streams = v
+        .iter()
+        .map(|(stream_name, id_entry)| {
+            let entries = id_entry
+                .iter()
+                .map(|(entry_id, ent)| {
+                    RespValue::Array(vec![RespValue::bulk(entry_id), ent.entry_resp_array()])
+                })
+                .collect();
+            RespValue::Array(vec![
+                RespValue::bulk(stream_name),
+                RespValue::Array(entries),
+            ])
+        })
+        .collect();
+    RespValue::Array(streams)
 }
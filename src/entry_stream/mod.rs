@@ -1,26 +1,26 @@
 use std::{
     collections::HashMap,
-    io::Write,
-    net::TcpStream,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::entry_stream::entry_utils::get_xread_resp_array;
-use crate::utils::get_bulk_string;
+use crate::resp::RespValue;
 
 pub mod entry_utils;
 use crate::constants::*;
 
+/// A stream name with the entries read from it, keyed by entry id
+pub type StreamRead = (Vec<u8>, Vec<(String, RedisEntry)>);
+
 #[derive(Debug, Clone)]
 pub struct RedisEntry {
-    pub values: Vec<(String, String)>,
+    pub values: Vec<(Vec<u8>, Vec<u8>)>,
     pub next_sequence_id: Option<String>,
     pub prev_entry_id: Option<String>,
     pub insertion_time: SystemTime,
 }
 
 impl RedisEntry {
-    pub fn new(v: Vec<(String, String)>, prev_entry_id: Option<String>) -> Self {
+    pub fn new(v: Vec<(Vec<u8>, Vec<u8>)>, prev_entry_id: Option<String>) -> Self {
         Self {
             values: v,
             next_sequence_id: None,
@@ -29,29 +29,23 @@ impl RedisEntry {
         }
     }
 
-    pub fn entry_resp_array(&self) -> String {
-        let mut use_vec: Vec<&str> = Vec::new();
+    pub fn entry_resp_array(&self) -> RespValue {
+        let mut use_vec: Vec<&[u8]> = Vec::new();
         self.values.iter().for_each(|e| {
-            use_vec.extend([&e.0.as_str(), &e.1.as_str()]);
+            use_vec.extend([e.0.as_slice(), e.1.as_slice()]);
         });
 
-        let mut resp = format!("*{}\r\n", use_vec.len());
-        for element in use_vec {
-            resp.push_str(&format!("${}\r\n{}\r\n", element.len(), element));
-        }
-        resp
+        RespValue::bulk_array(use_vec)
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct RedisEntryStream {
     pub entries: HashMap<String, RedisEntry>,
     pub last_id: (usize, usize),
     pub sequences: HashMap<usize, usize>,
     pub first_sequence_id: Option<String>,
     pub last_sequence_id: Option<String>,
-    //TODO: hashmap or vec?
-    pub waiting_streams: HashMap<String, TcpStream>,
 }
 
 impl RedisEntryStream {
@@ -61,15 +55,20 @@ impl RedisEntryStream {
 
     pub fn get_next_sequence(&mut self, seq: usize) -> usize {
         //eprintln!("in get sequence, SEQUENCES:{:?}", self.sequences);
-        let ret = self.sequences.entry(seq).or_insert(0).clone();
+        let ret = *self.sequences.entry(seq).or_insert(0);
         *(self.sequences.get_mut(&seq).unwrap()) += 1;
         //eprintln!("in get sequence, returning:{ret}");
         ret
     }
 
     //pub fn handle_add(&mut self, entry_id: &str, use_vec: Vec<(String, String)>) -> Vec<u8> {
-    pub fn handle_add(&mut self, entry_id: &str, use_vec: Vec<(String, String)>) -> String {
-        let res = self.stream_id_response(&entry_id);
+    /// Appends an entry, returns whether it was added along with the RESP reply
+    pub fn handle_add(
+        &mut self,
+        entry_id: &str,
+        use_vec: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> (bool, RespValue) {
+        let res = self.stream_id_response(entry_id);
         ////eprintln!("xadd result:{:?}", String::from_utf8_lossy(&res.1));
         //eprintln!("xadd result:{:?}", &res.1);
 
@@ -92,31 +91,16 @@ impl RedisEntryStream {
                 }
             }
 
-            eprintln!("IN XADD waiting:{:?}", self.waiting_streams);
-            if !self.waiting_streams.is_empty() {
-                eprintln!("\n WAITIN FORRR \n\n");
-                for (k, mut st) in &self.waiting_streams {
-                    let mut resp_args = Vec::new();
-                    let mut stream_id_and_entry = Vec::new();
-                    stream_id_and_entry.push((entry_id.to_string(), use_entry.clone()));
-                    resp_args.push((k.clone(), stream_id_and_entry));
-                    st.write_all(&get_xread_resp_array(&resp_args).as_bytes())
-                        .unwrap();
-                }
-                eprintln!("AFTER FOR");
-                self.waiting_streams = HashMap::new();
-            }
-
             //eprintln!("creating entry with vec:{:?}", use_entry);
             self.entries.insert(entry_id.to_string(), use_entry);
 
             eprintln!("succesful insert curr stream:{:?}", self);
         }
-        res.1
+        res
     }
 
     //pub fn stream_id_response(&mut self, id: &str) -> (bool, Vec<u8>) {
-    pub fn stream_id_response(&mut self, id: &str) -> (bool, String) {
+    pub fn stream_id_response(&mut self, id: &str) -> (bool, RespValue) {
         let mut parts: Vec<_> = Vec::new();
 
         if id == "*" {
@@ -129,7 +113,7 @@ impl RedisEntryStream {
             let use_id = format!("{since_the_epoch}-0");
             self.sequences.insert(since_the_epoch, 1);
             //eprintln!("after inser:{:?}", self.sequences);
-            return (true, get_bulk_string(&use_id));
+            return (true, RespValue::bulk(&use_id));
         }
 
         for part in id.split("-") {
@@ -149,7 +133,7 @@ impl RedisEntryStream {
                         parts.push(seq_num);
                     } else {
                         //return (false, SMALLER_ERROR.into());
-                        return (false, SMALLER_ERROR.to_string());
+                        return (false, RespValue::error(SMALLER_ERROR));
                     }
                 }
             }
@@ -158,7 +142,7 @@ impl RedisEntryStream {
 
         if parts[1] < 1 && parts[0] < 1 {
             //eprintln!("got 0");
-            return (false, ZERO_ERROR.into());
+            return (false, RespValue::error(ZERO_ERROR));
         }
 
         if parts[0] > self.last_id.0 || (parts[0] == self.last_id.0 && parts[1] > self.last_id.1) {
@@ -166,18 +150,18 @@ impl RedisEntryStream {
             self.last_id = (parts[0], parts[1]);
             let use_id = format!("{}-{}", parts[0], parts[1]);
             //eprintln!("returning id:{use_id}");
-            return (true, get_bulk_string(&use_id));
+            return (true, RespValue::bulk(&use_id));
         }
 
-        return (false, SMALLER_ERROR.into());
+        (false, RespValue::error(SMALLER_ERROR))
     }
 
     //pub fn get_from_range(&self, start: &str, end: &str) -> Vec<u8> {
-    pub fn get_from_range(&self, start: &str, end: &str) -> String {
+    pub fn get_from_range(&self, start: &str, end: &str) -> RespValue {
         //eprintln!("IN XRANGE FUNC, curr entries:{:?}", self.entries);
         let mut check_keys = Vec::new();
         if self.first_sequence_id.is_none() {
-            return RESP_NULL.into();
+            return RespValue::Null;
         }
 
         let start_time = &{
@@ -204,28 +188,19 @@ impl RedisEntryStream {
             Some(_ent) => {
                 //eprintln!("got a start entry:{:?}", ent);
                 let mut curr_id = Some(start_time);
-                loop {
-                    //eprintln!("in range-loop");
-                    match curr_id {
-                        Some(use_id) => {
-                            //eprintln!("found next wntry using id:{use_id}");
-                            if !end_id.contains('-') && use_id.split('-').nth(0).unwrap() > end_id {
-                                //eprintln!("BREAKING in first loop");
-                                break;
-                            } else if end_id.contains('-') && use_id.as_str() > end_id {
-                                //eprintln!("GOT TO END of range breaking with:{:?}", check_keys);
-                                break;
-                            }
-
-                            let curr = self.entries.get(curr_id.unwrap()).unwrap();
-                            check_keys.push((use_id.clone(), curr.clone()));
-                            curr_id = curr.next_sequence_id.as_ref();
-                        }
-                        None => {
-                            //eprintln!("next id is none, breaking with:{:?}", check_keys);
-                            break;
-                        }
+                while let Some(use_id) = curr_id {
+                    //eprintln!("found next wntry using id:{use_id}");
+                    if !end_id.contains('-') && use_id.split('-').nth(0).unwrap() > end_id {
+                        //eprintln!("BREAKING in first loop");
+                        break;
+                    } else if end_id.contains('-') && use_id.as_str() > end_id {
+                        //eprintln!("GOT TO END of range breaking with:{:?}", check_keys);
+                        break;
                     }
+
+                    let curr = self.entries.get(curr_id.unwrap()).unwrap();
+                    check_keys.push((use_id.clone(), curr.clone()));
+                    curr_id = curr.next_sequence_id.as_ref();
                 }
             }
             None => return self.get_stream_resp_array(&check_keys),
@@ -237,27 +212,23 @@ impl RedisEntryStream {
         resp_arrays
     }
 
-    pub fn get_stream_resp_array(&self, v: &Vec<(String, RedisEntry)>) -> String {
+    pub fn get_stream_resp_array(&self, v: &[(String, RedisEntry)]) -> RespValue {
         if v.is_empty() {
             //eprintln!("getting resp arr for empty");
-            return RESP_NULL.into();
+            return RespValue::Null;
         }
 
-        let mut resp = format!("*{}\r\n", v.len());
-        v.iter().for_each(|(entry_id, ent)| {
-            resp.push_str("*2\r\n");
-            resp.push_str(&get_bulk_string(&entry_id));
-            resp.push_str(&ent.entry_resp_array());
-        });
+        let entries = v
+            .iter()
+            .map(|(entry_id, ent)| {
+                RespValue::Array(vec![RespValue::bulk(entry_id), ent.entry_resp_array()])
+            })
+            .collect();
 
-        resp
+        RespValue::Array(entries)
     }
     //pub fn xread_range(&self, stream_name: &str, start: &str) -> Vec<u8> {
-    pub fn xread_range(
-        &self,
-        stream_name: &str,
-        start: &str,
-    ) -> Option<(String, Vec<(String, RedisEntry)>)> {
+    pub fn xread_range(&self, stream_name: &[u8], start: &str) -> Option<StreamRead> {
         eprintln!("IN XREAD FUNC, curr entries:{:?}", self.entries);
         let mut check_keys = Vec::new();
         let start_t = {
@@ -270,8 +241,7 @@ impl RedisEntryStream {
                     .clone()
                     .unwrap()
                     .split('-')
-                    .into_iter()
-                    .gt(start.split('-').into_iter())
+                    .gt(start.split('-'))
                 {
                     self.first_sequence_id.clone()
                 } else {
@@ -284,58 +254,40 @@ impl RedisEntryStream {
             } else if start.contains('-') {
                 let excl_id = start.to_string();
 
-                match self.entries.get(&excl_id) {
-                    Some(ent) => Some(ent.next_sequence_id.clone().unwrap()),
-                    None => None,
-                }
+                self.entries
+                    .get(&excl_id)
+                    .map(|ent| ent.next_sequence_id.clone().unwrap())
             } else {
                 Some(format!("{start}-{}", 0))
             }
         };
 
-        let start_id;
-        if start_t.is_some() {
-            start_id = start_t.clone().unwrap();
-        } else {
-            return None;
-        }
+        let start_id = start_t?;
 
         eprintln!("using start:{start_id}");
-        match self.entries.get(&start_id) {
-            Some(_ent) => {
-                eprintln!("got a start entry:{:?}", _ent);
-                let mut curr_id = Some(&start_id);
-                loop {
-                    //eprintln!("in range-loop");
-                    match curr_id {
-                        Some(use_id) => {
-                            //eprintln!("found next entry using id:{use_id}");
-                            let curr = self.entries.get(use_id).unwrap();
-                            check_keys.push((use_id.clone(), curr.clone()));
-                            curr_id = curr.next_sequence_id.as_ref();
-                        }
-                        None => {
-                            //eprintln!("next id is none, breaking with:{:?}", check_keys);
-                            break;
-                        }
-                    }
-                }
+        if let Some(_ent) = self.entries.get(&start_id) {
+            eprintln!("got a start entry:{:?}", _ent);
+            let mut curr_id = Some(&start_id);
+            while let Some(use_id) = curr_id {
+                //eprintln!("found next entry using id:{use_id}");
+                let curr = self.entries.get(use_id).unwrap();
+                check_keys.push((use_id.clone(), curr.clone()));
+                curr_id = curr.next_sequence_id.as_ref();
             }
-            None => {}
         }
 
         eprintln!("Got check keys:{:?}", check_keys);
 
-        Some((stream_name.to_string(), check_keys))
+        Some((stream_name.to_vec(), check_keys))
     }
 
     pub fn block_xread(
         &self,
-        stream_name: &str,
+        stream_name: &[u8],
         block_start_time: SystemTime,
         time_to_block_for: Duration,
         start: &str,
-    ) -> Option<(String, Vec<(String, RedisEntry)>)> {
+    ) -> Option<StreamRead> {
         //eprintln!(
         //    "IN XBLOCK FUNC checking for entries starting:{:?}\n, until {:?}\n, blocking for {:?}\n, curr entries:{:?}\n, stream_name{stream_name},\n start seqid:{start}",
         //    block_start_time,
@@ -368,8 +320,7 @@ impl RedisEntryStream {
                     .clone()
                     .unwrap()
                     .split('-')
-                    .into_iter()
-                    .ge(start.split('-').into_iter())
+                    .ge(start.split('-'))
                 {
                     self.first_sequence_id.clone()
                 } else {
@@ -383,62 +334,43 @@ impl RedisEntryStream {
             } else if start.contains('-') {
                 let excl_id = start.to_string();
 
-                match self.entries.get(&excl_id) {
-                    Some(ent) => Some(ent.next_sequence_id.clone().unwrap()),
-                    None => {
-                        //eprintln!("in exclusive returning start None");
-                        None
-                    }
-                }
+                self.entries
+                    .get(&excl_id)
+                    .map(|ent| ent.next_sequence_id.clone().unwrap())
             } else {
                 Some(format!("{start}-{}", 0))
             }
         };
 
-        let start_id;
-        if start_t.is_some() {
-            start_id = start_t.clone().unwrap();
-        } else {
+        let Some(start_id) = start_t else {
             //eprintln!("breaking starttime none");
             return None;
-        }
+        };
 
-        match self.entries.get(block_start.as_ref().unwrap()) {
-            Some(_ent) => {
-                //eprintln!("got a start entry:{:?}", ent);
-                let mut curr_id = block_start;
-                loop {
-                    //eprintln!("in range-loop");
-                    match curr_id {
-                        Some(use_id) => {
-                            //eprintln!("found prev entry using id:{use_id}");
-                            if use_id < &start_id {
-                                //eprintln!("BREAKING XBLOCK went below seq start");
-                                break;
-                            }
-                            let curr = self.entries.get(use_id).unwrap();
-                            if curr.insertion_time < block_start_time
-                                || curr.insertion_time > block_start_time + time_to_block_for
-                            {
-                                //eprintln!("BLOCK EXCEEDED time, breaking\n\n");
-                                break;
-                            }
-                            check_keys.push((use_id.clone(), curr.clone()));
-                            curr_id = &curr.prev_entry_id;
-                        }
-                        None => {
-                            //eprintln!("PREVIOUS id is none, breaking with:{:?}", check_keys);
-                            break;
-                        }
-                    }
+        if let Some(_ent) = self.entries.get(block_start.as_ref().unwrap()) {
+            //eprintln!("got a start entry:{:?}", ent);
+            let mut curr_id = block_start;
+            while let Some(use_id) = curr_id {
+                //eprintln!("found prev entry using id:{use_id}");
+                if use_id < &start_id {
+                    //eprintln!("BREAKING XBLOCK went below seq start");
+                    break;
+                }
+                let curr = self.entries.get(use_id).unwrap();
+                if curr.insertion_time < block_start_time
+                    || curr.insertion_time > block_start_time + time_to_block_for
+                {
+                    //eprintln!("BLOCK EXCEEDED time, breaking\n\n");
+                    break;
                 }
+                check_keys.push((use_id.clone(), curr.clone()));
+                curr_id = &curr.prev_entry_id;
             }
-            None => {}
         }
 
         //eprintln!("Got check keys:{:?}", check_keys);
         if !check_keys.is_empty() {
-            Some((stream_name.to_string(), check_keys))
+            Some((stream_name.to_vec(), check_keys))
         } else {
             None
         }
@@ -0,0 +1,358 @@
+/*
+* Readiness based networking core
+*
+* A single thread owns every client socket, epoll tells it which ones can be read or written.
+* Each connection keeps its own read and write buffers, so a slow client only holds up itself:
+*
+*   readable -> read everything available -> run each complete command -> buffer the replies
+*   writable -> flush what the socket did not take last time
+*
+* A blocked client has its input left unread until its reply is sent. Clients blocked on keys,
+* as BLPOP does, are served right after the command that fed their keys and the loop only wakes
+* up for their deadlines. XREAD BLOCK and WAIT have a pending reply instead, and while any client
+* has one the loop wakes up every tick to check on it.
+*
+* Published messages and commands propagated to replicas go into outboxes, the loop moves them
+* onto the receiving connections after each round, like any other reply.
+*
+* Another thread stops the loop through a Stopper, which wakes it up via an eventfd.
+*/
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{Shutdown, TcpListener};
+use std::os::fd::AsRawFd;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::handler::{serve_blocked_clients, ServerContext, Session};
+use crate::resp::RespValue;
+
+pub mod poller;
+
+use poller::{Event, Poller, Waker};
+
+/// Token of the listening socket, client ids start at 1
+const LISTENER: u64 = 0;
+
+/// Token of the stop waker, never handed out as a client id
+const WAKER: u64 = u64::MAX;
+
+/// How often blocked clients are checked while any are waiting
+const BLOCKED_TICK: Duration = Duration::from_millis(10);
+
+struct Client {
+    session: Session,
+    /// Whether the socket is registered for writes, only while output is left over
+    wants_write: bool,
+}
+
+pub struct EventLoop {
+    poller: Poller,
+    listener: TcpListener,
+    ctx: ServerContext,
+    clients: HashMap<u64, Client>,
+    waker: Arc<Waker>,
+    stopping: Arc<AtomicBool>,
+}
+
+/// Stops a running event loop from another thread
+#[derive(Debug, Clone)]
+pub struct Stopper {
+    waker: Arc<Waker>,
+    stopping: Arc<AtomicBool>,
+}
+
+impl Stopper {
+    /// Asks the loop to close every connection and return from `run`
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        if let Err(e) = self.waker.wake() {
+            eprintln!("failed to wake event loop: {e}");
+        }
+    }
+}
+
+impl EventLoop {
+    pub fn new(listener: TcpListener, ctx: ServerContext) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        let poller = Poller::new()?;
+        poller.add(listener.as_raw_fd(), LISTENER, false)?;
+        let waker = Waker::new()?;
+        poller.add(waker.fd(), WAKER, false)?;
+        Ok(EventLoop {
+            poller,
+            listener,
+            ctx,
+            clients: HashMap::new(),
+            waker: Arc::new(waker),
+            stopping: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn stopper(&self) -> Stopper {
+        Stopper {
+            waker: Arc::clone(&self.waker),
+            stopping: Arc::clone(&self.stopping),
+        }
+    }
+
+    /// Wakes the loop up from another thread, e.g. after queueing something for it to send
+    pub fn waker(&self) -> Arc<Waker> {
+        Arc::clone(&self.waker)
+    }
+
+    /// Hands an already connected session to the loop, e.g. the link to our master
+    pub fn register(&mut self, session: Session) -> io::Result<()> {
+        let token = session.conn.id;
+        self.poller
+            .add(session.conn.stream.as_raw_fd(), token, false)?;
+        self.clients.insert(
+            token,
+            Client {
+                session,
+                wants_write: false,
+            },
+        );
+        // the master may have sent commands along with the handshake replies
+        self.drive(token, false);
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut events: Vec<Event> = Vec::new();
+        loop {
+            let any_polling = self.clients.values().any(|c| c.session.is_polling());
+            let next_deadline = self.ctx.new_db.lock().unwrap().blocked.next_deadline();
+            let until_deadline = next_deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let timeout = match (any_polling.then_some(BLOCKED_TICK), until_deadline) {
+                (Some(tick), Some(until)) => Some(tick.min(until)),
+                (tick, until) => tick.or(until),
+            };
+            self.poller.wait(&mut events, timeout)?;
+
+            if self.stopping.load(Ordering::SeqCst) {
+                self.close_all();
+                return Ok(());
+            }
+
+            for event in &events {
+                if event.token == LISTENER {
+                    self.accept();
+                } else if event.token == WAKER {
+                    self.waker.reset();
+                } else {
+                    self.drive(event.token, event.readable || event.closed);
+                }
+            }
+
+            let polling: Vec<u64> = self
+                .clients
+                .iter()
+                .filter(|(_, c)| c.session.is_polling())
+                .map(|(token, _)| *token)
+                .collect();
+            for token in polling {
+                self.drive(token, false);
+            }
+
+            self.ctx
+                .new_db
+                .lock()
+                .unwrap()
+                .blocked
+                .expire(Instant::now());
+            // what one client is handed may run commands that hand something to another
+            loop {
+                let unblocked = self.deliver_unblocked();
+                let queued = self.deliver_queued();
+                if !unblocked && !queued {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Hands replies to clients that were blocked on keys, which may then run what they sent since
+    /// Returns whether there were any
+    fn deliver_unblocked(&mut self) -> bool {
+        let unblocked = self.ctx.new_db.lock().unwrap().blocked.take_unblocked();
+        if unblocked.is_empty() {
+            return false;
+        }
+        for (token, reply) in unblocked {
+            if let Some(client) = self.clients.get_mut(&token) {
+                client.session.unblock(reply);
+                self.drive(token, false);
+            }
+        }
+        true
+    }
+
+    /// Queues published messages and the replication stream on their connections and flushes them
+    /// Returns whether there were any
+    fn deliver_queued(&mut self) -> bool {
+        let mut messages = std::mem::take(&mut self.ctx.channels_db.lock().unwrap().outbox);
+        messages.append(&mut self.ctx.broadcast_info.lock().unwrap().outbox);
+        if messages.is_empty() {
+            return false;
+        }
+        let mut tokens = Vec::new();
+        for (token, message) in messages {
+            if let Some(client) = self.clients.get_mut(&token) {
+                client.session.conn.write_value(&message);
+                tokens.push(token);
+            }
+        }
+        tokens.dedup();
+        for token in tokens {
+            self.drive(token, false);
+        }
+        true
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    eprintln!("\n\naccepted new connection\n\n");
+                    let session = Session::new(stream, &self.ctx);
+                    if let Err(e) = self.register(session) {
+                        eprintln!("failed to register connection: {e}");
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    // e.g. out of file descriptors, the backlog keeps them until we can accept
+                    eprintln!("error: {e}");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Reads from the client when it is readable, runs what it sent and flushes the replies
+    fn drive(&mut self, token: u64, readable: bool) {
+        let Some(client) = self.clients.get_mut(&token) else {
+            return;
+        };
+        let session = &mut client.session;
+
+        let mut open = true;
+        if readable {
+            match session.conn.read_available() {
+                Ok(alive) => open = alive,
+                Err(e) => {
+                    eprintln!("Connection error: {e}");
+                    self.close(token);
+                    return;
+                }
+            }
+        }
+
+        let keep = run_commands(session, &self.ctx);
+        if let Err(e) = session.conn.flush() {
+            eprintln!("Connection error: {e}");
+            self.close(token);
+            return;
+        }
+        if !open || !keep {
+            self.close(token);
+            return;
+        }
+
+        let wants_write = session.conn.has_pending_output();
+        if wants_write != client.wants_write {
+            let fd = session.conn.stream.as_raw_fd();
+            match self.poller.modify(fd, token, wants_write) {
+                Ok(()) => client.wants_write = wants_write,
+                Err(e) => {
+                    eprintln!("failed to update interest: {e}");
+                    self.close(token);
+                }
+            }
+        }
+    }
+
+    fn close(&mut self, token: u64) {
+        if let Some(client) = self.clients.remove(&token) {
+            eprintln!("closing connection {token}");
+            let _ = self.poller.delete(client.session.conn.stream.as_raw_fd());
+            // a client that hung up while blocked must not have elements popped for it
+            if client.session.is_blocked() {
+                let mut lk = self.ctx.new_db.lock().unwrap();
+                lk.blocked.forget_client(token);
+            }
+            if client.session.conn.num_channels > 0 {
+                self.ctx.channels_db.lock().unwrap().unsubscribe_all(token);
+            }
+            self.ctx
+                .broadcast_info
+                .lock()
+                .unwrap()
+                .remove_replica(token);
+        }
+    }
+
+    /// Hangs up on every client, sending what the sockets still take
+    fn close_all(&mut self) {
+        for (_, mut client) in self.clients.drain() {
+            let _ = client.session.conn.flush();
+            let _ = client.session.conn.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Runs every complete command in the read buffer until the client blocks
+/// Returns false when the connection should be closed
+fn run_commands(session: &mut Session, ctx: &ServerContext) -> bool {
+    session.poll_pending(Instant::now());
+    while !session.is_blocked() {
+        match session.conn.next_command() {
+            Ok(Some(command)) => {
+                // a command panicking outside the locks only drops its own client
+                let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let res = session.handle_command(command, ctx);
+                    // clients blocked on keys the command fed are served before anything else runs
+                    serve_blocked_clients(ctx);
+                    res
+                }));
+                match res {
+                    Ok(Ok(true)) => {}
+                    Ok(Ok(false)) => return false,
+                    Ok(Err(e)) => {
+                        eprintln!("Error handling client {}", e);
+                        return false;
+                    }
+                    Err(_) => {
+                        // shared state it left half updated must not be served to anyone
+                        if ctx.new_db.is_poisoned()
+                            || ctx.broadcast_info.is_poisoned()
+                            || ctx.channels_db.is_poisoned()
+                        {
+                            eprintln!("command handler panicked holding shared state, aborting");
+                            process::abort();
+                        }
+                        eprintln!("command handler panicked, closing client");
+                        return false;
+                    }
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Connection error: {}", e);
+                // tell the client what was wrong with its request before hanging up
+                if e.kind() == ErrorKind::InvalidData {
+                    session
+                        .conn
+                        .write_value(&RespValue::Error(format!("ERR {e}")));
+                }
+                return false;
+            }
+        }
+    }
+    true
+}
@@ -0,0 +1,144 @@
+use std::io;
+use std::os::fd::RawFd;
+use std::time::Duration;
+
+/// Thin wrapper over a level triggered epoll instance
+/// Every registered fd carries a token that comes back with its events
+pub struct Poller {
+    epfd: RawFd,
+    events: Vec<libc::epoll_event>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub token: u64,
+    pub readable: bool,
+    pub writable: bool,
+    /// The peer hung up or the socket errored, a read will tell which
+    pub closed: bool,
+}
+
+impl Poller {
+    pub fn new() -> io::Result<Self> {
+        let epfd = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        Ok(Poller {
+            epfd,
+            events: vec![libc::epoll_event { events: 0, u64: 0 }; 1024],
+        })
+    }
+
+    /// Starts watching fd for reads, and for writes too when `writable` is set
+    pub fn add(&self, fd: RawFd, token: u64, writable: bool) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_ADD, fd, token, writable)
+    }
+
+    pub fn modify(&self, fd: RawFd, token: u64, writable: bool) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_MOD, fd, token, writable)
+    }
+
+    pub fn delete(&self, fd: RawFd) -> io::Result<()> {
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        cvt(unsafe { libc::epoll_ctl(self.epfd, libc::EPOLL_CTL_DEL, fd, &mut event) })?;
+        Ok(())
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd, token: u64, writable: bool) -> io::Result<()> {
+        let mut interest = libc::EPOLLIN | libc::EPOLLRDHUP;
+        if writable {
+            interest |= libc::EPOLLOUT;
+        }
+        let mut event = libc::epoll_event {
+            events: interest as u32,
+            u64: token,
+        };
+        cvt(unsafe { libc::epoll_ctl(self.epfd, op, fd, &mut event) })?;
+        Ok(())
+    }
+
+    /// Waits for readiness, forever when there is no timeout
+    /// A signal interrupting the wait gives no events rather than an error
+    pub fn wait(&mut self, ready: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<()> {
+        ready.clear();
+        let timeout_ms = timeout.map_or(-1, |t| {
+            // round up so a sub millisecond deadline does not spin
+            t.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32
+        });
+        let n = unsafe {
+            libc::epoll_wait(
+                self.epfd,
+                self.events.as_mut_ptr(),
+                self.events.len() as i32,
+                timeout_ms,
+            )
+        };
+        let n = match cvt(n) {
+            Ok(n) => n as usize,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        ready.extend(self.events[..n].iter().map(|e| {
+            let flags = e.events as i32;
+            Event {
+                token: e.u64,
+                readable: flags & libc::EPOLLIN != 0,
+                writable: flags & libc::EPOLLOUT != 0,
+                closed: flags & (libc::EPOLLHUP | libc::EPOLLRDHUP | libc::EPOLLERR) != 0,
+            }
+        }));
+        Ok(())
+    }
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.epfd);
+        }
+    }
+}
+
+/// Wakes a thread blocked in `Poller::wait` from anywhere, backed by an eventfd
+#[derive(Debug)]
+pub struct Waker {
+    fd: RawFd,
+}
+
+impl Waker {
+    pub fn new() -> io::Result<Self> {
+        let fd = cvt(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) })?;
+        Ok(Waker { fd })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    pub fn wake(&self) -> io::Result<()> {
+        cvt(unsafe { libc::eventfd_write(self.fd, 1) })?;
+        Ok(())
+    }
+
+    /// Clears pending wakeups so a level triggered poller stops reporting it
+    pub fn reset(&self) {
+        let mut value: libc::eventfd_t = 0;
+        unsafe {
+            libc::eventfd_read(self.fd, &mut value);
+        }
+    }
+}
+
+impl Drop for Waker {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+fn cvt(res: libc::c_int) -> io::Result<libc::c_int> {
+    if res == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
@@ -0,0 +1,168 @@
+/*
+* Commands added by library users
+*
+* A type implementing Command is registered on the server builder and looked up next to the
+* built in table. The session does the rest the same way it does for built ins: arity is
+* checked first, MULTI queues it, the keyspace is locked around execute and the command is
+* propagated to replicas when it changed the keyspace, as counted by the database's dirty counter.
+*/
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::handler::command_handlers::wrong_args_error;
+use crate::handler::command_table::{unknown_command_error, CommandSpec, Flag, COMMAND_TABLE};
+use crate::redis_database::RedisDatabase;
+use crate::resp::{Protocol, RespValue};
+
+/// What a command sends back, encoded in whichever protocol the client speaks
+pub type Reply = RespValue;
+
+pub trait Command: Send + Sync {
+    /// Name clients call it by, matched ignoring case
+    fn name(&self) -> &'static str;
+
+    /// Arity counting the name, negative for a minimum, see the command table
+    fn arity(&self) -> i32;
+
+    fn flags(&self) -> &'static [Flag] {
+        &[]
+    }
+
+    /// first_key, last_key and step of the key arguments, no keys by default
+    fn key_positions(&self) -> (i32, i32, i32) {
+        (0, 0, 0)
+    }
+
+    fn acl_categories(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Runs the command, args are everything after the name
+    fn execute(&self, ctx: &mut CommandContext, args: &[Vec<u8>]) -> Reply;
+}
+
+/// What a custom command gets to work with while it runs
+pub struct CommandContext<'a> {
+    /// The keyspace, locked for the whole command
+    /// Changes made in place through get_mut and friends have to bump `db.dirty` to be propagated
+    pub db: &'a mut RedisDatabase,
+    /// Protocol of the calling client
+    pub protocol: Protocol,
+}
+
+/// Why a command could not be registered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterError {
+    /// Empty or containing whitespace
+    InvalidName(String),
+    /// A built in or another registered command goes by the same name, ignoring case
+    NameTaken(String),
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegisterError::InvalidName(name) => write!(f, "Invalid command name: '{name}'"),
+            RegisterError::NameTaken(name) => write!(f, "Command already registered: '{name}'"),
+        }
+    }
+}
+
+impl Error for RegisterError {}
+
+/// Built in commands plus whatever was registered on the server builder
+#[derive(Clone)]
+pub struct CommandRegistry {
+    specs: Vec<CommandSpec>,
+    commands: HashMap<&'static str, Arc<dyn Command>>,
+    // every spec, built ins included, by lowercase name
+    by_name: HashMap<String, CommandSpec>,
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        CommandRegistry {
+            specs: Vec::new(),
+            commands: HashMap::new(),
+            by_name: COMMAND_TABLE
+                .iter()
+                .map(|spec| (spec.name.to_string(), spec.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Debug for CommandRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.specs.iter().map(|s| s.name))
+            .finish()
+    }
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a command, clients call it by its name in any case
+    /// Fails when the name is empty, has whitespace or is taken by a built in or another command
+    pub fn register(&mut self, command: impl Command + 'static) -> Result<(), RegisterError> {
+        let name = command.name();
+        if name.is_empty() || name.chars().any(char::is_whitespace) {
+            return Err(RegisterError::InvalidName(name.to_string()));
+        }
+        if self.lookup(name.as_bytes()).is_some() {
+            return Err(RegisterError::NameTaken(name.to_string()));
+        }
+        let spec = CommandSpec::new(
+            name,
+            command.arity(),
+            command.flags(),
+            command.key_positions(),
+            command.acl_categories(),
+        );
+        self.by_name.insert(name.to_ascii_lowercase(), spec.clone());
+        self.specs.push(spec);
+        self.commands.insert(name, Arc::new(command));
+        Ok(())
+    }
+
+    /// Every command, built ins first
+    pub fn specs(&self) -> impl Iterator<Item = &CommandSpec> {
+        COMMAND_TABLE.iter().chain(&self.specs)
+    }
+
+    pub fn len(&self) -> usize {
+        COMMAND_TABLE.len() + self.specs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Finds a command by name, ignoring case
+    pub fn lookup(&self, name: &[u8]) -> Option<&CommandSpec> {
+        let name = std::str::from_utf8(name).ok()?;
+        self.by_name.get(&name.to_ascii_lowercase())
+    }
+
+    /// The command a request runs, or the error reply when it is unknown or has the wrong arity
+    pub fn lookup_command(&self, args: &[Vec<u8>]) -> Result<&CommandSpec, RespValue> {
+        let Some(spec) = args.first().and_then(|name| self.lookup(name)) else {
+            return Err(unknown_command_error(args));
+        };
+        if !spec.arity_matches(args.len()) {
+            return Err(wrong_args_error(spec.name));
+        }
+        Ok(spec)
+    }
+
+    /// The implementation of a registered command by the name it was registered with
+    /// Built ins have none
+    pub fn custom(&self, name: &str) -> Option<&Arc<dyn Command>> {
+        self.commands.get(name)
+    }
+}
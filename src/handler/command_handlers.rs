@@ -1,59 +1,865 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
-
-use crate::redis_database::{Expiration, RdbError, RedisDatabase, RedisValue};
-
-pub fn handle_set(
-    k: String,
-    v: String,
-    new_db: &Arc<Mutex<RedisDatabase>>,
-    expiry_info: Option<(&str, &str)>,
-) -> Result<(), Box<RdbError>> {
-    eprintln!("HANDLING SET FOR K:{k}, V:{v}");
-    let mut use_insert = RedisValue {
-        value: v,
-        expires_at: None,
-    };
-    if let Some((expiry_type, expiry_time)) = expiry_info {
-        match expiry_type {
-            "px" => {
-                let time_arg: u64 = expiry_time.parse().expect("failed to parse expiry time");
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64; ////eprintln!("got MILLISECONDS expiry:{time_arg}");
-                let end_time_s = now + time_arg;
-                ////eprintln!("AT: {now}, MSexpiry:{time_arg},end:{end_time_s}");
-                let use_expiry = Some(Expiration::Milliseconds(end_time_s));
-                use_insert.expires_at = use_expiry;
-            }
-            "ex" => {
-                let time_arg: u32 = expiry_time.parse().expect("failed to parse expiry time");
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-                let end_time_s = now as u32 + time_arg;
-
-                ////eprintln!("AT: {now}, got SECONDS expiry:{time_arg}, expected end:{end_time_s}");
-                let use_expiry = Some(Expiration::Seconds(end_time_s as u32));
-                use_insert.expires_at = use_expiry;
+use std::time::{Duration, SystemTime};
+
+use crate::constants::*;
+use crate::entry_stream::entry_utils::get_xread_resp_array;
+use crate::redis_connection::broadcast_info::BroadCastInfo;
+use crate::redis_connection::RedisConnection;
+use crate::redis_database::{Expiration, RedisData, RedisDatabase, RedisValue, WrongType};
+use crate::resp::{Protocol, RespValue};
+use crate::utils::{
+    arg_str, format_redis_float, parse_redis_float, parse_redis_int, printable_arg, unix_time_ms,
+};
+
+/// Sends a write command on to every connected replica when running as master
+pub fn propagate(
+    info_fields: &HashMap<String, String>,
+    broadcast_info: &Arc<Mutex<BroadCastInfo>>,
+    command: &[Vec<u8>],
+) {
+    if info_fields.get(ROLE).is_some_and(|k| k == MASTER) {
+        let mut lk = broadcast_info.lock().unwrap();
+        lk.broadcast_command(command);
+    }
+}
+
+pub fn wrong_args_error(cmd: &str) -> RespValue {
+    RespValue::Error(format!("ERR wrong number of arguments for '{cmd}' command"))
+}
+
+/// HELLO [protover [AUTH username password] [SETNAME clientname]]
+/// Switches the connection's protocol and replies with the server info map in the new one
+pub fn handle_hello(conn: &mut RedisConnection, args: &[Vec<u8>], role: &str) -> RespValue {
+    let mut protocol = conn.protocol;
+    let mut name = None;
+    if let Some(version) = args.first() {
+        protocol = match parse_redis_int(version) {
+            Some(2) => Protocol::Resp2,
+            Some(3) => Protocol::Resp3,
+            Some(_) => return RespValue::error(NOPROTO_ERROR),
+            None => {
+                return RespValue::error("ERR Protocol version is not an integer or out of range")
+            }
+        };
+
+        let mut options = args[1..].iter();
+        while let Some(option) = options.next() {
+            match arg_str(option).to_lowercase().as_str() {
+                "auth" => {
+                    let (Some(user), Some(_pass)) = (options.next(), options.next()) else {
+                        return RespValue::error(SYNTAX_ERROR);
+                    };
+                    // no passwords are configured, so only the default user exists
+                    if user.as_slice() != b"default" {
+                        return RespValue::error(WRONGPASS_ERROR);
+                    }
+                }
+                "setname" => {
+                    let Some(new_name) = options.next() else {
+                        return RespValue::error(SYNTAX_ERROR);
+                    };
+                    if new_name.iter().any(|c| !c.is_ascii_graphic()) {
+                        return RespValue::error(CLIENT_NAME_ERROR);
+                    }
+                    name = Some(new_name.clone());
+                }
+                _ => return RespValue::error(SYNTAX_ERROR),
+            }
+        }
+    }
+
+    // nothing changes unless every option was valid
+    conn.protocol = protocol;
+    if name.is_some() {
+        conn.name = name;
+    }
+    let role = if role == MASTER { "master" } else { "replica" };
+    RespValue::Map(vec![
+        (RespValue::bulk("server"), RespValue::bulk("redis")),
+        (RespValue::bulk("version"), RespValue::bulk(REDIS_VERSION)),
+        (
+            RespValue::bulk("proto"),
+            RespValue::Integer(if protocol == Protocol::Resp3 { 3 } else { 2 }),
+        ),
+        (RespValue::bulk("id"), RespValue::Integer(conn.id as i64)),
+        (RespValue::bulk("mode"), RespValue::bulk("standalone")),
+        (RespValue::bulk("role"), RespValue::bulk(role)),
+        (RespValue::bulk("modules"), RespValue::empty_array()),
+    ])
+}
+
+/// CLIENT ID | GETNAME | SETNAME name | UNBLOCK id [TIMEOUT|ERROR]
+pub fn handle_client(
+    conn: &mut RedisConnection,
+    db: &Mutex<RedisDatabase>,
+    args: &[Vec<u8>],
+) -> RespValue {
+    let subcommand = arg_str(&args[0]).to_lowercase();
+    match (subcommand.as_str(), &args[1..]) {
+        ("id", []) => RespValue::Integer(conn.id as i64),
+        ("getname", []) => conn.name.as_ref().map_or(RespValue::Null, RespValue::bulk),
+        ("setname", [name]) => {
+            if name.iter().any(|c| !c.is_ascii_graphic()) {
+                return RespValue::error(CLIENT_NAME_ERROR);
+            }
+            // an empty name clears it
+            conn.name = (!name.is_empty()).then(|| name.clone());
+            RespValue::ok()
+        }
+        ("unblock", [id, reason @ ..]) if reason.len() <= 1 => {
+            let Some(id) = parse_redis_int(id) else {
+                return RespValue::error(NOT_INT_ERROR);
+            };
+            let reply = match reason.first() {
+                None => None,
+                Some(r) if r.eq_ignore_ascii_case(b"timeout") => None,
+                Some(r) if r.eq_ignore_ascii_case(b"error") => Some(RespValue::error(
+                    "UNBLOCKED client unblocked via CLIENT UNBLOCK",
+                )),
+                Some(_) => {
+                    return RespValue::error("ERR CLIENT UNBLOCK reason should be TIMEOUT or ERROR")
+                }
+            };
+            let unblocked = db.lock().unwrap().blocked.unblock(id as u64, reply);
+            RespValue::Integer(unblocked as i64)
+        }
+        ("id" | "getname" | "setname" | "unblock", _) => {
+            wrong_args_error(&format!("client|{subcommand}"))
+        }
+        _ => RespValue::Error(format!(
+            "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+            printable_arg(&args[0])
+        )),
+    }
+}
+
+/// Expiry argument accepted by SET and GETEX
+#[derive(Debug, Clone)]
+pub enum ExpiryOption {
+    At(Expiration),
+    KeepTtl,
+    Persist,
+}
+
+#[derive(Debug, Default)]
+pub struct SetOptions {
+    pub nx: bool,
+    pub xx: bool,
+    pub get: bool,
+    pub expiry: Option<ExpiryOption>,
+}
+
+/// Turns an EX/PX/EXAT/PXAT argument into an absolute expiry
+/// Errors are returned as ready to send error replies
+pub fn parse_expiry(unit: &str, time: &[u8], cmd: &str) -> Result<Expiration, RespValue> {
+    let Some(time) = parse_redis_int(time) else {
+        return Err(RespValue::error(NOT_INT_ERROR));
+    };
+    let invalid = RespValue::Error(format!("ERR invalid expire time in '{cmd}' command"));
+    if time <= 0 {
+        return Err(invalid);
+    }
+
+    let now = unix_time_ms() as i64;
+    let when_ms = match unit.to_lowercase().as_str() {
+        "ex" => time.checked_mul(1000).and_then(|ms| ms.checked_add(now)),
+        "px" => time.checked_add(now),
+        "exat" => time.checked_mul(1000),
+        "pxat" => Some(time),
+        _ => return Err(RespValue::error(SYNTAX_ERROR)),
+    };
+    when_ms
+        .map(|ms| Expiration::Milliseconds(ms as u64))
+        .ok_or(invalid)
+}
+
+/// Parses `[NX | XX] [GET] [EX seconds | PX milliseconds | EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL]`
+pub fn parse_set_options(cmd: &str, options: &[Vec<u8>]) -> Result<SetOptions, RespValue> {
+    let mut opts = SetOptions::default();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let lower = arg_str(option).to_lowercase();
+        match lower.as_str() {
+            "nx" if !opts.xx => opts.nx = true,
+            "xx" if !opts.nx => opts.xx = true,
+            "get" => opts.get = true,
+            "keepttl" if opts.expiry.is_none() => opts.expiry = Some(ExpiryOption::KeepTtl),
+            "ex" | "px" | "exat" | "pxat" if opts.expiry.is_none() => {
+                let Some(time) = options.next() else {
+                    return Err(RespValue::error(SYNTAX_ERROR));
+                };
+                opts.expiry = Some(ExpiryOption::At(parse_expiry(&lower, time, cmd)?));
+            }
+            _ => return Err(RespValue::error(SYNTAX_ERROR)),
+        }
+    }
+    Ok(opts)
+}
+
+/// Applies a SET with its options
+/// Returns whether the value was written and the previous string value
+pub fn apply_set(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    value: &[u8],
+    opts: &SetOptions,
+) -> Result<(bool, Option<Vec<u8>>), WrongType> {
+    // without GET a plain SET overwrites a key of any type
+    let old_value = match db.get_string(key) {
+        Ok(v) => v.cloned(),
+        Err(WrongType) if opts.get => return Err(WrongType),
+        Err(WrongType) => None,
+    };
+
+    let exists = db.contains_key(key);
+    if (opts.nx && exists) || (opts.xx && !exists) {
+        return Ok((false, old_value));
+    }
+
+    let expires_at = match &opts.expiry {
+        Some(ExpiryOption::At(e)) => Some(e.clone()),
+        Some(ExpiryOption::KeepTtl) => db.get(key).and_then(|v| v.expires_at.clone()),
+        Some(ExpiryOption::Persist) | None => None,
+    };
+    db.insert(
+        key.to_vec(),
+        RedisValue {
+            value: RedisData::String(value.to_vec()),
+            expires_at,
+        },
+    );
+    Ok((true, old_value))
+}
+
+/// Replicas are sent a SET with an expiry as SET key value PXAT, so the key expires there at
+/// the same time it does here however late the command arrives
+fn propagate_set_as_pxat(db: &mut RedisDatabase, key: &[u8], value: &[u8], opts: &SetOptions) {
+    if let Some(ExpiryOption::At(expires_at)) = &opts.expiry {
+        db.propagate_as = Some(vec![
+            b"SET".to_vec(),
+            key.to_vec(),
+            value.to_vec(),
+            b"PXAT".to_vec(),
+            expires_at.as_millis().to_string().into_bytes(),
+        ]);
+    }
+}
+
+/// Replicas are sent the EXPIRE family and GETEX as PEXPIREAT with the absolute time
+fn propagate_as_pexpireat(db: &mut RedisDatabase, key: &[u8], when_ms: u64) {
+    db.propagate_as = Some(vec![
+        b"PEXPIREAT".to_vec(),
+        key.to_vec(),
+        when_ms.to_string().into_bytes(),
+    ]);
+}
+
+/// SET key value [NX | XX] [GET] [EX | PX | EXAT | PXAT time | KEEPTTL]
+pub fn handle_set(db: &mut RedisDatabase, args: &[Vec<u8>]) -> RespValue {
+    let opts = match parse_set_options("set", &args[3..]) {
+        Ok(opts) => opts,
+        Err(e) => return e,
+    };
+
+    let res = apply_set(db, &args[1], &args[2], &opts);
+    if let Ok((true, _)) = res {
+        propagate_set_as_pxat(db, &args[1], &args[2], &opts);
+    }
+    match res {
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+        Ok((_, old_value)) if opts.get => match old_value {
+            Some(v) => RespValue::bulk(&v),
+            None => RespValue::Null,
+        },
+        Ok((true, _)) => RespValue::ok(),
+        Ok((false, _)) => RespValue::Null,
+    }
+}
+
+/// SETNX key value, SETEX key seconds value and PSETEX key milliseconds value
+pub fn handle_set_variant(db: &mut RedisDatabase, args: &[Vec<u8>]) -> RespValue {
+    let cmd = arg_str(&args[0]).to_lowercase();
+    let arity = if cmd == "setnx" { 3 } else { 4 };
+    if args.len() != arity {
+        return wrong_args_error(&cmd);
+    }
+    let (value, opts) = match cmd.as_str() {
+        "setnx" => (
+            &args[2],
+            SetOptions {
+                nx: true,
+                ..Default::default()
+            },
+        ),
+        _ => {
+            let unit = if cmd == "psetex" { "px" } else { "ex" };
+            let expiry = match parse_expiry(unit, &args[2], &cmd) {
+                Ok(e) => e,
+                Err(e) => return e,
+            };
+            (
+                &args[3],
+                SetOptions {
+                    expiry: Some(ExpiryOption::At(expiry)),
+                    ..Default::default()
+                },
+            )
+        }
+    };
+
+    let res = apply_set(db, &args[1], value, &opts);
+    if let Ok((true, _)) = res {
+        propagate_set_as_pxat(db, &args[1], value, &opts);
+    }
+    match res {
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+        Ok((written, _)) if cmd == "setnx" => RespValue::Integer(written as i64),
+        Ok(_) => RespValue::ok(),
+    }
+}
+
+/// GETSET key value, sets the value dropping any expiry and returns the old one
+pub fn handle_getset(db: &mut RedisDatabase, key: &[u8], value: &[u8]) -> RespValue {
+    let opts = SetOptions {
+        get: true,
+        ..Default::default()
+    };
+    match apply_set(db, key, value, &opts) {
+        Ok((_, Some(old_value))) => RespValue::bulk(&old_value),
+        Ok((_, None)) => RespValue::Null,
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
+
+/// GETDEL key
+pub fn handle_getdel(db: &mut RedisDatabase, key: &[u8]) -> RespValue {
+    match db.get_string(key) {
+        Ok(Some(value)) => {
+            let resp = RespValue::bulk(value);
+            db.remove(key);
+            resp
+        }
+        Ok(None) => RespValue::Null,
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
+
+/// GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds | PXAT unix-time-milliseconds | PERSIST]
+pub fn handle_getex(db: &mut RedisDatabase, args: &[Vec<u8>]) -> RespValue {
+    if args.len() < 2 {
+        return wrong_args_error("getex");
+    }
+    let key = &args[1];
+    let expiry = match args[2..] {
+        [] => None,
+        [ref option] if option.eq_ignore_ascii_case(b"persist") => Some(ExpiryOption::Persist),
+        [ref unit, ref time]
+            if ["ex", "px", "exat", "pxat"].contains(&arg_str(unit).to_lowercase().as_str()) =>
+        {
+            match parse_expiry(arg_str(unit), time, "getex") {
+                Ok(e) => Some(ExpiryOption::At(e)),
+                Err(e) => return e,
+            }
+        }
+        _ => return RespValue::error(SYNTAX_ERROR),
+    };
+
+    let resp = match db.get_string(key) {
+        Ok(Some(value)) => RespValue::bulk(value),
+        Ok(None) => return RespValue::Null,
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    match expiry {
+        Some(ExpiryOption::At(e)) if e.is_expired() => {
+            db.remove(key);
+            db.propagate_as = Some(vec![b"DEL".to_vec(), key.clone()]);
+        }
+        Some(ExpiryOption::At(e)) => {
+            propagate_as_pexpireat(db, key, e.as_millis());
+            db.set_expiry(key, Some(e));
+        }
+        Some(ExpiryOption::Persist) => {
+            db.set_expiry(key, None);
+            db.propagate_as = Some(vec![b"PERSIST".to_vec(), key.clone()]);
+        }
+        Some(ExpiryOption::KeepTtl) | None => {}
+    }
+    resp
+}
+
+/// Replaces the number stored as a string under key with `update(current)`, keeping any expiry
+/// A missing key counts as zero, the new value is returned on success
+fn update_numeric_string<T: Default + ToString>(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    parse: impl Fn(&[u8]) -> Option<T>,
+    parse_error: &str,
+    update: impl FnOnce(T) -> Result<T, &'static str>,
+) -> Result<T, RespValue> {
+    let current = match db.get_string(key) {
+        Ok(Some(s)) => parse(s).ok_or_else(|| RespValue::error(parse_error))?,
+        Ok(None) => T::default(),
+        Err(WrongType) => return Err(RespValue::error(WRONGTYPE_ERROR)),
+    };
+    let new_val = update(current).map_err(RespValue::error)?;
+
+    let new_string = RedisData::String(new_val.to_string().into_bytes());
+    match db.get_mut(key) {
+        Some(rv) => {
+            rv.value = new_string;
+            db.dirty += 1;
+        }
+        None => db.insert(key.to_vec(), RedisValue::new(new_string)),
+    }
+    Ok(new_val)
+}
+
+/// INCR, DECR, INCRBY and DECRBY
+pub fn handle_incr_by(db: &mut RedisDatabase, key: &[u8], delta: i64) -> RespValue {
+    let res = update_numeric_string(db, key, parse_redis_int, NOT_INT_ERROR, |val| {
+        val.checked_add(delta).ok_or(OVERFLOW_ERROR)
+    });
+    match res {
+        Ok(new_val) => RespValue::Integer(new_val),
+        Err(e) => e,
+    }
+}
+
+/// INCRBYFLOAT key increment
+pub fn handle_incr_by_float(db: &mut RedisDatabase, key: &[u8], increment: &[u8]) -> RespValue {
+    let Some(increment) = parse_redis_float(increment) else {
+        return RespValue::error(NOT_FLOAT_ERROR);
+    };
+    let res = update_numeric_string(db, key, parse_redis_float, NOT_FLOAT_ERROR, |val| {
+        // adding 0.0 turns -0 into 0 so we never store "-0"
+        let new_val = val + increment + 0.0;
+        if new_val.is_finite() {
+            Ok(new_val)
+        } else {
+            Err(NAN_OR_INF_ERROR)
+        }
+    });
+    match res {
+        Ok(new_val) => RespValue::bulk(format_redis_float(new_val)),
+        Err(e) => e,
+    }
+}
+
+/// APPEND key value, creates the key if missing and returns the new length
+pub fn handle_append(db: &mut RedisDatabase, key: &[u8], value: &[u8]) -> RespValue {
+    let new_len = match db.get_mut(key) {
+        Some(RedisValue {
+            value: RedisData::String(s),
+            ..
+        }) => {
+            s.extend_from_slice(value);
+            let len = s.len();
+            db.dirty += 1;
+            len
+        }
+        Some(_) => return RespValue::error(WRONGTYPE_ERROR),
+        None => {
+            db.insert(
+                key.to_vec(),
+                RedisValue::new(RedisData::String(value.to_vec())),
+            );
+            value.len()
+        }
+    };
+    RespValue::Integer(new_len as i64)
+}
+
+pub fn handle_strlen(db: &mut RedisDatabase, key: &[u8]) -> RespValue {
+    match db.get_string(key) {
+        Ok(Some(s)) => RespValue::Integer(s.len() as i64),
+        Ok(None) => RespValue::Integer(0),
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
+
+/// GETRANGE key start end, both ends inclusive and negative indexes count from the end
+pub fn handle_getrange(db: &mut RedisDatabase, key: &[u8], start: &[u8], end: &[u8]) -> RespValue {
+    let (Some(start), Some(end)) = (parse_redis_int(start), parse_redis_int(end)) else {
+        return RespValue::error(NOT_INT_ERROR);
+    };
+    let value = match db.get_string(key) {
+        Ok(Some(s)) => s,
+        Ok(None) => return RespValue::bulk(""),
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+
+    let len = value.len() as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 {
+        (len + end).max(0)
+    } else {
+        end.min(len - 1)
+    };
+    if len == 0 || start > end {
+        return RespValue::bulk("");
+    }
+    RespValue::bulk(&value[start as usize..=end as usize])
+}
+
+/// SETRANGE key offset value, zero pads the string if offset is past its end
+pub fn handle_setrange(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    offset: &[u8],
+    value: &[u8],
+) -> RespValue {
+    let Some(offset) = parse_redis_int(offset).filter(|o| *o >= 0) else {
+        return RespValue::error(OFFSET_OUT_OF_RANGE_ERROR);
+    };
+    let offset = offset as usize;
+    // writing nothing changes nothing, whatever the offset, it only reports the length
+    if value.is_empty() {
+        return match db.get_string(key) {
+            Ok(s) => RespValue::Integer(s.map_or(0, |s| s.len() as i64)),
+            Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+        };
+    }
+    if offset + value.len() > MAX_STRING_LEN {
+        return RespValue::error(STRING_TOO_LONG_ERROR);
+    }
+
+    let mut bytes = match db.get_string(key) {
+        Ok(Some(s)) => s.clone(),
+        Ok(None) => Vec::new(),
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    let end = offset + value.len();
+    if bytes.len() < end {
+        bytes.resize(end, 0);
+    }
+    bytes[offset..end].copy_from_slice(value);
+
+    let new_len = bytes.len();
+    let new_string = RedisData::String(bytes);
+    match db.get_mut(key) {
+        Some(rv) => {
+            rv.value = new_string;
+            db.dirty += 1;
+        }
+        None => db.insert(key.to_vec(), RedisValue::new(new_string)),
+    }
+    RespValue::Integer(new_len as i64)
+}
+
+/// MGET key [key ...], keys that are missing or not strings are returned as nulls
+pub fn handle_mget(db: &mut RedisDatabase, keys: &[Vec<u8>]) -> RespValue {
+    let values = keys.iter().map(|key| match db.get_string(key) {
+        Ok(Some(value)) => RespValue::bulk(value),
+        _ => RespValue::Null,
+    });
+    RespValue::Array(values.collect())
+}
+
+/// MSET/MSETNX key value [key value ...]
+/// With `only_if_none` nothing is written if any of the keys already exists
+pub fn handle_mset(db: &mut RedisDatabase, pairs: &[Vec<u8>], only_if_none: bool) -> RespValue {
+    if only_if_none && pairs.chunks(2).any(|pair| db.contains_key(&pair[0])) {
+        return RespValue::Integer(0);
+    }
+    pairs.chunks(2).for_each(|pair| {
+        db.insert(
+            pair[0].clone(),
+            RedisValue::new(RedisData::String(pair[1].clone())),
+        )
+    });
+
+    if only_if_none {
+        RespValue::Integer(1)
+    } else {
+        RespValue::ok()
+    }
+}
+
+/// LPUSH/RPUSH, clients blocked on the list are served once the command is done
+pub fn handle_push(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    values: &[Vec<u8>],
+    left: bool,
+) -> RespValue {
+    let use_list = match db.get_or_create_list(key) {
+        Ok(l) => l,
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    values.iter().for_each(|e| use_list.push(e.clone(), left));
+    let len = use_list.len();
+    db.dirty += values.len() as u64;
+
+    RespValue::Integer(len as i64)
+}
+
+/// XADD, clients blocked in XREAD on the stream are served once the command is done
+pub fn handle_xadd(
+    db: &mut RedisDatabase,
+    stream_name: &[u8],
+    stream_id: &str,
+    fields: &[Vec<u8>],
+) -> RespValue {
+    let curr_stream = match db.get_or_create_stream(stream_name) {
+        Ok(s) => s,
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+
+    // map key values to each other in a tuple
+    let use_vec = fields
+        .chunks_exact(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+
+    let (added, res) = curr_stream.handle_add(stream_id, use_vec);
+    if added {
+        db.blocked.signal(stream_name);
+        db.dirty += 1;
+    }
+    res
+}
+
+/// Reads each stream from its start id, Null when none has new entries
+/// With a block window only entries added during the block count
+pub fn handle_xread(
+    db: &mut RedisDatabase,
+    streams: &[(Vec<u8>, String)],
+    block: Option<(SystemTime, Duration)>,
+) -> RespValue {
+    let mut final_res = Vec::new();
+    for (stream_name, start) in streams {
+        eprintln!("running xread for stream_name{:?}", &stream_name);
+        let curr_stream = match db.get_stream(stream_name) {
+            Ok(Some(s)) => s,
+            Ok(None) => continue,
+            Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+        };
+        let res = match block {
+            Some((block_start_time, time_to_block_for)) => {
+                curr_stream.block_xread(stream_name, block_start_time, time_to_block_for, start)
             }
+            None => curr_stream.xread_range(stream_name, start),
+        };
+        if let Some(r) = res {
+            final_res.push(r)
+        }
+    }
+
+    let full_stream_bytes = get_xread_resp_array(&final_res);
+    eprintln!("FINAL xread res:{:?}", full_stream_bytes);
+    full_stream_bytes
+}
+
+/// RENAME/RENAMENX, clients blocked on the destination are served if a list moved there
+pub fn handle_rename(
+    db: &mut RedisDatabase,
+    from: &[u8],
+    to: &[u8],
+    only_if_new: bool,
+) -> RespValue {
+    if !db.contains_key(from) {
+        return RespValue::error(NO_SUCH_KEY_ERROR);
+    }
+    if only_if_new && db.contains_key(to) {
+        return RespValue::Integer(0);
+    }
+    db.rename(from, to);
+
+    if only_if_new {
+        RespValue::Integer(1)
+    } else {
+        RespValue::ok()
+    }
+}
+
+/// COPY source destination [DB destination-db] [REPLACE]
+pub fn handle_copy(db: &mut RedisDatabase, args: &[Vec<u8>]) -> RespValue {
+    let (from, to) = (&args[0], &args[1]);
+    let mut replace = false;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        match arg_str(option).to_lowercase().as_str() {
+            "replace" => replace = true,
+            // only the default database exists
+            "db" => match options.next().map(|n| parse_redis_int(n)) {
+                Some(Some(0)) => {}
+                Some(Some(_)) => return RespValue::error("ERR DB index is out of range"),
+                Some(None) => return RespValue::error(NOT_INT_ERROR),
+                None => return RespValue::error(SYNTAX_ERROR),
+            },
+            _ => return RespValue::error(SYNTAX_ERROR),
+        }
+    }
+
+    if from == to {
+        return RespValue::error("ERR source and destination objects are the same");
+    }
+    RespValue::Integer(db.copy(from, to, replace) as i64)
+}
+
+/// EXPIRE/PEXPIRE/EXPIREAT/PEXPIREAT key time [NX|XX|GT|LT]
+/// `unit_ms` is the length of one time unit, `absolute` is set for the *AT variants
+pub fn handle_expire(
+    db: &mut RedisDatabase,
+    args: &[Vec<u8>],
+    unit_ms: i64,
+    absolute: bool,
+) -> RespValue {
+    let cmd = arg_str(&args[0]).to_lowercase();
+    let key = &args[1];
+    let Some(time) = parse_redis_int(&args[2]) else {
+        return RespValue::error(NOT_INT_ERROR);
+    };
+
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for option in &args[3..] {
+        match arg_str(option).to_lowercase().as_str() {
+            "nx" => nx = true,
+            "xx" => xx = true,
+            "gt" => gt = true,
+            "lt" => lt = true,
             _ => {
-                return Err(Box::new(RdbError::UnsupportedFeature(
-                    "WRONG SET ARGUMENTS",
-                )))
+                let option = String::from_utf8_lossy(option);
+                return RespValue::Error(format!("ERR Unsupported option {option}"));
+            }
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return RespValue::error(
+            "ERR NX and XX, GT or LT options at the same time are not compatible",
+        );
+    }
+    if gt && lt {
+        return RespValue::error("ERR GT and LT options at the same time are not compatible");
+    }
+
+    let now = unix_time_ms() as i64;
+    let when = time.checked_mul(unit_ms).and_then(|t| {
+        if absolute {
+            Some(t)
+        } else {
+            t.checked_add(now)
+        }
+    });
+    let Some(when) = when else {
+        return RespValue::Error(format!("ERR invalid expire time in '{cmd}' command"));
+    };
+
+    let Some(value) = db.get(key) else {
+        return RespValue::Integer(0);
+    };
+    // a key without an expiry counts as never expiring for GT and LT
+    let current = value.expires_at.as_ref().map(|e| e.as_millis() as i64);
+    let allowed = match current {
+        Some(current) => {
+            let rejected = nx || (gt && when <= current) || (lt && when >= current);
+            !rejected
+        }
+        None => !xx && !gt,
+    };
+    if !allowed {
+        return RespValue::Integer(0);
+    }
+
+    // an expiry already passed deletes the key, which replicas are sent as a DEL
+    if when <= now {
+        db.remove(key);
+        db.propagate_as = Some(vec![b"DEL".to_vec(), key.clone()]);
+    } else {
+        db.set_expiry(key, Some(Expiration::Milliseconds(when as u64)));
+        propagate_as_pexpireat(db, key, when as u64);
+    }
+    RespValue::Integer(1)
+}
+
+/// TTL/PTTL/EXPIRETIME/PEXPIRETIME, -2 for a missing key and -1 for a key without an expiry
+pub fn handle_ttl(db: &mut RedisDatabase, key: &[u8], in_ms: bool, absolute: bool) -> RespValue {
+    let Some(value) = db.get(key) else {
+        return RespValue::Integer(-2);
+    };
+    let Some(expiry) = &value.expires_at else {
+        return RespValue::Integer(-1);
+    };
+
+    let when = expiry.as_millis();
+    let ms = if absolute {
+        when
+    } else {
+        when.saturating_sub(unix_time_ms())
+    };
+    let res = match (in_ms, absolute) {
+        (true, _) => ms,
+        (false, true) => ms / 1000,
+        // round to the closest second like Redis does
+        (false, false) => (ms + 500) / 1000,
+    };
+    RespValue::Integer(res as i64)
+}
+
+/// EXPIREMEMBER/PEXPIREMEMBER/EXPIREMEMBERAT/PEXPIREMEMBERAT key member time
+/// Returns 0 when the key or the member is missing, a time in the past removes the member
+pub fn handle_expire_member(
+    db: &mut RedisDatabase,
+    args: &[Vec<u8>],
+    unit_ms: i64,
+    absolute: bool,
+) -> RespValue {
+    let cmd = arg_str(&args[0]).to_lowercase();
+    let Some(time) = parse_redis_int(&args[3]) else {
+        return RespValue::error(NOT_INT_ERROR);
+    };
+    let now = unix_time_ms() as i64;
+    let when = time.checked_mul(unit_ms).and_then(|t| {
+        if absolute {
+            Some(t)
+        } else {
+            t.checked_add(now)
+        }
+    });
+    let Some(when) = when else {
+        return RespValue::Error(format!("ERR invalid expire time in '{cmd}' command"));
+    };
+
+    let expires_at = Expiration::Milliseconds(when.max(0) as u64);
+    let (key, member) = (&args[1], &args[2]);
+    match db.expire_member(key, member, expires_at.clone()) {
+        Ok(found) => {
+            // replicas get the absolute time, or a ZREM when the member is already gone
+            if found && expires_at.is_expired() {
+                db.propagate_as = Some(vec![b"ZREM".to_vec(), key.clone(), member.clone()]);
+            } else if found {
+                db.propagate_as = Some(vec![
+                    b"PEXPIREMEMBERAT".to_vec(),
+                    key.clone(),
+                    member.clone(),
+                    expires_at.as_millis().to_string().into_bytes(),
+                ]);
             }
+            RespValue::Integer(found as i64)
         }
-        ////eprintln!("before inserting in db, expiry:{:?}", use_expiry);
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
     }
+}
 
-    {
-        eprintln!("IN HANDLE SET FUNCTION, BEFORE LOCK");
-        let mut lk = new_db.lock().unwrap();
-        lk.insert(k.clone(), use_insert);
-        let res = lk.get(&k);
-        eprintln!("IN HANDLE SET FUNCTION, AFTER LOCK GET RES: {:?}", res);
+/// MEMBERTTL key member, seconds left, -2 for a missing key or member and -1 without an expiry
+pub fn handle_member_ttl(db: &mut RedisDatabase, key: &[u8], member: &[u8]) -> RespValue {
+    let set = match db.get_sorted_set(key) {
+        Ok(Some(set)) => set,
+        Ok(None) => return RespValue::Integer(-2),
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    if set.get_member(member).is_none() {
+        return RespValue::Integer(-2);
     }
-    Ok(())
+    let Some(expiry) = set.member_expiry(member) else {
+        return RespValue::Integer(-1);
+    };
+    let ms = expiry.as_millis().saturating_sub(unix_time_ms());
+    // round to the closest second like TTL
+    RespValue::Integer(((ms + 500) / 1000) as i64)
 }
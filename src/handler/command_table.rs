@@ -0,0 +1,427 @@
+/*
+* Every command the server understands, looked up before a request is dispatched and
+* reported back to clients through COMMAND, COMMAND INFO and COMMAND DOCS
+*
+* Arity counts the command name like redis does. A positive arity is the exact number of
+* arguments, a negative one the minimum, so SET key value [options] is -3.
+* Keys are found at first_key, first_key + step, ... up to last_key, where a negative
+* last_key counts back from the end. Commands with keys elsewhere are flagged movablekeys.
+*/
+use crate::handler::command::CommandRegistry;
+use crate::handler::command_handlers::wrong_args_error;
+use crate::resp::RespValue;
+use crate::utils::{arg_str, parse_redis_int, printable_arg};
+
+use self::Flag::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Write,
+    Readonly,
+    DenyOom,
+    Admin,
+    PubSub,
+    NoScript,
+    Blocking,
+    Loading,
+    Stale,
+    Fast,
+    NoAuth,
+    NoMulti,
+    MovableKeys,
+}
+
+impl Flag {
+    pub fn name(self) -> &'static str {
+        match self {
+            Write => "write",
+            Readonly => "readonly",
+            DenyOom => "denyoom",
+            Admin => "admin",
+            PubSub => "pubsub",
+            NoScript => "noscript",
+            Blocking => "blocking",
+            Loading => "loading",
+            Stale => "stale",
+            Fast => "fast",
+            NoAuth => "no_auth",
+            NoMulti => "no_multi",
+            MovableKeys => "movablekeys",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub arity: i32,
+    pub flags: &'static [Flag],
+    pub first_key: i32,
+    pub last_key: i32,
+    pub step: i32,
+    pub acl_categories: &'static [&'static str],
+}
+
+impl CommandSpec {
+    pub(crate) const fn new(
+        name: &'static str,
+        arity: i32,
+        flags: &'static [Flag],
+        (first_key, last_key, step): (i32, i32, i32),
+        acl_categories: &'static [&'static str],
+    ) -> Self {
+        CommandSpec {
+            name,
+            arity,
+            flags,
+            first_key,
+            last_key,
+            step,
+            acl_categories,
+        }
+    }
+
+    pub fn arity_matches(&self, argc: usize) -> bool {
+        let arity = self.arity.unsigned_abs() as usize;
+        if self.arity < 0 {
+            argc >= arity
+        } else {
+            argc == arity
+        }
+    }
+
+    pub fn has_flag(&self, flag: Flag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// Group the command is documented under, taken from its data type category
+    pub fn group(&self) -> &'static str {
+        let groups = [
+            ("@string", "string"),
+            ("@list", "list"),
+            ("@hash", "hash"),
+            ("@set", "set"),
+            ("@sortedset", "sorted-set"),
+            ("@stream", "stream"),
+            ("@pubsub", "pubsub"),
+            ("@transaction", "transactions"),
+            ("@connection", "connection"),
+            ("@keyspace", "generic"),
+        ];
+        groups
+            .iter()
+            .find(|(category, _)| self.acl_categories.contains(category))
+            .map_or("server", |(_, group)| group)
+    }
+
+    /// The keys in a full command line, name included, e.g. both keys of RENAME
+    pub fn keys<'a>(&self, args: &'a [Vec<u8>]) -> Vec<&'a [u8]> {
+        if self.name == "xread" {
+            // XREAD ... STREAMS key [key ...] id [id ...]
+            let Some(pos) = args.iter().position(|a| a.eq_ignore_ascii_case(b"streams")) else {
+                return Vec::new();
+            };
+            let streams = &args[pos + 1..];
+            return streams[..streams.len() / 2]
+                .iter()
+                .map(|k| k.as_slice())
+                .collect();
+        }
+        // SINTERCARD numkeys key [key ...] [LIMIT limit], BLMPOP has a timeout before numkeys
+        let numkeys_at = match self.name {
+            "sintercard" | "lmpop" => Some(1),
+            "blmpop" => Some(2),
+            _ => None,
+        };
+        if let Some(at) = numkeys_at {
+            let numkeys = args.get(at).and_then(|n| parse_redis_int(n)).unwrap_or(0);
+            return args
+                .iter()
+                .skip(at + 1)
+                .take(numkeys.max(0) as usize)
+                .map(|k| k.as_slice())
+                .collect();
+        }
+        if self.first_key <= 0 {
+            return Vec::new();
+        }
+        let last = if self.last_key < 0 {
+            args.len() as i32 + self.last_key
+        } else {
+            self.last_key
+        };
+        (self.first_key..=last.min(args.len() as i32 - 1))
+            .step_by(self.step.max(1) as usize)
+            .map(|i| args[i as usize].as_slice())
+            .collect()
+    }
+
+    /// One entry of the COMMAND and COMMAND INFO replies
+    pub fn info(&self) -> RespValue {
+        let simple_set =
+            |names: Vec<&str>| RespValue::Set(names.into_iter().map(RespValue::simple).collect());
+        RespValue::Array(vec![
+            RespValue::bulk(self.name),
+            RespValue::Integer(self.arity as i64),
+            simple_set(self.flags.iter().map(|f| f.name()).collect()),
+            RespValue::Integer(self.first_key as i64),
+            RespValue::Integer(self.last_key as i64),
+            RespValue::Integer(self.step as i64),
+            simple_set(self.acl_categories.to_vec()),
+            // tips, key specifications and subcommands are not described
+            RespValue::empty_array(),
+            RespValue::empty_array(),
+            RespValue::empty_array(),
+        ])
+    }
+
+    /// One entry of the COMMAND DOCS reply
+    pub fn docs(&self) -> RespValue {
+        RespValue::Map(vec![(
+            RespValue::bulk("group"),
+            RespValue::bulk(self.group()),
+        )])
+    }
+}
+
+const STRING_READ: &[&str] = &["@read", "@string", "@fast"];
+const STRING_WRITE: &[&str] = &["@write", "@string", "@fast"];
+const STRING_WRITE_SLOW: &[&str] = &["@write", "@string", "@slow"];
+const KEYSPACE_READ: &[&str] = &["@keyspace", "@read", "@fast"];
+const KEYSPACE_WRITE: &[&str] = &["@keyspace", "@write", "@fast"];
+const LIST_READ: &[&str] = &["@read", "@list", "@fast"];
+const LIST_READ_SLOW: &[&str] = &["@read", "@list", "@slow"];
+const LIST_WRITE: &[&str] = &["@write", "@list", "@fast"];
+const LIST_WRITE_SLOW: &[&str] = &["@write", "@list", "@slow"];
+const LIST_BLOCKING: &[&str] = &["@write", "@list", "@slow", "@blocking"];
+const HASH_READ: &[&str] = &["@read", "@hash", "@fast"];
+const HASH_WRITE: &[&str] = &["@write", "@hash", "@fast"];
+const SET_READ: &[&str] = &["@read", "@set", "@fast"];
+const SET_READ_SLOW: &[&str] = &["@read", "@set", "@slow"];
+const SET_WRITE: &[&str] = &["@write", "@set", "@fast"];
+const SET_WRITE_SLOW: &[&str] = &["@write", "@set", "@slow"];
+const ZSET_READ: &[&str] = &["@read", "@sortedset", "@fast"];
+const ZSET_READ_SLOW: &[&str] = &["@read", "@sortedset", "@slow"];
+const ZSET_WRITE: &[&str] = &["@write", "@sortedset", "@fast"];
+const ZSET_WRITE_SLOW: &[&str] = &["@write", "@sortedset", "@slow"];
+const ADMIN: &[&str] = &["@admin", "@slow", "@dangerous"];
+const NO_KEYS: (i32, i32, i32) = (0, 0, 0);
+const ONE_KEY: (i32, i32, i32) = (1, 1, 1);
+const ALL_KEYS: (i32, i32, i32) = (1, -1, 1);
+
+#[rustfmt::skip]
+pub static COMMAND_TABLE: &[CommandSpec] = &[
+    // connection
+    CommandSpec::new("ping", -1, &[Fast, Stale], NO_KEYS, &["@fast", "@connection"]),
+    CommandSpec::new("echo", 2, &[Fast], NO_KEYS, &["@fast", "@connection"]),
+    CommandSpec::new("hello", -1, &[NoScript, Loading, Stale, Fast, NoAuth], NO_KEYS, &["@fast", "@connection"]),
+    CommandSpec::new("command", -1, &[Loading, Stale], NO_KEYS, &["@slow", "@connection"]),
+    CommandSpec::new("client", -2, &[Loading, Stale], NO_KEYS, &["@slow", "@connection"]),
+    // strings
+    CommandSpec::new("get", 2, &[Readonly, Fast], ONE_KEY, STRING_READ),
+    CommandSpec::new("set", -3, &[Write, DenyOom], ONE_KEY, STRING_WRITE_SLOW),
+    CommandSpec::new("setnx", 3, &[Write, DenyOom, Fast], ONE_KEY, STRING_WRITE),
+    CommandSpec::new("setex", 4, &[Write, DenyOom], ONE_KEY, STRING_WRITE_SLOW),
+    CommandSpec::new("psetex", 4, &[Write, DenyOom], ONE_KEY, STRING_WRITE_SLOW),
+    CommandSpec::new("getset", 3, &[Write, DenyOom, Fast], ONE_KEY, STRING_WRITE),
+    CommandSpec::new("getdel", 2, &[Write, Fast], ONE_KEY, STRING_WRITE),
+    CommandSpec::new("getex", -2, &[Write, Fast], ONE_KEY, STRING_WRITE),
+    CommandSpec::new("strlen", 2, &[Readonly, Fast], ONE_KEY, STRING_READ),
+    CommandSpec::new("getrange", 4, &[Readonly], ONE_KEY, &["@read", "@string", "@slow"]),
+    CommandSpec::new("setrange", 4, &[Write, DenyOom], ONE_KEY, STRING_WRITE_SLOW),
+    CommandSpec::new("append", 3, &[Write, DenyOom, Fast], ONE_KEY, STRING_WRITE),
+    CommandSpec::new("mget", -2, &[Readonly, Fast], ALL_KEYS, STRING_READ),
+    CommandSpec::new("mset", -3, &[Write, DenyOom], (1, -1, 2), STRING_WRITE_SLOW),
+    CommandSpec::new("msetnx", -3, &[Write, DenyOom], (1, -1, 2), STRING_WRITE_SLOW),
+    CommandSpec::new("incr", 2, &[Write, DenyOom, Fast], ONE_KEY, STRING_WRITE),
+    CommandSpec::new("decr", 2, &[Write, DenyOom, Fast], ONE_KEY, STRING_WRITE),
+    CommandSpec::new("incrby", 3, &[Write, DenyOom, Fast], ONE_KEY, STRING_WRITE),
+    CommandSpec::new("decrby", 3, &[Write, DenyOom, Fast], ONE_KEY, STRING_WRITE),
+    CommandSpec::new("incrbyfloat", 3, &[Write, DenyOom, Fast], ONE_KEY, STRING_WRITE),
+    // generic keys
+    CommandSpec::new("del", -2, &[Write], ALL_KEYS, &["@keyspace", "@write", "@slow"]),
+    CommandSpec::new("unlink", -2, &[Write, Fast], ALL_KEYS, KEYSPACE_WRITE),
+    CommandSpec::new("exists", -2, &[Readonly, Fast], ALL_KEYS, KEYSPACE_READ),
+    CommandSpec::new("touch", -2, &[Readonly, Fast], ALL_KEYS, KEYSPACE_READ),
+    CommandSpec::new("type", 2, &[Readonly, Fast], ONE_KEY, KEYSPACE_READ),
+    CommandSpec::new("keys", 2, &[Readonly], NO_KEYS, &["@keyspace", "@read", "@slow", "@dangerous"]),
+    CommandSpec::new("rename", 3, &[Write], (1, 2, 1), &["@keyspace", "@write", "@slow"]),
+    CommandSpec::new("renamenx", 3, &[Write, Fast], (1, 2, 1), KEYSPACE_WRITE),
+    CommandSpec::new("copy", -3, &[Write, DenyOom], (1, 2, 1), &["@keyspace", "@write", "@slow"]),
+    CommandSpec::new("expire", -3, &[Write, Fast], ONE_KEY, KEYSPACE_WRITE),
+    CommandSpec::new("pexpire", -3, &[Write, Fast], ONE_KEY, KEYSPACE_WRITE),
+    CommandSpec::new("expireat", -3, &[Write, Fast], ONE_KEY, KEYSPACE_WRITE),
+    CommandSpec::new("pexpireat", -3, &[Write, Fast], ONE_KEY, KEYSPACE_WRITE),
+    CommandSpec::new("ttl", 2, &[Readonly, Fast], ONE_KEY, KEYSPACE_READ),
+    CommandSpec::new("pttl", 2, &[Readonly, Fast], ONE_KEY, KEYSPACE_READ),
+    CommandSpec::new("expiretime", 2, &[Readonly, Fast], ONE_KEY, KEYSPACE_READ),
+    CommandSpec::new("pexpiretime", 2, &[Readonly, Fast], ONE_KEY, KEYSPACE_READ),
+    CommandSpec::new("persist", 2, &[Write, Fast], ONE_KEY, KEYSPACE_WRITE),
+    // server
+    CommandSpec::new("config", -2, &[Admin, NoScript, Loading, Stale], NO_KEYS, ADMIN),
+    CommandSpec::new("save", 1, &[Admin, NoScript, NoMulti], NO_KEYS, ADMIN),
+    CommandSpec::new("info", -1, &[Loading, Stale], NO_KEYS, &["@slow", "@dangerous"]),
+    // replication
+    CommandSpec::new("replconf", -1, &[Admin, NoScript, Loading, Stale], NO_KEYS, ADMIN),
+    CommandSpec::new("psync", -3, &[Admin, NoScript, NoMulti], NO_KEYS, ADMIN),
+    CommandSpec::new("wait", 3, &[NoScript], NO_KEYS, &["@slow", "@connection"]),
+    // transactions
+    CommandSpec::new("multi", 1, &[NoScript, Loading, Stale, Fast], NO_KEYS, &["@fast", "@transaction"]),
+    CommandSpec::new("exec", 1, &[NoScript, Loading, Stale], NO_KEYS, &["@slow", "@transaction"]),
+    CommandSpec::new("discard", 1, &[NoScript, Loading, Stale, Fast], NO_KEYS, &["@fast", "@transaction"]),
+    // streams
+    CommandSpec::new("xadd", -5, &[Write, DenyOom, Fast], ONE_KEY, &["@write", "@stream", "@fast"]),
+    CommandSpec::new("xrange", -4, &[Readonly], ONE_KEY, &["@read", "@stream", "@slow"]),
+    CommandSpec::new("xread", -4, &[Readonly, Blocking, MovableKeys], NO_KEYS, &["@read", "@stream", "@slow", "@blocking"]),
+    // lists
+    CommandSpec::new("rpush", -3, &[Write, DenyOom, Fast], ONE_KEY, LIST_WRITE),
+    CommandSpec::new("lpush", -3, &[Write, DenyOom, Fast], ONE_KEY, LIST_WRITE),
+    CommandSpec::new("lpushx", -3, &[Write, DenyOom, Fast], ONE_KEY, LIST_WRITE),
+    CommandSpec::new("rpushx", -3, &[Write, DenyOom, Fast], ONE_KEY, LIST_WRITE),
+    CommandSpec::new("lrange", 4, &[Readonly], ONE_KEY, LIST_READ_SLOW),
+    CommandSpec::new("llen", 2, &[Readonly, Fast], ONE_KEY, LIST_READ),
+    CommandSpec::new("lindex", 3, &[Readonly], ONE_KEY, LIST_READ_SLOW),
+    CommandSpec::new("lpos", -3, &[Readonly], ONE_KEY, LIST_READ_SLOW),
+    CommandSpec::new("lpop", -2, &[Write, Fast], ONE_KEY, LIST_WRITE),
+    CommandSpec::new("rpop", -2, &[Write, Fast], ONE_KEY, LIST_WRITE),
+    CommandSpec::new("lset", 4, &[Write, DenyOom], ONE_KEY, LIST_WRITE_SLOW),
+    CommandSpec::new("linsert", 5, &[Write, DenyOom], ONE_KEY, LIST_WRITE_SLOW),
+    CommandSpec::new("lrem", 4, &[Write], ONE_KEY, LIST_WRITE_SLOW),
+    CommandSpec::new("ltrim", 4, &[Write], ONE_KEY, LIST_WRITE_SLOW),
+    CommandSpec::new("lmove", 5, &[Write, DenyOom], (1, 2, 1), LIST_WRITE_SLOW),
+    CommandSpec::new("lmpop", -4, &[Write], NO_KEYS, LIST_WRITE_SLOW),
+    CommandSpec::new("blpop", -3, &[Write, Blocking, NoScript], (1, -2, 1), LIST_BLOCKING),
+    CommandSpec::new("brpop", -3, &[Write, Blocking, NoScript], (1, -2, 1), LIST_BLOCKING),
+    CommandSpec::new("blmove", 6, &[Write, DenyOom, Blocking, NoScript], (1, 2, 1), LIST_BLOCKING),
+    CommandSpec::new("brpoplpush", 4, &[Write, DenyOom, Blocking, NoScript], (1, 2, 1), LIST_BLOCKING),
+    CommandSpec::new("blmpop", -5, &[Write, Blocking], NO_KEYS, LIST_BLOCKING),
+    // pub/sub
+    CommandSpec::new("subscribe", -2, &[PubSub, NoScript, Loading, Stale], NO_KEYS, &["@pubsub", "@slow"]),
+    // redis also takes no channels to mean all of them, we need one
+    CommandSpec::new("unsubscribe", -2, &[PubSub, NoScript, Loading, Stale], NO_KEYS, &["@pubsub", "@slow"]),
+    CommandSpec::new("publish", 3, &[PubSub, Loading, Stale, Fast], NO_KEYS, &["@pubsub", "@fast"]),
+    // hashes
+    CommandSpec::new("hset", -4, &[Write, DenyOom, Fast], ONE_KEY, HASH_WRITE),
+    CommandSpec::new("hsetnx", 4, &[Write, DenyOom, Fast], ONE_KEY, HASH_WRITE),
+    CommandSpec::new("hget", 3, &[Readonly, Fast], ONE_KEY, HASH_READ),
+    CommandSpec::new("hmget", -3, &[Readonly, Fast], ONE_KEY, HASH_READ),
+    CommandSpec::new("hdel", -3, &[Write, Fast], ONE_KEY, HASH_WRITE),
+    CommandSpec::new("hgetall", 2, &[Readonly], ONE_KEY, &["@read", "@hash", "@slow"]),
+    CommandSpec::new("hkeys", 2, &[Readonly], ONE_KEY, &["@read", "@hash", "@slow"]),
+    CommandSpec::new("hvals", 2, &[Readonly], ONE_KEY, &["@read", "@hash", "@slow"]),
+    CommandSpec::new("hlen", 2, &[Readonly, Fast], ONE_KEY, HASH_READ),
+    CommandSpec::new("hexists", 3, &[Readonly, Fast], ONE_KEY, HASH_READ),
+    CommandSpec::new("hstrlen", 3, &[Readonly, Fast], ONE_KEY, HASH_READ),
+    CommandSpec::new("hincrby", 4, &[Write, DenyOom, Fast], ONE_KEY, HASH_WRITE),
+    CommandSpec::new("hincrbyfloat", 4, &[Write, DenyOom, Fast], ONE_KEY, HASH_WRITE),
+    // sets
+    CommandSpec::new("sadd", -3, &[Write, DenyOom, Fast], ONE_KEY, SET_WRITE),
+    CommandSpec::new("srem", -3, &[Write, Fast], ONE_KEY, SET_WRITE),
+    CommandSpec::new("smembers", 2, &[Readonly], ONE_KEY, SET_READ_SLOW),
+    CommandSpec::new("sismember", 3, &[Readonly, Fast], ONE_KEY, SET_READ),
+    CommandSpec::new("smismember", -3, &[Readonly, Fast], ONE_KEY, SET_READ),
+    CommandSpec::new("scard", 2, &[Readonly, Fast], ONE_KEY, SET_READ),
+    CommandSpec::new("spop", -2, &[Write, Fast], ONE_KEY, SET_WRITE),
+    CommandSpec::new("srandmember", -2, &[Readonly], ONE_KEY, SET_READ_SLOW),
+    CommandSpec::new("smove", 4, &[Write, Fast], (1, 2, 1), SET_WRITE),
+    CommandSpec::new("sinter", -2, &[Readonly], ALL_KEYS, SET_READ_SLOW),
+    CommandSpec::new("sunion", -2, &[Readonly], ALL_KEYS, SET_READ_SLOW),
+    CommandSpec::new("sdiff", -2, &[Readonly], ALL_KEYS, SET_READ_SLOW),
+    CommandSpec::new("sinterstore", -3, &[Write, DenyOom], ALL_KEYS, SET_WRITE_SLOW),
+    CommandSpec::new("sunionstore", -3, &[Write, DenyOom], ALL_KEYS, SET_WRITE_SLOW),
+    CommandSpec::new("sdiffstore", -3, &[Write, DenyOom], ALL_KEYS, SET_WRITE_SLOW),
+    CommandSpec::new("sintercard", -3, &[Readonly, MovableKeys], NO_KEYS, SET_READ_SLOW),
+    // sorted sets
+    CommandSpec::new("zadd", -4, &[Write, DenyOom, Fast], ONE_KEY, ZSET_WRITE),
+    CommandSpec::new("zincrby", 4, &[Write, DenyOom, Fast], ONE_KEY, ZSET_WRITE),
+    CommandSpec::new("zrank", -3, &[Readonly, Fast], ONE_KEY, ZSET_READ),
+    CommandSpec::new("zrange", -4, &[Readonly], ONE_KEY, ZSET_READ_SLOW),
+    CommandSpec::new("zrangebyscore", -4, &[Readonly], ONE_KEY, ZSET_READ_SLOW),
+    CommandSpec::new("zrevrangebyscore", -4, &[Readonly], ONE_KEY, ZSET_READ_SLOW),
+    CommandSpec::new("zrangebylex", -4, &[Readonly], ONE_KEY, ZSET_READ_SLOW),
+    CommandSpec::new("zrevrangebylex", -4, &[Readonly], ONE_KEY, ZSET_READ_SLOW),
+    CommandSpec::new("zrevrange", -4, &[Readonly], ONE_KEY, ZSET_READ_SLOW),
+    CommandSpec::new("zrangestore", -5, &[Write, DenyOom], (1, 2, 1), ZSET_WRITE_SLOW),
+    CommandSpec::new("zcard", 2, &[Readonly, Fast], ONE_KEY, ZSET_READ),
+    CommandSpec::new("zscore", 3, &[Readonly, Fast], ONE_KEY, ZSET_READ),
+    CommandSpec::new("zrem", -3, &[Write, Fast], ONE_KEY, ZSET_WRITE),
+    // member expiry, as in KeyDB
+    CommandSpec::new("expiremember", 4, &[Write, Fast], ONE_KEY, ZSET_WRITE),
+    CommandSpec::new("pexpiremember", 4, &[Write, Fast], ONE_KEY, ZSET_WRITE),
+    CommandSpec::new("expirememberat", 4, &[Write, Fast], ONE_KEY, ZSET_WRITE),
+    CommandSpec::new("pexpirememberat", 4, &[Write, Fast], ONE_KEY, ZSET_WRITE),
+    CommandSpec::new("memberttl", 3, &[Readonly, Fast], ONE_KEY, ZSET_READ),
+];
+
+/// ERR unknown command 'foo', with args beginning with: 'a' 'b'
+pub fn unknown_command_error(args: &[Vec<u8>]) -> RespValue {
+    let Some((name, rest)) = args.split_first() else {
+        return RespValue::error("ERR unknown command ''");
+    };
+    let mut msg = format!(
+        "ERR unknown command '{}', with args beginning with: ",
+        printable_arg(name)
+    );
+    for arg in rest {
+        // keep the reply short when a client sends something huge
+        if msg.len() >= 128 {
+            break;
+        }
+        msg.push_str(&format!("'{}' ", printable_arg(arg)));
+    }
+    RespValue::Error(msg)
+}
+
+/// COMMAND [COUNT | INFO [name ...] | DOCS [name ...] | GETKEYS command [arg ...]]
+pub fn handle_command_cmd(registry: &CommandRegistry, args: &[Vec<u8>]) -> RespValue {
+    let Some(subcommand) = args.first() else {
+        return RespValue::Array(registry.specs().map(CommandSpec::info).collect());
+    };
+    let names = &args[1..];
+    let subcommand = arg_str(subcommand).to_lowercase();
+    match subcommand.as_str() {
+        "count" if names.is_empty() => RespValue::Integer(registry.len() as i64),
+        "info" if names.is_empty() => {
+            RespValue::Array(registry.specs().map(CommandSpec::info).collect())
+        }
+        "info" => RespValue::Array(
+            names
+                .iter()
+                .map(|n| {
+                    registry
+                        .lookup(n)
+                        .map_or(RespValue::Null, CommandSpec::info)
+                })
+                .collect(),
+        ),
+        "docs" if names.is_empty() => RespValue::Map(
+            registry
+                .specs()
+                .map(|spec| (RespValue::bulk(spec.name), spec.docs()))
+                .collect(),
+        ),
+        // unknown names are left out of the map
+        "docs" => RespValue::Map(
+            names
+                .iter()
+                .filter_map(|n| registry.lookup(n))
+                .map(|spec| (RespValue::bulk(spec.name), spec.docs()))
+                .collect(),
+        ),
+        "getkeys" if !names.is_empty() => match registry.lookup(&names[0]) {
+            None => RespValue::error("ERR Invalid command specified"),
+            Some(spec) if !spec.arity_matches(names.len()) => {
+                RespValue::error("ERR Invalid number of arguments specified for command")
+            }
+            Some(spec) => match spec.keys(names) {
+                keys if keys.is_empty() => RespValue::error("ERR The command has no key arguments"),
+                keys => RespValue::bulk_array(keys),
+            },
+        },
+        "count" | "getkeys" => wrong_args_error(&format!("command|{subcommand}")),
+        _ => RespValue::Error(format!(
+            "ERR unknown subcommand '{}'. Try COMMAND HELP.",
+            printable_arg(&args[0])
+        )),
+    }
+}
@@ -0,0 +1,160 @@
+use crate::constants::*;
+use crate::redis_database::{RedisDatabase, WrongType};
+use crate::redis_hash::RedisHash;
+use crate::resp::RespValue;
+use crate::utils::{parse_redis_float, parse_redis_int};
+
+/// Runs a read only command on the hash under key, `missing` is the reply when there is none
+fn read_hash(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    missing: RespValue,
+    read: impl FnOnce(&RedisHash) -> RespValue,
+) -> RespValue {
+    match db.get_hash(key) {
+        Ok(Some(hash)) => read(hash),
+        Ok(None) => missing,
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
+
+/// Runs a write on the hash under key, creating it when the write leaves any field behind
+/// `write` gives the reply and how many changes it made, for the database's dirty count
+/// A missing key is written aside first, so a failing write like HINCRBYFLOAT by inf leaves
+/// no key behind and nothing to propagate
+fn write_hash(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    write: impl FnOnce(&mut RedisHash) -> (RespValue, usize),
+) -> RespValue {
+    let (res, changed) = match db.get_hash(key) {
+        Ok(Some(hash)) => write(hash),
+        Ok(None) => {
+            let mut hash = RedisHash::new();
+            let written = write(&mut hash);
+            if !hash.is_empty() {
+                if let Ok(created) = db.get_or_create_hash(key) {
+                    *created = hash;
+                }
+            }
+            written
+        }
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    db.dirty += changed as u64;
+    res
+}
+
+/// HSET key field value [field value ...], returns how many fields were added
+pub fn handle_hset(db: &mut RedisDatabase, key: &[u8], pairs: &[Vec<u8>]) -> RespValue {
+    write_hash(db, key, |hash| {
+        let added = pairs
+            .chunks_exact(2)
+            .filter(|pair| hash.insert(&pair[0], &pair[1]))
+            .count();
+        // an overwritten field is a change too
+        (RespValue::Integer(added as i64), pairs.len() / 2)
+    })
+}
+
+pub fn handle_hsetnx(db: &mut RedisDatabase, key: &[u8], field: &[u8], value: &[u8]) -> RespValue {
+    write_hash(db, key, |hash| {
+        let added = !hash.contains(field) && hash.insert(field, value);
+        (RespValue::Integer(added as i64), added as usize)
+    })
+}
+
+pub fn handle_hget(db: &mut RedisDatabase, key: &[u8], field: &[u8]) -> RespValue {
+    read_hash(db, key, RespValue::Null, |hash| {
+        hash.get(field).map_or(RespValue::Null, RespValue::bulk)
+    })
+}
+
+/// HMGET key field [field ...], a null for each missing field
+pub fn handle_hmget(db: &mut RedisDatabase, key: &[u8], fields: &[Vec<u8>]) -> RespValue {
+    let all_null = RespValue::Array(vec![RespValue::Null; fields.len()]);
+    read_hash(db, key, all_null, |hash| {
+        RespValue::Array(
+            fields
+                .iter()
+                .map(|f| hash.get(f).map_or(RespValue::Null, RespValue::bulk))
+                .collect(),
+        )
+    })
+}
+
+/// HDEL key field [field ...], the key goes away with its last field
+pub fn handle_hdel(db: &mut RedisDatabase, key: &[u8], fields: &[Vec<u8>]) -> RespValue {
+    let hash = match db.get_hash(key) {
+        Ok(Some(h)) => h,
+        Ok(None) => return RespValue::Integer(0),
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    let removed = fields.iter().filter(|f| hash.remove(f)).count();
+    if hash.is_empty() {
+        db.remove(key);
+    }
+    db.dirty += removed as u64;
+    RespValue::Integer(removed as i64)
+}
+
+/// HGETALL key, field value pairs as a map, flattened for RESP2 clients
+pub fn handle_hgetall(db: &mut RedisDatabase, key: &[u8]) -> RespValue {
+    read_hash(db, key, RespValue::Map(Vec::new()), |hash| {
+        RespValue::Map(
+            hash.iter()
+                .map(|(f, v)| (RespValue::bulk(f), RespValue::bulk(v)))
+                .collect(),
+        )
+    })
+}
+
+/// HKEYS or HVALS key
+pub fn handle_hkeys(db: &mut RedisDatabase, key: &[u8], values: bool) -> RespValue {
+    read_hash(db, key, RespValue::empty_array(), |hash| {
+        RespValue::bulk_array(hash.iter().map(|(f, v)| if values { v } else { f }))
+    })
+}
+
+pub fn handle_hlen(db: &mut RedisDatabase, key: &[u8]) -> RespValue {
+    read_hash(db, key, RespValue::Integer(0), |hash| {
+        RespValue::Integer(hash.len() as i64)
+    })
+}
+
+pub fn handle_hexists(db: &mut RedisDatabase, key: &[u8], field: &[u8]) -> RespValue {
+    read_hash(db, key, RespValue::Integer(0), |hash| {
+        RespValue::Integer(hash.contains(field) as i64)
+    })
+}
+
+pub fn handle_hstrlen(db: &mut RedisDatabase, key: &[u8], field: &[u8]) -> RespValue {
+    read_hash(db, key, RespValue::Integer(0), |hash| {
+        RespValue::Integer(hash.get(field).map_or(0, |v| v.len()) as i64)
+    })
+}
+
+pub fn handle_hincrby(db: &mut RedisDatabase, key: &[u8], field: &[u8], delta: &[u8]) -> RespValue {
+    let Some(delta) = parse_redis_int(delta) else {
+        return RespValue::error(NOT_INT_ERROR);
+    };
+    write_hash(db, key, |hash| match hash.incr_by(field, delta) {
+        Ok(new_val) => (RespValue::Integer(new_val), 1),
+        Err(e) => (RespValue::error(e), 0),
+    })
+}
+
+pub fn handle_hincrbyfloat(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    field: &[u8],
+    increment: &[u8],
+) -> RespValue {
+    let Some(increment) = parse_redis_float(increment) else {
+        return RespValue::error(NOT_FLOAT_ERROR);
+    };
+    write_hash(db, key, |hash| match hash.incr_by_float(field, increment) {
+        Ok(new_val) => (RespValue::bulk(new_val), 1),
+        Err(e) => (RespValue::error(e), 0),
+    })
+}
@@ -0,0 +1,525 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::constants::*;
+use crate::handler::command_handlers::handle_push;
+use crate::redis_database::blocking::Served;
+use crate::redis_database::{RedisDatabase, WrongType};
+use crate::redis_list::RedisList;
+use crate::resp::RespValue;
+use crate::utils::parse_redis_int;
+
+/// Runs a read only command on the list under key, `missing` is the reply when there is none
+fn read_list(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    missing: RespValue,
+    read: impl FnOnce(&RedisList) -> RespValue,
+) -> RespValue {
+    match db.get_list(key) {
+        Ok(Some(list)) => read(list),
+        Ok(None) => missing,
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
+
+/// Runs a write on an existing list, `missing` is the reply when there is none
+/// `write` gives the reply and how many changes it made, for the database's dirty count
+/// A list the write leaves empty is dropped
+fn write_list(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    missing: RespValue,
+    write: impl FnOnce(&mut RedisList) -> (RespValue, usize),
+) -> RespValue {
+    let list = match db.get_list(key) {
+        Ok(Some(list)) => list,
+        Ok(None) => return missing,
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    let (res, changed) = write(list);
+    if list.is_empty() {
+        db.remove(key);
+    }
+    db.dirty += changed as u64;
+    res
+}
+
+/// LEFT or RIGHT as taken by LMOVE, true for LEFT
+fn parse_side(arg: &[u8]) -> Option<bool> {
+    if arg.eq_ignore_ascii_case(b"left") {
+        Some(true)
+    } else if arg.eq_ignore_ascii_case(b"right") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Pops up to `count` elements from either end, dropping the key when the list runs out
+/// None when there is no list to pop from
+pub fn pop_list(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    count: usize,
+    left: bool,
+) -> Result<Option<Vec<Vec<u8>>>, WrongType> {
+    let Some(list) = db.get_list(key)? else {
+        return Ok(None);
+    };
+    let popped = list.pop(count, left);
+    if list.is_empty() {
+        db.remove(key);
+    }
+    db.dirty += popped.len() as u64;
+    Ok(Some(popped))
+}
+
+pub fn handle_llen(db: &mut RedisDatabase, key: &[u8]) -> RespValue {
+    read_list(db, key, RespValue::Integer(0), |list| {
+        RespValue::Integer(list.len() as i64)
+    })
+}
+
+pub fn handle_lrange(db: &mut RedisDatabase, key: &[u8], start: &[u8], stop: &[u8]) -> RespValue {
+    let (Some(start), Some(stop)) = (parse_redis_int(start), parse_redis_int(stop)) else {
+        return RespValue::error(NOT_INT_ERROR);
+    };
+    read_list(db, key, RespValue::empty_array(), |list| {
+        RespValue::bulk_array(list.range(start, stop))
+    })
+}
+
+/// LPOP or RPOP key [count]
+/// Without a count the reply is a single element, with one it is an array even for 0
+pub fn handle_pop(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    count: Option<&[u8]>,
+    left: bool,
+) -> RespValue {
+    let count = match count.map(parse_redis_int) {
+        None => None,
+        Some(Some(n)) if n >= 0 => Some(n as usize),
+        Some(Some(_)) => return RespValue::error(NOT_POSITIVE_ERROR),
+        Some(None) => return RespValue::error(NOT_INT_ERROR),
+    };
+    match pop_list(db, key, count.unwrap_or(1), left) {
+        Ok(Some(popped)) if count.is_some() => RespValue::bulk_array(popped),
+        Ok(Some(popped)) => popped.first().map_or(RespValue::Null, RespValue::bulk),
+        Ok(None) if count.is_some() => RespValue::NullArray,
+        Ok(None) => RespValue::Null,
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
+
+pub fn handle_lindex(db: &mut RedisDatabase, key: &[u8], index: &[u8]) -> RespValue {
+    let Some(index) = parse_redis_int(index) else {
+        return RespValue::error(NOT_INT_ERROR);
+    };
+    read_list(db, key, RespValue::Null, |list| {
+        list.get(index).map_or(RespValue::Null, RespValue::bulk)
+    })
+}
+
+pub fn handle_lset(db: &mut RedisDatabase, key: &[u8], index: &[u8], value: &[u8]) -> RespValue {
+    let Some(index) = parse_redis_int(index) else {
+        return RespValue::error(NOT_INT_ERROR);
+    };
+    let missing = RespValue::error(NO_SUCH_KEY_ERROR);
+    write_list(db, key, missing, |list| {
+        if list.set(index, value.to_vec()) {
+            (RespValue::ok(), 1)
+        } else {
+            (RespValue::error(INDEX_OUT_OF_RANGE_ERROR), 0)
+        }
+    })
+}
+
+/// LINSERT key BEFORE|AFTER pivot element, -1 when the pivot is not found
+pub fn handle_linsert(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    position: &[u8],
+    pivot: &[u8],
+    value: &[u8],
+) -> RespValue {
+    let before = if position.eq_ignore_ascii_case(b"before") {
+        true
+    } else if position.eq_ignore_ascii_case(b"after") {
+        false
+    } else {
+        return RespValue::error(SYNTAX_ERROR);
+    };
+    write_list(db, key, RespValue::Integer(0), |list| {
+        match list.insert(pivot, value.to_vec(), before) {
+            Some(len) => (RespValue::Integer(len as i64), 1),
+            None => (RespValue::Integer(-1), 0),
+        }
+    })
+}
+
+/// LREM key count element, returns how many elements were removed
+pub fn handle_lrem(db: &mut RedisDatabase, key: &[u8], count: &[u8], value: &[u8]) -> RespValue {
+    let Some(count) = parse_redis_int(count) else {
+        return RespValue::error(NOT_INT_ERROR);
+    };
+    write_list(db, key, RespValue::Integer(0), |list| {
+        let removed = list.remove(count, value);
+        (RespValue::Integer(removed as i64), removed)
+    })
+}
+
+pub fn handle_ltrim(db: &mut RedisDatabase, key: &[u8], start: &[u8], stop: &[u8]) -> RespValue {
+    let (Some(start), Some(stop)) = (parse_redis_int(start), parse_redis_int(stop)) else {
+        return RespValue::error(NOT_INT_ERROR);
+    };
+    write_list(db, key, RespValue::ok(), |list| {
+        let len = list.len();
+        list.trim(start, stop);
+        (RespValue::ok(), len - list.len())
+    })
+}
+
+/// LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]
+/// Without COUNT the reply is the first matching index or null, with it an array of them
+pub fn handle_lpos(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    element: &[u8],
+    options: &[Vec<u8>],
+) -> RespValue {
+    let mut rank = 1;
+    let mut count = None;
+    let mut maxlen = 0;
+    for pair in options.chunks(2) {
+        let [option, value] = pair else {
+            return RespValue::error(SYNTAX_ERROR);
+        };
+        let Some(value) = parse_redis_int(value) else {
+            return RespValue::error(NOT_INT_ERROR);
+        };
+        if option.eq_ignore_ascii_case(b"rank") {
+            if value == 0 {
+                return RespValue::error("ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list");
+            }
+            rank = value;
+        } else if option.eq_ignore_ascii_case(b"count") {
+            if value < 0 {
+                return RespValue::error("ERR COUNT can't be negative");
+            }
+            count = Some(value as usize);
+        } else if option.eq_ignore_ascii_case(b"maxlen") {
+            if value < 0 {
+                return RespValue::error("ERR MAXLEN can't be negative");
+            }
+            maxlen = value as usize;
+        } else {
+            return RespValue::error(SYNTAX_ERROR);
+        }
+    }
+
+    let missing = match count {
+        Some(_) => RespValue::empty_array(),
+        None => RespValue::Null,
+    };
+    read_list(db, key, missing, |list| match count {
+        // COUNT 0 asks for every match
+        Some(count) => {
+            let limit = if count == 0 { usize::MAX } else { count };
+            let found = list.positions(element, rank, limit, maxlen);
+            RespValue::Array(
+                found
+                    .into_iter()
+                    .map(|i| RespValue::Integer(i as i64))
+                    .collect(),
+            )
+        }
+        None => list
+            .positions(element, rank, 1, maxlen)
+            .first()
+            .map_or(RespValue::Null, |i| RespValue::Integer(*i as i64)),
+    })
+}
+
+/// LPUSHX or RPUSHX, only pushes onto a list that already exists
+pub fn handle_pushx(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    values: &[Vec<u8>],
+    left: bool,
+) -> RespValue {
+    match db.get_list(key) {
+        Ok(Some(_)) => handle_push(db, key, values, left),
+        Ok(None) => RespValue::Integer(0),
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
+
+/// LMOVE source destination LEFT|RIGHT LEFT|RIGHT, null when the source is empty
+pub fn handle_lmove(
+    db: &mut RedisDatabase,
+    source: &[u8],
+    destination: &[u8],
+    from: &[u8],
+    to: &[u8],
+) -> RespValue {
+    let (Some(from_left), Some(to_left)) = (parse_side(from), parse_side(to)) else {
+        return RespValue::error(SYNTAX_ERROR);
+    };
+    // the destination is checked first so a wrong type never loses the element
+    if let Err(WrongType) = db.get_list(destination) {
+        return RespValue::error(WRONGTYPE_ERROR);
+    }
+    let element = match pop_list(db, source, 1, from_left) {
+        Ok(Some(mut popped)) => popped.remove(0),
+        Ok(None) => return RespValue::Null,
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    match db.get_or_create_list(destination) {
+        Ok(list) => list.push(element.clone(), to_left),
+        Err(WrongType) => unreachable!("checked above"),
+    }
+    db.dirty += 1;
+    RespValue::bulk(element)
+}
+
+fn side_name(left: bool) -> &'static [u8] {
+    if left {
+        b"LEFT"
+    } else {
+        b"RIGHT"
+    }
+}
+
+fn pop_name(left: bool) -> &'static [u8] {
+    if left {
+        b"LPOP"
+    } else {
+        b"RPOP"
+    }
+}
+
+/// Timeout of a blocking list command in seconds, None for 0 which waits forever
+pub fn parse_timeout(arg: &[u8]) -> Result<Option<Duration>, RespValue> {
+    match std::str::from_utf8(arg)
+        .ok()
+        .and_then(|t| t.parse::<f64>().ok())
+    {
+        Some(t) if t < 0.0 => Err(RespValue::error("ERR timeout is negative")),
+        Some(0.0) => Ok(None),
+        Some(t) if t.is_finite() => Duration::try_from_secs_f64(t)
+            .map_err(|_| RespValue::error(TIMEOUT_OUT_OF_RANGE_ERROR))
+            .and_then(check_timeout)
+            .map(Some),
+        _ => Err(RespValue::error(TIMEOUT_NOT_FLOAT_ERROR)),
+    }
+}
+
+/// Passes a timeout a deadline can be set from, past some point adding it to now overflows
+pub fn check_timeout(timeout: Duration) -> Result<Duration, RespValue> {
+    let in_range = Instant::now().checked_add(timeout).is_some()
+        && SystemTime::now().checked_add(timeout).is_some();
+    if in_range {
+        Ok(timeout)
+    } else {
+        Err(RespValue::error(TIMEOUT_OUT_OF_RANGE_ERROR))
+    }
+}
+
+/// numkeys key [key ...] LEFT|RIGHT [COUNT count] as taken by LMPOP and BLMPOP
+pub fn parse_mpop(args: &[Vec<u8>]) -> Result<(Vec<Vec<u8>>, ListWait), RespValue> {
+    let numkeys = match parse_redis_int(&args[0]) {
+        Some(n) if n > 0 => n as usize,
+        Some(_) => return Err(RespValue::error("ERR numkeys should be greater than 0")),
+        None => return Err(RespValue::error(NOT_INT_ERROR)),
+    };
+    let Some(keys) = args.get(1..1 + numkeys) else {
+        return Err(RespValue::error(SYNTAX_ERROR));
+    };
+    let (left, count) = match &args[1 + numkeys..] {
+        [side] => (parse_side(side), 1),
+        [side, option, count] if option.eq_ignore_ascii_case(b"count") => {
+            match parse_redis_int(count) {
+                Some(c) if c > 0 => (parse_side(side), c as usize),
+                _ => return Err(RespValue::error("ERR count should be greater than 0")),
+            }
+        }
+        _ => return Err(RespValue::error(SYNTAX_ERROR)),
+    };
+    let left = left.ok_or(RespValue::error(SYNTAX_ERROR))?;
+    Ok((keys.to_vec(), ListWait::MultiPop { left, count }))
+}
+
+/// A blocking list command with its arguments parsed
+#[derive(Debug, Clone)]
+pub struct BlockingPop {
+    pub keys: Vec<Vec<u8>>,
+    /// None waits forever
+    pub timeout: Option<Duration>,
+    pub wait: ListWait,
+}
+
+/// LMPOP is parsed here too since it takes the same arguments as BLMPOP bar the timeout
+pub fn parse_blocking_pop(name: &str, args: &[Vec<u8>]) -> Result<BlockingPop, RespValue> {
+    match name {
+        "blpop" | "brpop" => {
+            let (timeout, keys) = args.split_last().expect("arity is checked");
+            let wait = ListWait::Pop {
+                left: name == "blpop",
+            };
+            Ok(BlockingPop {
+                keys: keys.to_vec(),
+                timeout: parse_timeout(timeout)?,
+                wait,
+            })
+        }
+        "blmove" | "brpoplpush" => {
+            // BRPOPLPUSH source destination timeout is BLMOVE source destination RIGHT LEFT
+            let (from_left, to_left, timeout) = match &args[2..] {
+                [timeout] => (false, true, timeout),
+                [from, to, timeout] => match (parse_side(from), parse_side(to)) {
+                    (Some(from_left), Some(to_left)) => (from_left, to_left, timeout),
+                    _ => return Err(RespValue::error(SYNTAX_ERROR)),
+                },
+                _ => return Err(RespValue::error(SYNTAX_ERROR)),
+            };
+            let wait = ListWait::Move {
+                destination: args[1].clone(),
+                from_left,
+                to_left,
+            };
+            Ok(BlockingPop {
+                keys: vec![args[0].clone()],
+                timeout: parse_timeout(timeout)?,
+                wait,
+            })
+        }
+        "blmpop" => {
+            let timeout = parse_timeout(&args[0])?;
+            let (keys, wait) = parse_mpop(&args[1..])?;
+            Ok(BlockingPop {
+                keys,
+                timeout,
+                wait,
+            })
+        }
+        _ => {
+            let (keys, wait) = parse_mpop(args)?;
+            Ok(BlockingPop {
+                keys,
+                timeout: None,
+                wait,
+            })
+        }
+    }
+}
+
+/// What a blocking list command does on the first of its keys that has elements
+#[derive(Debug, Clone)]
+pub enum ListWait {
+    /// BLPOP and BRPOP
+    Pop { left: bool },
+    /// BLMPOP and LMPOP
+    MultiPop { left: bool, count: usize },
+    /// BLMOVE and BRPOPLPUSH
+    Move {
+        destination: Vec<u8>,
+        from_left: bool,
+        to_left: bool,
+    },
+}
+
+impl ListWait {
+    /// Runs the command on key, None while there is nothing to pop
+    pub fn serve(&self, db: &mut RedisDatabase, key: &[u8]) -> Result<Option<Served>, WrongType> {
+        let served = match self {
+            ListWait::Pop { left } => pop_list(db, key, 1, *left)?.map(|mut popped| Served {
+                reply: RespValue::bulk_array([key.to_vec(), popped.remove(0)]),
+                propagate: Some(vec![pop_name(*left).to_vec(), key.to_vec()]),
+            }),
+            ListWait::MultiPop { left, count } => {
+                pop_list(db, key, *count, *left)?.map(|popped| Served {
+                    reply: RespValue::Array(vec![
+                        RespValue::bulk(key),
+                        RespValue::bulk_array(popped),
+                    ]),
+                    propagate: Some(vec![
+                        pop_name(*left).to_vec(),
+                        key.to_vec(),
+                        count.to_string().into_bytes(),
+                    ]),
+                })
+            }
+            ListWait::Move {
+                destination,
+                from_left,
+                to_left,
+            } => {
+                if db.get_list(key)?.is_none() {
+                    return Ok(None);
+                }
+                let from = side_name(*from_left);
+                let to = side_name(*to_left);
+                let reply = handle_lmove(db, key, destination, from, to);
+                let propagate = (!reply.is_error()).then(|| {
+                    vec![
+                        b"LMOVE".to_vec(),
+                        key.to_vec(),
+                        destination.clone(),
+                        from.to_vec(),
+                        to.to_vec(),
+                    ]
+                });
+                Some(Served { reply, propagate })
+            }
+        };
+        Ok(served)
+    }
+}
+
+/// BLPOP, BRPOP, BLMOVE, BRPOPLPUSH and BLMPOP
+/// Serves the first key with elements right away, otherwise the client is blocked on all of
+/// them, or gets a null when it may not block as inside MULTI
+/// None means the client is now blocked
+pub fn handle_blocking_pop(
+    db: &mut RedisDatabase,
+    client_id: u64,
+    pop: BlockingPop,
+    can_block: bool,
+) -> Option<Served> {
+    let BlockingPop {
+        keys,
+        timeout,
+        wait,
+    } = pop;
+    for key in &keys {
+        match wait.serve(db, key) {
+            Ok(Some(served)) => return Some(served),
+            Ok(None) => {}
+            Err(WrongType) => {
+                return Some(Served {
+                    reply: RespValue::error(WRONGTYPE_ERROR),
+                    propagate: None,
+                })
+            }
+        }
+    }
+    if !can_block {
+        return Some(Served {
+            reply: RespValue::NullArray,
+            propagate: None,
+        });
+    }
+    // parse_timeout made sure this fits
+    let deadline = timeout.and_then(|t| Instant::now().checked_add(t));
+    // a key that turned into another type is skipped, the client keeps waiting on it
+    let serve = move |db: &mut RedisDatabase, key: &[u8]| wait.serve(db, key).ok().flatten();
+    db.blocked.block(
+        client_id,
+        &keys,
+        deadline,
+        RespValue::NullArray,
+        Box::new(serve),
+    );
+    None
+}
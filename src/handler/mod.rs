@@ -1,1078 +1,1581 @@
 use std::collections::HashMap;
 use std::env;
 use std::error::Error;
-use std::fs::{self, File};
-use std::io::{prelude::*, Write};
+use std::fs;
 use std::net::TcpStream;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::thread::{self, sleep};
-use std::time::{Duration, SystemTime};
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::entry_stream::entry_utils::{get_all_stream_names, get_xread_resp_array};
-use crate::entry_stream::RedisEntryStream;
-use crate::redis_channel::Channel;
+use crate::entry_stream::entry_utils::get_all_stream_names;
+use crate::redis_channel::{Channel, PubSub, Subscriber};
 use crate::redis_connection::broadcast_info::BroadCastInfo;
 use crate::redis_connection::RedisConnection;
-use crate::redis_database::{
-    read_rdb_file, write_rdb_file, RdbError, RdbFile, RedisDatabase, RedisValue,
-};
-use crate::redis_list::RedisList;
-use crate::redis_sorted_set::RedisSortedSet;
-use crate::utils::{get_port, get_redis_int, get_resp_from_string, read_rdb_keys};
+use crate::redis_database::blocking::Served;
+use crate::redis_database::{write_rdb_file, RdbFile, RedisDatabase, WrongType};
+use crate::resp::{Protocol, RespValue};
+use crate::utils::{arg_str, get_port, parse_redis_int, printable_arg};
 
 use crate::constants::*;
+use crate::handler::command_table::{handle_command_cmd, unknown_command_error};
+
+use crate::handler::hash_handlers::{
+    handle_hdel, handle_hexists, handle_hget, handle_hgetall, handle_hincrby, handle_hincrbyfloat,
+    handle_hkeys, handle_hlen, handle_hmget, handle_hset, handle_hsetnx, handle_hstrlen,
+};
 
-use crate::handler::command_handlers::handle_set;
-use crate::utils::get_bulk_string;
+use crate::handler::list_handlers::{
+    check_timeout, handle_blocking_pop, handle_lindex, handle_linsert, handle_llen, handle_lmove,
+    handle_lpos, handle_lrange, handle_lrem, handle_lset, handle_ltrim, handle_pop, handle_pushx,
+    parse_blocking_pop,
+};
 
+use crate::handler::set_handlers::{
+    handle_sadd, handle_scard, handle_set_algebra, handle_set_algebra_store, handle_sintercard,
+    handle_sismember, handle_smembers, handle_smismember, handle_smove, handle_spop,
+    handle_srandmember, handle_srem, SetOp,
+};
+
+use crate::handler::zset_handlers::{
+    handle_zadd, handle_zincrby, handle_zrange, handle_zrangestore, handle_zrem,
+};
+
+use crate::handler::command_handlers::{
+    handle_append, handle_client, handle_copy, handle_expire, handle_expire_member, handle_getdel,
+    handle_getex, handle_getrange, handle_getset, handle_hello, handle_incr_by,
+    handle_incr_by_float, handle_member_ttl, handle_mget, handle_mset, handle_push, handle_rename,
+    handle_set, handle_set_variant, handle_setrange, handle_strlen, handle_ttl, handle_xadd,
+    handle_xread, propagate, wrong_args_error,
+};
+
+pub mod command;
 mod command_handlers;
+pub mod command_table;
+mod hash_handlers;
+mod list_handlers;
+mod set_handlers;
+mod zset_handlers;
+
+pub use command::{Command, CommandContext, CommandRegistry, RegisterError, Reply};
+pub use command_table::Flag;
+
+/// Server wide state every connection shares
+#[derive(Debug, Clone)]
+pub struct ServerContext {
+    pub dir: Option<String>,
+    pub db_filename: Option<String>,
+    pub info_fields: HashMap<String, String>,
+    pub broadcast_info: Arc<Mutex<BroadCastInfo>>,
+    pub replica_port: Option<String>,
+    pub master_port: Option<String>,
+    pub new_db: Arc<Mutex<RedisDatabase>>,
+    pub channels_db: Arc<Mutex<PubSub>>,
+    pub commands: Arc<CommandRegistry>,
+}
+
+type PollReply = Box<dyn FnMut(bool) -> Option<RespValue> + Send>;
 
-pub fn handle_connection(
-    //stream: Arc<Mutex<TcpStream>>,
-    stream: TcpStream,
-    dir: Option<String>,
-    db_filename: Option<String>,
-    info_fields: HashMap<String, String>,
-    broadcast_info: Arc<Mutex<BroadCastInfo>>,
-    replica_port: &Option<&str>,
-    master_port: &Option<String>,
-    new_db: Arc<Mutex<RedisDatabase>>,
-    entry_streams: Arc<Mutex<HashMap<String, RedisEntryStream>>>,
-    lists_map: Arc<Mutex<HashMap<String, RedisList>>>,
-    channels_db: Arc<Mutex<HashMap<String, Channel>>>,
-    sets_map: Arc<Mutex<HashMap<String, RedisSortedSet>>>, //subscribers_db: Arc<Mutex<HashMap<String, Subscriber>>>,
-) -> Result<(), Box<dyn Error>> {
-    eprintln!(
-        "handling_connection, master_port:{:?}, stream port:{:?}",
-        master_port,
-        get_port(&stream)
-    );
-
-    let sent_by_main = master_port.is_some() && get_port(&stream) == *master_port;
-
-    let mut conn = RedisConnection::new(stream.try_clone().unwrap());
-
-    if sent_by_main {
-        conn.is_master = true;
-        eprintln!("\n\n\n\nHANDLING HANDSHAKE\n\n\n\n\n");
-        conn.write_to_stream(&conn.format_resp_array(&["PING"]).as_bytes());
-        let _res = conn.try_read_command();
-        sleep(Duration::from_millis(10));
-        //eprintln!("Read result: {:?}", res);
-
-        let use_bytes = conn.format_resp_array(&[REPL_CONF, LISTENING_PORT, replica_port.unwrap()]);
-        conn.write_to_stream(&use_bytes.as_bytes());
-        let _res = conn.try_read_command();
-        sleep(Duration::from_millis(10));
-
-        conn.write_to_stream(
-            &conn
-                .format_resp_array(&[REPL_CONF, "capa", "psync2"])
-                .as_bytes(),
+/// A reply a blocked client is waiting on, the event loop polls it until it is ready
+/// The poll function gets whether the deadline passed and must give a reply when it has
+pub struct PendingReply {
+    pub deadline: Option<Instant>,
+    poll: PollReply,
+}
+
+impl PendingReply {
+    pub fn new(
+        deadline: Option<Instant>,
+        poll: impl FnMut(bool) -> Option<RespValue> + Send + 'static,
+    ) -> Self {
+        PendingReply {
+            deadline,
+            poll: Box::new(poll),
+        }
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Option<RespValue> {
+        let timed_out = self.deadline.is_some_and(|d| now >= d);
+        (self.poll)(timed_out)
+    }
+
+    /// Replies with whatever is there now, commands inside MULTI never block
+    pub fn resolve_now(&mut self) -> RespValue {
+        (self.poll)(true).unwrap_or(RespValue::Null)
+    }
+}
+
+/// One client connection along with the state it keeps between commands
+pub struct Session {
+    pub conn: RedisConnection,
+    sent_by_main: bool,
+    write_command: Vec<Vec<u8>>,
+    all_multi_commands: Vec<Vec<Vec<u8>>>,
+    /// A command queued since MULTI was rejected, so EXEC aborts
+    multi_dirty: bool,
+    is_exec_mode: bool,
+    hold_all_exec_reponse: Vec<RespValue>,
+    pending: Option<PendingReply>,
+    /// Parked in the database's blocked clients until the event loop hands it a reply
+    blocked_on_keys: bool,
+}
+
+impl Session {
+    /// Wraps a new connection, running the replica handshake first when it is the link to our master
+    pub fn new(stream: TcpStream, ctx: &ServerContext) -> Self {
+        eprintln!(
+            "handling_connection, master_port:{:?}, stream port:{:?}",
+            ctx.master_port,
+            get_port(&stream)
         );
-        let _res = conn.try_read_command();
-        sleep(Duration::from_millis(10));
 
-        conn.write_to_stream(&conn.format_resp_array(&[PSYNC, "?", "-1"]).as_bytes());
-        //ignore the last sent after psync
+        let sent_by_main = ctx.master_port.is_some() && get_port(&stream) == ctx.master_port;
+
+        let mut conn = RedisConnection::new(stream);
+
+        if sent_by_main {
+            conn.is_master = true;
+            eprintln!("\n\n\n\nHANDLING HANDSHAKE\n\n\n\n\n");
+            handshake(&mut conn, ctx.replica_port.as_deref().unwrap());
+        }
+
+        Session {
+            conn,
+            sent_by_main,
+            write_command: Vec::new(),
+            all_multi_commands: Vec::new(),
+            multi_dirty: false,
+            is_exec_mode: false,
+            hold_all_exec_reponse: Vec::new(),
+            pending: None,
+            blocked_on_keys: false,
+        }
+    }
+
+    /// A blocked client has its input left unread until the reply it waits on is sent
+    pub fn is_blocked(&self) -> bool {
+        self.pending.is_some() || self.blocked_on_keys
+    }
+
+    /// Whether the reply has to be polled for, clients blocked on keys are woken up instead
+    pub fn is_polling(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Sends the reply of a client blocked on keys, whether it was served or timed out
+    pub fn unblock(&mut self, reply: RespValue) {
+        if std::mem::take(&mut self.blocked_on_keys) {
+            self.conn.write_value(&reply);
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().and_then(|p| p.deadline)
+    }
+
+    /// Sends the reply the client is blocked on once it is ready
+    pub fn poll_pending(&mut self, now: Instant) {
+        if let Some(reply) = self.pending.as_mut().and_then(|p| p.poll(now)) {
+            self.pending = None;
+            self.conn.write_value(&reply);
+        }
     }
 
-    let mut write_command: Vec<_> = Vec::new();
-
-    let mut all_multi_commands = Vec::new();
-    let mut is_exec_mode = false;
-    let mut hold_all_exec_reponse: Vec<String> = Vec::new();
-    loop {
-        match conn.try_read_command() {
-            Ok(Some(mut commands)) => {
-                eprintln!("ALL COMMANDS:{:?}", commands);
-
-                let mut response_to_write = String::new();
-                let mut exec_present = false;
-                let mut discard_present = false;
-                let mut discard_index: usize = 0;
-                commands.iter().flatten().enumerate().for_each(|(i, s)| {
-                    exec_present = s.to_lowercase() == "exec";
-                    if s.to_uppercase() == "DISCARD" {
-                        discard_index = i;
-                        discard_present = true;
+    /// Runs a command read from the connection, replies are left in its write buffer
+    /// Returns false when the connection should be closed
+    pub fn handle_command(
+        &mut self,
+        command: Vec<Vec<u8>>,
+        ctx: &ServerContext,
+    ) -> Result<bool, Box<dyn Error>> {
+        let ServerContext {
+            dir,
+            db_filename,
+            info_fields,
+            broadcast_info,
+            new_db,
+            channels_db,
+            commands: registry,
+            ..
+        } = ctx;
+        let Session {
+            conn,
+            sent_by_main,
+            write_command,
+            all_multi_commands,
+            multi_dirty,
+            is_exec_mode,
+            hold_all_exec_reponse,
+            pending,
+            blocked_on_keys,
+        } = self;
+        let sent_by_main = *sent_by_main;
+
+        // an empty request is ignored like redis-server does, also while queueing for MULTI
+        if command.is_empty() {
+            return Ok(true);
+        }
+        let mut commands = vec![command];
+
+        if conn.multi_waiting {
+            let name = commands[0].first().cloned().unwrap_or_default();
+            if name.eq_ignore_ascii_case(b"discard") {
+                conn.multi_waiting = false;
+                *multi_dirty = false;
+                all_multi_commands.clear();
+                conn.write_value(&RespValue::ok());
+                return Ok(true);
+            } else if name.eq_ignore_ascii_case(b"exec") {
+                conn.multi_waiting = false;
+                if std::mem::take(multi_dirty) {
+                    all_multi_commands.clear();
+                    conn.write_value(&RespValue::error(EXECABORT_ERROR));
+                    return Ok(true);
+                }
+                all_multi_commands.extend(commands);
+                commands = std::mem::take(all_multi_commands);
+                *is_exec_mode = true;
+                //eprintln!("\n\nEXEC MODE\n\n");
+                //eprintln!("exec commands:{:?}", commands);
+            } else {
+                // a command that could never run fails the whole transaction at EXEC
+                if let Err(e) = registry.lookup_command(&commands[0]) {
+                    *multi_dirty = true;
+                    conn.write_value(&e);
+                    return Ok(true);
+                }
+                all_multi_commands.extend(commands);
+                conn.write_value(&RespValue::simple("QUEUED"));
+                return Ok(true);
+            }
+        }
+
+        for all_lines in commands {
+            if all_lines.is_empty() {
+                continue;
+            }
+            let mut response_to_write = None;
+
+            let spec = match registry.lookup_command(&all_lines) {
+                Ok(spec) => spec,
+                Err(e) => {
+                    // nothing is ever sent back to our master
+                    if !sent_by_main {
+                        conn.write_value(&e);
                     }
-                });
-
-                if conn.multi_waiting && discard_present {
-                    conn.multi_waiting = false;
-                    commands = commands[discard_index + 1..].to_vec();
-                    all_multi_commands = Vec::new();
-                    conn.write_to_stream(RESP_OK.as_bytes());
-                    //DO NOT continue in case some commands read from buffer after discard
-                } else if conn.multi_waiting && exec_present {
-                    all_multi_commands.extend(commands);
-                    commands = all_multi_commands;
-                    all_multi_commands = Vec::new();
-                    is_exec_mode = true;
-                    //eprintln!("\n\nEXEC MODE\n\n");
-                    //eprintln!("exec commands:{:?}", commands);
-                    conn.multi_waiting = false;
-                } else if conn.multi_waiting {
-                    all_multi_commands.extend(commands);
-                    conn.write_to_stream(QUEUED_RESP.as_bytes());
                     continue;
                 }
+            };
+            let cmd = String::from_utf8_lossy(&all_lines[0]).to_lowercase();
+
+            // RESP3 clients may run any command while subscribed since messages arrive as push frames
+            if conn.in_sub_mode
+                && conn.protocol == Protocol::Resp2
+                && !ALLOWED_SUB_COMMANDS
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(&cmd))
+            {
+                eprintln!("IN SUB MODE IGNORING COMMAND:{:?}", all_lines);
+                let use_err = format!("ERR Can't execute '{cmd}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context");
+                conn.write_value(&RespValue::Error(use_err));
+                continue;
+            }
+            //eprintln!("handling command:{cmd}");
+            match spec.name {
+                "command" => {
+                    //eprintln!("INITIATION, no command");
+                    response_to_write = Some(handle_command_cmd(registry, &all_lines[1..]));
+                }
+                "ping" => {
+                    if conn.in_sub_mode && conn.protocol == Protocol::Resp2 {
+                        response_to_write = Some(RespValue::bulk_array(["pong", ""]));
+                    } else if !sent_by_main {
+                        response_to_write = Some(RespValue::simple("PONG"));
+                    }
+                }
+                "hello" => {
+                    let role = info_fields.get(ROLE).unwrap();
+                    response_to_write = Some(handle_hello(conn, &all_lines[1..], role));
+                }
+
+                "echo" => {
+                    // a bulk string rather than a simple one so any bytes can be echoed
+                    response_to_write = Some(RespValue::bulk(&all_lines[1]));
+                }
 
-                for all_lines in commands {
-                    if all_lines.is_empty() {
-                        continue;
+                "set" => {
+                    //eprintln!("IN handle client SET,");
+                    //eprintln!("sent by MAIN:{sent_by_main}");
+
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_set(lk, &all_lines)
+                    });
+                    if !sent_by_main {
+                        //eprintln!("after set writing ok to stream, curr db:{:?}", new_db);
+                        response_to_write = Some(res);
                     }
+                }
 
-                    let cmd = &all_lines[0];
+                "setnx" | "setex" | "psetex" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_set_variant(lk, &all_lines)
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                    if conn.in_sub_mode && !ALLOWED_SUB_COMMANDS.contains(&cmd.as_str()) {
-                        eprintln!("IN SUB MODE IGNORING COMMAND:{:?}", all_lines);
-                        let use_err = format!("-ERR Can't execute '{cmd}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n");
-                        conn.write_to_stream(use_err.as_bytes());
-                        continue;
+                "getset" => {
+                    let res =
+                        run_write(ctx, write_command, &all_lines, |lk| match &all_lines[1..] {
+                            [key, value] => handle_getset(lk, key, value),
+                            _ => wrong_args_error("getset"),
+                        });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
                     }
-                    //eprintln!("handling command:{cmd}");
-                    match cmd.to_lowercase().as_str() {
-                        "command" => {
-                            //eprintln!("INITIATION, no command");
-                            return Ok(());
-                        }
-                        "ping" => {
-                            if conn.in_sub_mode {
-                                response_to_write = conn.format_resp_array(&["pong", ""]);
-                            } else if !sent_by_main {
-                                response_to_write = PONG_RESPONSE.to_string();
-                            }
-                        }
-                        "echo" => {
-                            //let resp = [b"+", all_lines[1].as_bytes(), b"\r\n"].concat();
-                            let resp = format!("+{}\r\n", all_lines[1]);
-                            response_to_write = resp.to_string()
-                        }
+                }
 
-                        "set" => {
-                            write_command = all_lines.clone();
-                            //eprintln!("after setting write commands:{:?}", write_command);
-                            //eprintln!("IN handle client SET,");
-                            //eprintln!("sent by MAIN:{sent_by_main}");
+                "getdel" => {
+                    let res =
+                        run_write(ctx, write_command, &all_lines, |lk| match &all_lines[1..] {
+                            [key] => handle_getdel(lk, key),
+                            _ => wrong_args_error("getdel"),
+                        });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            if all_lines.len() < 3 {
-                                response_to_write = RESP_NULL.to_string();
-                                continue;
-                            }
+                "getex" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_getex(lk, &all_lines)
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            if info_fields.get(ROLE).is_some_and(|k| k == MASTER) {
-                                //eprintln!(
-                                //    "Master starting PROPAGATION with info, {:?}",
-                                //    broadcast_info
-                                //);
-                                {
-                                    let mut lk = broadcast_info.lock().unwrap();
-                                    lk.broadcast_command(&all_lines);
-                                }
-                            }
+                /*
+                 * GET SECTION
+                 * */
+                "get" => {
+                    //eprintln!("IN handle client GET, db:{:?}", new_db);
+                    let get_key = &all_lines[1];
+                    {
+                        ////eprintln!("in handle GET function before lock");
+                        let mut lk = new_db.lock().expect("failed to lock db in get");
+                        // expired keys are dropped by the keyspace lookup
+                        response_to_write = Some(match lk.get_string(get_key) {
+                            Ok(Some(value)) => RespValue::bulk(value),
+                            Ok(None) => RespValue::Null,
+                            Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+                        });
+                    }
+                }
 
-                            let k = all_lines[1].clone();
-                            let v = all_lines[2].clone();
+                "strlen" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(match &all_lines[1..] {
+                        [key] => handle_strlen(&mut lk, key),
+                        _ => wrong_args_error("strlen"),
+                    });
+                }
 
-                            let mut use_time = None;
-                            if all_lines.len() > 4 {
-                                use_time = Some((all_lines[3].as_str(), all_lines[4].as_str()));
-                            }
-                            let r = handle_set(k, v, &new_db, use_time);
-                            if r.is_ok() && !sent_by_main {
-                                //eprintln!("after set writing ok to stream, curr db:{:?}", new_db);
-                                response_to_write = RESP_OK.to_string()
-                            }
-                        }
+                "getrange" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(match &all_lines[1..] {
+                        [key, start, end] => handle_getrange(&mut lk, key, start, end),
+                        _ => wrong_args_error("getrange"),
+                    });
+                }
 
-                        /*
-                         * GET SECTION
-                         * */
-                        "get" => {
-                            if all_lines.len() < 2 {
-                                response_to_write = RESP_NULL.to_string();
-                                continue;
-                            }
-                            //eprintln!("IN handle client GET, db:{:?}", new_db);
-                            let get_key = &all_lines[1];
-                            {
-                                ////eprintln!("in handle GET function before lock");
-                                let mut lk = new_db.lock().expect("failed to lock db in get");
-                                ////eprintln!("in handle GET function locked db:{:?}", lk);
-                                if let Some(res) = lk.get(&get_key) {
-                                    if res.expires_at.is_some()
-                                        && res.expires_at.as_ref().unwrap().is_expired()
-                                    {
-                                        ////eprintln!("ASKING FOR EXPIRED!!?? key: {get_key}");
-                                        lk.data.remove(get_key);
-                                        response_to_write = RESP_NULL.to_string();
-                                    } else {
-                                        let resp = get_bulk_string(&res.value);
-                                        response_to_write = resp;
-                                    }
-                                } else {
-                                    eprintln!("IN GET FOUND NONE");
-                                    response_to_write = RESP_NULL.to_string();
-                                }
-                            }
-                        }
+                "mget" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(match &all_lines[1..] {
+                        [] => wrong_args_error("mget"),
+                        keys => handle_mget(&mut lk, keys),
+                    });
+                }
 
-                        /*
-                         *CONFIG
-                         * */
-                        "config" => {
-                            let config_command = all_lines[1].to_lowercase();
-                            let config_field = all_lines[2].to_lowercase();
-                            let dir = dir.clone();
-                            let db_filename = db_filename.clone();
-                            match config_command.as_str() {
-                                "get" => match config_field.as_str() {
-                                    "dir" => {
-                                        if let Some(dir_name) = &dir {
-                                            response_to_write =
-                                                conn.format_resp_array(&[&config_field, dir_name])
-                                        } else {
-                                            response_to_write = RESP_NULL.to_string();
-                                        }
-                                    }
-                                    "dbfilename" => {
-                                        if let Some(db_name) = &db_filename {
-                                            response_to_write =
-                                                conn.format_resp_array(&[&config_field, &db_name])
-                                        } else {
-                                            response_to_write = RESP_NULL.to_string();
-                                        }
-                                    }
-                                    _ => {
-                                        eprintln!("UNRECOGNIZED GET CONFIG FIELD");
-                                    }
-                                },
-                                _ => {
-                                    eprintln!("UNRECOGNIZED CONFIG COMMAND")
-                                }
-                            }
-                        }
+                "append" => {
+                    let res =
+                        run_write(ctx, write_command, &all_lines, |lk| match &all_lines[1..] {
+                            [key, value] => handle_append(lk, key, value),
+                            _ => wrong_args_error("append"),
+                        });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                        //KEYS
-                        "keys" => {
-                            let path: PathBuf;
-                            if db_filename.is_some() && dir.is_some() {
-                                //eprintln!("FOUND FILE");
-                                let file = db_filename.as_ref().unwrap();
-
-                                let directory = dir.as_ref().unwrap();
-                                //eprintln!("FOUND DIR");
-                                // write current hashmap to rdb
-                                path = Path::new(directory).join(file);
-                            } else {
-                                path = env::current_dir().unwrap().join("dump.rdb");
-                            }
+                "setrange" => {
+                    let res =
+                        run_write(ctx, write_command, &all_lines, |lk| match &all_lines[1..] {
+                            [key, offset, value] => handle_setrange(lk, key, offset, value),
+                            _ => wrong_args_error("setrange"),
+                        });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            //eprintln!("USING PATH:{:?}", &path);
-
-                            let mut file = File::open(&path)?;
-                            let mut buffer = Vec::new();
-                            file.read_to_end(&mut buffer)?;
-
-                            // //eprintln!("Printin rdb as HEX");
-                            // print_hex::print_hex_dump(&buffer);
-                            match read_rdb_file(path) {
-                                Ok(rdb) => {
-                                    let ret_keys = read_rdb_keys(rdb, all_lines[1].clone());
-
-                                    //EXAMPLE: *1\r\n$3\r\nfoo\r\n
-                                    response_to_write = conn.format_resp_array(
-                                        ret_keys
-                                            .iter()
-                                            .map(|e| e.as_str())
-                                            .collect::<Vec<&str>>()
-                                            .as_slice(),
-                                    )
-                                }
-                                Err(e) => {
-                                    eprintln!("failed to read from rdb file {:?}", e);
-                                    response_to_write = RESP_NULL.to_string();
-                                }
-                            }
+                "mset" | "msetnx" => {
+                    let lower_cmd = cmd.to_lowercase();
+                    let pairs = &all_lines[1..];
+                    // one lock for every key so other clients never see a partial write
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        if pairs.is_empty() || pairs.len() % 2 != 0 {
+                            wrong_args_error(&lower_cmd)
+                        } else {
+                            handle_mset(lk, pairs, lower_cmd == "msetnx")
                         }
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                        //SAVE
-                        "save" => {
-                            //eprintln!("IN SAVE");
-                            let mut path: PathBuf;
-                            if db_filename.is_some() && dir.is_some() {
-                                // create a new file path then write current hashmap to rdb
-                                path = Path::new(dir.as_ref().unwrap())
-                                    .join(db_filename.as_ref().unwrap());
-
-                                //eprintln!("Path:{:?}", &path);
-                                let mut new_rdb = RdbFile {
-                                    version: "0011".to_string(),
-                                    metadata: HashMap::new(),
-                                    databases: HashMap::new(),
-                                };
+                /*
+                 * GENERIC KEY COMMANDS
+                 * */
+                "del" | "unlink" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        let num_removed = all_lines[1..]
+                            .iter()
+                            .filter(|k| lk.remove(k).is_some())
+                            .count();
+                        RespValue::Integer(num_removed as i64)
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                                new_rdb
-                                    .metadata
-                                    .insert("redis-version".to_string(), "6.0.16".to_string());
-                                //eprintln!("IN Save, using map {:?}", new_db);
-                                {
-                                    let lk = new_db.lock().expect("failed to lock db in save");
-                                    new_rdb.databases.insert(0, lk.clone().try_into()?);
-                                    //eprintln!("Creating a new rdb with {:?}", new_rdb);
-                                }
-                                let _ = write_rdb_file(path, &new_rdb);
+                // a key repeated in the arguments is counted each time
+                "exists" | "touch" => {
+                    let mut lk = new_db.lock().unwrap();
+                    let num_found = all_lines[1..].iter().filter(|k| lk.contains_key(k)).count();
+                    response_to_write = Some(RespValue::Integer(num_found as i64));
+                }
 
-                                //eprintln!("after SAVE writing to file");
-                                response_to_write = RESP_OK.to_string();
-                            } else {
-                                //eprintln!("Creating DUMMY in curr dir");
-                                path = env::current_dir().unwrap();
-                                path.push("dump.rdb");
-                                //print_hex::create_dummy_rdb(&path)?;
-                                response_to_write = RESP_OK.to_string();
-                                // no need for data as it already mocked
-                            }
+                "rename" | "renamenx" => {
+                    let only_if_new = cmd.eq_ignore_ascii_case("renamenx");
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_rename(lk, &all_lines[1], &all_lines[2], only_if_new)
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
+
+                "copy" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_copy(lk, &all_lines[1..])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
+
+                /*
+                 * KEY EXPIRY
+                 * */
+                "expire" | "pexpire" | "expireat" | "pexpireat" => {
+                    let lower_cmd = cmd.to_lowercase();
+                    let unit_ms = if lower_cmd.starts_with('p') { 1 } else { 1000 };
+                    let absolute = lower_cmd.ends_with("at");
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_expire(lk, &all_lines, unit_ms, absolute)
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
+
+                "ttl" | "pttl" | "expiretime" | "pexpiretime" => {
+                    let lower_cmd = cmd.to_lowercase();
+                    let in_ms = lower_cmd.starts_with('p');
+                    let absolute = lower_cmd.ends_with("expiretime");
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_ttl(&mut lk, &all_lines[1], in_ms, absolute));
+                }
+
+                "persist" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        let had_expiry = lk
+                            .get(&all_lines[1])
+                            .is_some_and(|v| v.expires_at.is_some());
+                        if had_expiry {
+                            lk.set_expiry(&all_lines[1], None);
                         }
+                        RespValue::Integer(had_expiry as i64)
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
+
+                /*
+                 *CONFIG
+                 * */
+                "client" => {
+                    let res = handle_client(conn, new_db, &all_lines[1..]);
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                        //INFO
-                        "info" => {
-                            //if there is an extra key arg/"se"
-                            if all_lines.len() > 2 {
-                                let info_key = &all_lines[1];
-                                let mut use_resp = String::new();
-                                match info_key.to_lowercase().as_str() {
-                                    ROLE => {
-                                        use_resp.push_str("role:");
-                                        use_resp.push_str(info_fields.get(ROLE).unwrap());
-                                    }
-                                    "replication" => {
-                                        let fields = [ROLE, MASTER_REPL_ID, MASTER_REPL_OFFSET];
-                                        fields.iter().for_each(|elem| {
-                                            use_resp.push_str(elem);
-                                            use_resp.push_str(info_fields.get(*elem).unwrap());
-                                        });
-                                    }
-                                    _ => {}
+                "config" => {
+                    let config_command = arg_str(&all_lines[1]).to_lowercase();
+                    let dir = dir.clone();
+                    let db_filename = db_filename.clone();
+                    match config_command.as_str() {
+                        "get" if all_lines.len() != 3 => {
+                            response_to_write = Some(wrong_args_error("config|get"));
+                        }
+                        "get" => {
+                            let config_field = arg_str(&all_lines[2]).to_lowercase();
+                            let value = match config_field.as_str() {
+                                "dir" => dir.as_ref(),
+                                "dbfilename" => db_filename.as_ref(),
+                                _ => {
+                                    eprintln!("UNRECOGNIZED GET CONFIG FIELD");
+                                    None
                                 }
-                                //eprintln!("INFO RESPONSE:{:?}", use_resp);
-                                response_to_write = get_bulk_string(&use_resp);
-                            } else {
-                                //eprintln!("IN INFO ELSE");
-                                let mut use_val = String::new();
-
-                                info_fields
-                                    .iter()
-                                    .for_each(|(k, v)| use_val.extend([k, ":", v, "\r\n"]));
-                                // remove the last CRLF
-                                let info_res = get_bulk_string(&use_val[..use_val.len() - 2]);
-                                //eprintln!("RESPONSE:{:?}", &info_res);
-                                response_to_write = info_res;
-                            }
-                            //eprintln!("AFTER INFO SECTION");
+                            };
+                            // a name to value map, unknown or unset parameters are left out
+                            let pairs =
+                                value.map(|v| (RespValue::bulk(&config_field), RespValue::bulk(v)));
+                            response_to_write = Some(RespValue::Map(pairs.into_iter().collect()));
+                        }
+                        _ => {
+                            eprintln!("UNRECOGNIZED CONFIG COMMAND");
+                            response_to_write = Some(RespValue::Error(format!(
+                                "ERR unknown subcommand '{}'. Try CONFIG HELP.",
+                                printable_arg(&all_lines[1])
+                            )));
                         }
+                    }
+                }
 
-                        //REPL
-                        "replconf" => {
-                            //eprintln!("HANDLING REPL CONF");
-                            match all_lines[1].as_str() {
-                                GETACK => {
-                                    eprintln!("in get ack offset before - 37 is:{},", conn.offset);
-                                    let curr_offset = conn.offset - 37;
-                                    response_to_write = conn.format_resp_array(&[
-                                        REPL_CONF,
-                                        ACK,
-                                        curr_offset.to_string().as_str(),
-                                    ]);
-                                }
+                //KEYS
+                "keys" => {
+                    let ret_keys = new_db.lock().unwrap().keys(&all_lines[1]);
 
-                                LISTENING_PORT => {
-                                    {
-                                        let mut lk = broadcast_info.lock().unwrap();
-                                        lk.ports.push(all_lines[2].clone());
-                                    }
-                                    //eprintln!("after repl pushing ports:{:?}", broadcast_info);
-                                    response_to_write = RESP_OK.to_string();
-                                }
+                    //EXAMPLE: *1\r\n$3\r\nfoo\r\n
+                    response_to_write = Some(RespValue::bulk_array(ret_keys));
+                }
 
-                                ACK => {
-                                    let mut lk = broadcast_info.lock().unwrap();
-                                    //eprintln!("got command in ACK, num waiting for:{}, wiaiting until:{:?}",lk.num_waiting_for, waiting_until);
-                                    if lk.num_waiting_for > 0 {
-                                        //eprintln!("ADD TO ACK");
-                                        lk.num_acks += 1;
-                                    }
-                                }
+                //SAVE
+                "save" => {
+                    //eprintln!("IN SAVE");
+                    let mut path: PathBuf;
+                    if let (Some(dir), Some(db_filename)) = (&dir, &db_filename) {
+                        // create a new file path then write current hashmap to rdb
+                        path = Path::new(dir).join(db_filename);
+
+                        //eprintln!("Path:{:?}", &path);
+                        let mut new_rdb = RdbFile {
+                            version: "0011".to_string(),
+                            metadata: HashMap::new(),
+                            databases: HashMap::new(),
+                        };
+
+                        new_rdb
+                            .metadata
+                            .insert("redis-version".to_string(), "6.0.16".to_string());
+                        //eprintln!("IN Save, using map {:?}", new_db);
+                        {
+                            let lk = new_db.lock().expect("failed to lock db in save");
+                            new_rdb.databases.insert(0, lk.snapshot());
+                            //eprintln!("Creating a new rdb with {:?}", new_rdb);
+                        }
+                        let _ = write_rdb_file(path, &new_rdb);
+
+                        //eprintln!("after SAVE writing to file");
+                        response_to_write = Some(RespValue::ok());
+                    } else {
+                        //eprintln!("Creating DUMMY in curr dir");
+                        path = env::current_dir().unwrap();
+                        path.push("dump.rdb");
+                        //print_hex::create_dummy_rdb(&path)?;
+                        response_to_write = Some(RespValue::ok());
+                        // no need for data as it already mocked
+                    }
+                }
 
-                                _ => {
-                                    response_to_write = RESP_OK.to_string();
-                                    //eprintln!("WROTE ok to other replconf");
-                                }
+                //INFO
+                "info" => {
+                    //if there is an extra key arg/"se"
+                    if all_lines.len() > 2 {
+                        let info_key = &all_lines[1];
+                        let mut use_resp = String::new();
+                        match arg_str(info_key).to_lowercase().as_str() {
+                            ROLE => {
+                                use_resp.push_str("role:");
+                                use_resp.push_str(info_fields.get(ROLE).unwrap());
+                            }
+                            "replication" => {
+                                let fields = [ROLE, MASTER_REPL_ID, MASTER_REPL_OFFSET];
+                                fields.iter().for_each(|elem| {
+                                    use_resp.push_str(elem);
+                                    use_resp.push_str(info_fields.get(*elem).unwrap());
+                                });
                             }
+                            _ => {}
                         }
+                        //eprintln!("INFO RESPONSE:{:?}", use_resp);
+                        response_to_write = Some(RespValue::bulk(&use_resp));
+                    } else {
+                        //eprintln!("IN INFO ELSE");
+                        let mut use_val = String::new();
+
+                        info_fields
+                            .iter()
+                            .for_each(|(k, v)| use_val.extend([k, ":", v, "\r\n"]));
+                        // remove the last CRLF
+                        let info_res = RespValue::bulk(&use_val[..use_val.len() - 2]);
+                        //eprintln!("RESPONSE:{:?}", &info_res);
+                        response_to_write = Some(info_res);
+                    }
+                    //eprintln!("AFTER INFO SECTION");
+                }
 
-                        //PSYNC
-                        "psync" => {
-                            let resync_response = [
-                                b"+",
-                                FULLRESYNC.as_bytes(),
-                                b" ",
-                                info_fields.get(MASTER_REPL_ID).unwrap().as_bytes(),
-                                b" ",
-                                info_fields.get(MASTER_REPL_OFFSET).unwrap().as_bytes(),
-                                b"\r\n",
-                            ]
-                            .concat();
+                //REPL
+                "replconf" => {
+                    //eprintln!("HANDLING REPL CONF");
+                    match all_lines.get(1).map_or("", |a| arg_str(a)) {
+                        GETACK => {
+                            eprintln!("in get ack offset before - 37 is:{},", conn.offset);
+                            let curr_offset = conn.offset.saturating_sub(37);
+                            response_to_write = Some(RespValue::bulk_array([
+                                REPL_CONF,
+                                ACK,
+                                curr_offset.to_string().as_str(),
+                            ]));
+                        }
 
+                        LISTENING_PORT => {
                             {
                                 let mut lk = broadcast_info.lock().unwrap();
-                                let replica_stream =
-                                    RedisConnection::new(stream.try_clone().unwrap());
-                                //master_stream.is_master = true;
-                                lk.connections.push(replica_stream);
-                                let n = lk.connections.len();
-                                let s = &mut lk.connections[n - 1];
-                                //stream.write_all(&resync_response)?;
-                                eprintln!("\n\n\n MASTER GOT HANDSHAKE??\n\n\n");
-                                //eprint!("\n\n\nSENDING RESYNC RESPONSE USING SAVED STREAM\n\n");
-                                s.write_to_stream(&resync_response);
-
-                                //eprint!("\n\n\nSENDING RDB USING SAVED STREAM:{:?}\n\n", s);
-                                let dummy_rdb_path = env::current_dir().unwrap().join("empty.rdb");
-                                // let dummy_rdb_path =
-                                //     env::current_dir().unwrap().join("dump_dummy.rdb");
-                                // create_dummy_rdb(&dummy_rdb_path.as_path())
-                                //     .expect("FAILED TO MAKE DUMMY RDB");
-                                if let Ok(response_rdb_bytes) = fs::read(dummy_rdb_path) {
-                                    //eprintln!("IN MASTER SENDING RDB");
-                                    //eprintln!("writing rdb len {}", response_rdb_bytes.len());
-                                    //print_hex_dump(&response_rdb_bytes);
-
-                                    s.write_to_stream(
-                                        &[
-                                            b"$",
-                                            response_rdb_bytes.len().to_string().as_bytes(),
-                                            b"\r\n",
-                                        ]
-                                        .concat(),
-                                    );
-
-                                    s.write_to_stream(&response_rdb_bytes)
+                                if let Some(port) = all_lines.get(2) {
+                                    lk.ports.push(arg_str(port).to_string());
                                 }
-                            };
-                            //eprintln!(
-                            //    "AFTER FULL RESYNC adding connection to broadcast info, new {:?}",
-                            //    broadcast_info
-                            //);
+                            }
+                            //eprintln!("after repl pushing ports:{:?}", broadcast_info);
+                            response_to_write = Some(RespValue::ok());
                         }
 
-                        "wait" => {
-                            let mut all_repls = Vec::new();
-                            let num_required = all_lines[1].parse::<usize>().unwrap();
-                            let wait_for_ms =
-                                Duration::from_millis(all_lines[2].parse::<u64>().unwrap());
-                            {
-                                let mut lk = broadcast_info.lock().unwrap();
-                                lk.num_waiting_for = num_required;
-                                lk.waiting_until = SystemTime::now() + wait_for_ms; //eprintln!("setting num waiting for:{}", lk.num_waiting_for);
-                                lk.connections.iter().enumerate().for_each(|(i, c)| {
-                                    all_repls
-                                        .push((c.stream.try_clone().unwrap(), lk.ports[i].clone()))
-                                });
+                        ACK => {
+                            let mut lk = broadcast_info.lock().unwrap();
+                            //eprintln!("got command in ACK, num waiting for:{}, wiaiting until:{:?}",lk.num_waiting_for, waiting_until);
+                            if lk.num_waiting_for > 0 {
+                                //eprintln!("ADD TO ACK");
+                                lk.num_acks += 1;
                             }
+                        }
+
+                        _ => {
+                            response_to_write = Some(RespValue::ok());
+                            //eprintln!("WROTE ok to other replconf");
+                        }
+                    }
+                }
+
+                //PSYNC
+                "psync" => {
+                    let resync_response = RespValue::SimpleString(format!(
+                        "{FULLRESYNC} {} {}",
+                        info_fields.get(MASTER_REPL_ID).unwrap(),
+                        info_fields.get(MASTER_REPL_OFFSET).unwrap()
+                    ));
+
+                    eprintln!("\n\n\n MASTER GOT HANDSHAKE??\n\n\n");
+                    conn.write_value(&resync_response);
+                    let dummy_rdb_path = env::current_dir().unwrap().join("empty.rdb");
+                    // let dummy_rdb_path =
+                    //     env::current_dir().unwrap().join("dump_dummy.rdb");
+                    // create_dummy_rdb(&dummy_rdb_path.as_path())
+                    //     .expect("FAILED TO MAKE DUMMY RDB");
+                    if let Ok(response_rdb_bytes) = fs::read(dummy_rdb_path) {
+                        //eprintln!("writing rdb len {}", response_rdb_bytes.len());
+                        //print_hex_dump(&response_rdb_bytes);
+                        conn.write_raw(
+                            &[
+                                b"$",
+                                response_rdb_bytes.len().to_string().as_bytes(),
+                                b"\r\n",
+                            ]
+                            .concat(),
+                        );
+                        conn.write_raw(&response_rdb_bytes);
+                    }
+                    // from now on this connection is the replica link, propagated commands queue behind the RDB
+                    broadcast_info.lock().unwrap().add_replica(conn.id);
+                }
 
-                            let num_repls = all_repls.len();
-                            let ack_req = conn.format_resp_array(&[REPL_CONF, GETACK, "*"]);
+                "wait" => {
+                    let parsed = (
+                        arg_str(&all_lines[1]).parse::<usize>(),
+                        arg_str(&all_lines[2]).parse::<u64>(),
+                    );
+                    match parsed {
+                        (Ok(_), Ok(wait_ms))
+                            if check_timeout(Duration::from_millis(wait_ms)).is_err() =>
+                        {
+                            response_to_write = Some(RespValue::error(TIMEOUT_OUT_OF_RANGE_ERROR));
+                        }
+                        (Ok(num_required), Ok(wait_ms)) => {
+                            let wait_for_ms = Duration::from_millis(wait_ms);
+                            let mut lk = broadcast_info.lock().unwrap();
+                            lk.num_waiting_for = num_required;
+                            lk.waiting_until = SystemTime::now() + wait_for_ms; //eprintln!("setting num waiting for:{}", lk.num_waiting_for);
+                            let num_repls = lk.replicas.len();
 
                             //eprintln!(
                             //    "checking n={} replicas, waiting for{:?}",
                             //    num_repls, wait_for_ms
                             //);
 
-                            let mut acq_threads = Vec::new();
                             if !write_command.is_empty() {
-                                for replica in &mut all_repls {
-                                    let mut repl_stream = replica.0.try_clone().unwrap();
-                                    let arq = ack_req.clone();
-                                    let res = thread::spawn(move || {
-                                        repl_stream.write_all(&arq.as_bytes()).unwrap();
-                                    });
-                                    acq_threads.push(res);
-                                }
-                                acq_threads.into_iter().for_each(|e| {
-                                    let _ = e.join();
-                                });
+                                // queued behind the writes already propagated, so the ACKs cover them
+                                let ack_req =
+                                    [REPL_CONF, GETACK, "*"].map(|a| a.as_bytes().to_vec());
+                                lk.broadcast_command(&ack_req);
+                                drop(lk);
+
+                                // the ACKs come in on the replica connections, served by the same event loop
+                                let b_info = Arc::clone(broadcast_info);
+                                let deadline = Instant::now() + wait_for_ms;
+                                *pending =
+                                    Some(PendingReply::new(Some(deadline), move |timed_out| {
+                                        let mut lk = b_info.lock().unwrap();
+                                        if !timed_out && lk.num_acks < lk.num_waiting_for {
+                                            return None;
+                                        }
+                                        //eprintln!("\n\nRESPONDING WITH {} ACKS\n\n", lk.num_acks);
+                                        let reply = RespValue::Integer(lk.num_acks as i64);
+                                        lk.num_acks = 0;
+                                        lk.num_waiting_for = 0;
+                                        Some(reply)
+                                    }));
+                            } else {
+                                response_to_write = Some(RespValue::Integer(num_repls as i64));
+                            }
+                        }
+                        _ => {
+                            response_to_write = Some(RespValue::error(NOT_INT_ERROR));
+                        }
+                    }
+                }
 
-                                sleep(Duration::from(wait_for_ms));
-                                let mut lk = broadcast_info.lock().unwrap();
-                                if lk.num_waiting_for > 0
-                                    && (SystemTime::now() > lk.waiting_until
-                                        || lk.num_acks == lk.num_waiting_for)
-                                {
-                                    //eprintln!("\n\nRESPONDING WITH {} ACKS\n\n", lk.num_acks);
-                                    // conn.write_to_stream(
-                                    //     &[
-                                    //         ":".as_bytes(),
-                                    //         lk.num_acks.to_string().as_bytes(),
-                                    //         "\r\n".as_bytes(),
-                                    //     ]
-                                    //     .concat(),
-                                    // );
-
-                                    response_to_write = get_redis_int(lk.num_acks as i32);
-                                    lk.num_acks = 0;
-                                    lk.num_waiting_for = 0;
-                                }
-                                std::mem::drop(lk);
+                "type" => {
+                    let key = &all_lines[1];
+                    //eprintln!(
+                    //    "key:{key}, currentdb:{:?}, current entry_strem{:?}",
+                    //    new_db, entry_streams
+                    //);
+
+                    response_to_write = Some(match new_db.lock().unwrap().type_of(key) {
+                        Some(type_name) => RespValue::simple(type_name),
+                        None => RespValue::simple("none"),
+                    });
+                }
 
-                                //eprintln!("after threads");
-                            } else {
-                                response_to_write = format!(":{}\r\n", num_repls);
+                "xadd" => {
+                    let stream_name = all_lines[1].clone();
+                    let stream_id = arg_str(&all_lines[2]).to_string();
+                    //let (k, v) = (all_lines[3].clone(), all_lines[4].clone());
+                    //eprintln!("handling x_add with key:{stream_name}, id:{stream_id}, k:{k}, v:{v}");
+
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_xadd(
+                        &mut lk,
+                        &stream_name,
+                        &stream_id,
+                        &all_lines[3..],
+                    ));
+                }
+
+                "xrange" => {
+                    let stream_name = all_lines[1].clone();
+                    let start = arg_str(&all_lines[2]).to_string();
+                    let end = arg_str(&all_lines[3]).to_string();
+                    //eprintln!(
+                    //    "handling XRANGE with key:{stream_name}, start:{start}, end:{end}"
+                    //);
+                    {
+                        let mut lk = new_db.lock().unwrap();
+                        response_to_write = Some(match lk.get_stream(&stream_name) {
+                            Ok(Some(curr_stream)) => curr_stream.get_from_range(&start, &end),
+                            Ok(None) => RespValue::Null,
+                            Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+                        });
+                    }
+                }
+
+                "xread" => {
+                    let block = all_lines[1].eq_ignore_ascii_case(b"block");
+                    let block_start_time = SystemTime::now();
+                    // TODO: check for invalid times
+                    let mut time_to_block_for: Duration = Duration::from_millis(0);
+                    let all_streams;
+
+                    let time_str = arg_str(&all_lines[2]).parse::<u64>();
+                    if block && time_str.is_err() {
+                        response_to_write = Some(RespValue::error(TIMEOUT_NOT_INT_ERROR));
+                    } else if block
+                        && time_str
+                            .as_ref()
+                            .is_ok_and(|ms| check_timeout(Duration::from_millis(*ms)).is_err())
+                    {
+                        response_to_write = Some(RespValue::error(TIMEOUT_OUT_OF_RANGE_ERROR));
+                    } else {
+                        let mut full_block = false;
+                        if block {
+                            //TODO: IF TIME STRING IS 0 add a blcoked stream conn clone to
+                            //waiting streams
+
+                            eprintln!("\n\n\nGOT BLOCK with time{}\n\n\n", arg_str(&all_lines[2]));
+                            let actual_time = time_str.as_ref().unwrap();
+                            full_block = actual_time == &0;
+                            time_to_block_for = Duration::from_millis(*time_str.as_ref().unwrap());
+                            if full_block {
+                                eprintln!("\n\nFULL BLOCK\n\n");
                             }
+                            all_streams = get_all_stream_names(&all_lines[4..]);
+                        } else {
+                            all_streams = get_all_stream_names(&all_lines[2..]);
                         }
 
-                        "type" => {
-                            let key = &all_lines[1];
-                            //eprintln!(
-                            //    "key:{key}, currentdb:{:?}, current entry_strem{:?}",
-                            //    new_db, entry_streams
-                            //);
+                        if block {
+                            if full_block {
+                                eprintln!("\n\n\nADDING FULL BLOCK\n\n\n");
+                                // BLOCK 0 takes entries added any time after the block started
+                                time_to_block_for = Duration::from_secs(u32::MAX as u64);
+                            } else {
+                                eprintln!("\n\n\nNOT full block\n\n\n");
+                            }
+                            let deadline =
+                                (!full_block).then(|| Instant::now() + time_to_block_for);
+                            let window = Some((block_start_time, time_to_block_for));
 
-                            if new_db.lock().unwrap().get(key).is_some() {
-                                response_to_write = STRING.to_string();
-                            } else if entry_streams.lock().unwrap().get(key).is_some() {
-                                response_to_write = conn.get_simple_str("stream");
+                            let mut lk = new_db.lock().unwrap();
+                            let res = handle_xread(&mut lk, &all_streams, window);
+                            // nothing blocks inside MULTI
+                            if res != RespValue::Null || *is_exec_mode {
+                                response_to_write = Some(res);
                             } else {
-                                response_to_write = NONE_TYPE.to_string();
+                                let keys: Vec<Vec<u8>> =
+                                    all_streams.iter().map(|(name, _)| name.clone()).collect();
+                                let serve = move |db: &mut RedisDatabase, _: &[u8]| {
+                                    let res = handle_xread(db, &all_streams, window);
+                                    (res != RespValue::Null).then_some(Served {
+                                        reply: res,
+                                        propagate: None,
+                                    })
+                                };
+                                lk.blocked.block(
+                                    conn.id,
+                                    &keys,
+                                    deadline,
+                                    RespValue::Null,
+                                    Box::new(serve),
+                                );
+                                *blocked_on_keys = true;
                             }
+                        } else {
+                            let mut lk = new_db.lock().unwrap();
+                            response_to_write = Some(handle_xread(&mut lk, &all_streams, None));
                         }
+                    }
+                }
 
-                        "xadd" => {
-                            let stream_name = all_lines[1].clone();
-                            let stream_id = all_lines[2].clone();
-                            //let (k, v) = (all_lines[3].clone(), all_lines[4].clone());
-                            //eprintln!("handling x_add with key:{stream_name}, id:{stream_id}, k:{k}, v:{v}");
-
-                            let mut lk = entry_streams.lock().unwrap();
-                            let curr_stream =
-                                lk.entry(stream_name).or_insert(RedisEntryStream::new());
-
-                            let mut use_vec = Vec::new();
-                            // map key values to each other in a tuple
-                            for i in 3..all_lines.len() {
-                                if i % 2 == 0 {
-                                    continue;
-                                }
-                                use_vec.push((all_lines[i].clone(), all_lines[i + 1].clone()))
-                            }
+                "incr" | "decr" | "incrby" | "decrby" => {
+                    let lower_cmd = cmd.to_lowercase();
+                    let by = |delta: &[u8]| {
+                        parse_redis_int(delta).ok_or(RespValue::error(NOT_INT_ERROR))
+                    };
+                    let delta = match (lower_cmd.as_str(), &all_lines[1..]) {
+                        ("incr", [_]) => Ok(1),
+                        ("decr", [_]) => Ok(-1),
+                        ("incrby", [_, delta]) => by(delta),
+                        ("decrby", [_, delta]) => by(delta)
+                            .and_then(|d| d.checked_neg().ok_or(RespValue::error(OVERFLOW_ERROR))),
+                        _ => Err(wrong_args_error(&lower_cmd)),
+                    };
+                    let res = match delta {
+                        Ok(delta) => run_write(ctx, write_command, &all_lines, |lk| {
+                            handle_incr_by(lk, &all_lines[1], delta)
+                        }),
+                        Err(e) => e,
+                    };
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            let res = curr_stream.handle_add(&stream_id.as_str(), use_vec);
+                "incrbyfloat" => {
+                    let res =
+                        run_write(ctx, write_command, &all_lines, |lk| match &all_lines[1..] {
+                            [key, increment] => handle_incr_by_float(lk, key, increment),
+                            _ => wrong_args_error("incrbyfloat"),
+                        });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            response_to_write = res;
-                        }
+                "multi" => {
+                    conn.multi_waiting = true;
+                    conn.write_value(&RespValue::ok());
+                    continue;
+                }
 
-                        "xrange" => {
-                            let stream_name = all_lines[1].clone();
-                            let start = all_lines[2].clone();
-                            let end = all_lines[3].clone();
-                            //eprintln!(
-                            //    "handling XRANGE with key:{stream_name}, start:{start}, end:{end}"
-                            //);
-                            {
-                                let mut lk = entry_streams.lock().unwrap();
-                                let curr_stream =
-                                    lk.entry(stream_name).or_insert(RedisEntryStream::new());
+                "exec" => {
+                    if *is_exec_mode {
+                        conn.multi_waiting = false;
+                        *is_exec_mode = false;
+                        let exec_resp = std::mem::take(hold_all_exec_reponse);
+                        //conn.write_to_stream(exec_resp.as_bytes());
+                        response_to_write = Some(RespValue::Array(exec_resp));
+                    } else {
+                        response_to_write = Some(RespValue::error(EXEC_WITHOUT_MULTI));
+                    }
+                }
+                "discard" => {
+                    conn.write_value(&RespValue::error("ERR DISCARD without MULTI"));
+                }
 
-                                response_to_write = curr_stream.get_from_range(&start, &end);
-                            }
+                "rpush" | "lpush" | "rpushx" | "lpushx" => {
+                    let left = spec.name.starts_with('l');
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        if spec.name.ends_with('x') {
+                            handle_pushx(lk, &all_lines[1], &all_lines[2..], left)
+                        } else {
+                            handle_push(lk, &all_lines[1], &all_lines[2..], left)
                         }
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                        "xread" => {
-                            let block = all_lines[1] == "block";
-                            let block_start_time = SystemTime::now();
-                            // TODO: check for invalid times
-                            let mut time_to_block_for: Duration = Duration::from_millis(0);
-                            let all_streams;
-
-                            let time_str = all_lines[2].parse::<u64>();
-                            let mut full_block = false;
-                            if block {
-                                //TODO: IF TIME STRING IS 0 add a blcoked stream conn clone to
-                                //waiting streams
-
-                                eprintln!("\n\n\nGOT BLOCK with time{}\n\n\n", all_lines[2]);
-                                let actual_time = time_str.as_ref().unwrap();
-                                full_block = actual_time == &0;
-                                time_to_block_for =
-                                    Duration::from_millis(*time_str.as_ref().unwrap());
-                                if !full_block {
-                                    sleep(time_to_block_for);
-                                } else {
-                                    eprintln!("\n\nFULL BLOCK\n\n");
-                                }
-                                all_streams = get_all_stream_names(&all_lines[4..]);
-                            } else {
-                                all_streams = get_all_stream_names(&all_lines[2..]);
-                            }
+                "lrange" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_lrange(
+                        &mut lk,
+                        &all_lines[1],
+                        &all_lines[2],
+                        &all_lines[3],
+                    ));
+                }
 
-                            let mut final_res = Vec::new();
-                            let mut lk = entry_streams.lock().unwrap();
-                            for (stream_name, start) in all_streams {
-                                let curr_stream = lk
-                                    .entry(stream_name.clone())
-                                    .or_insert(RedisEntryStream::new());
-                                eprintln!("curr stream{:?}", curr_stream);
-
-                                eprintln!("running xread for stream_name{:?}", &stream_name);
-                                if full_block {
-                                    eprintln!("\n\n\nADDING FULL BLOCK\n\n\n");
-                                    curr_stream.waiting_streams.insert(
-                                        stream_name.clone(),
-                                        conn.stream.try_clone().unwrap(),
-                                    );
-                                } else {
-                                    eprintln!("\n\n\nNOT full block\n\n\n");
-                                    let res = {
-                                        if block {
-                                            curr_stream.block_xread(
-                                                &stream_name,
-                                                block_start_time,
-                                                time_to_block_for,
-                                                &start,
-                                            )
-                                        } else {
-                                            curr_stream.xread_range(&stream_name, &start)
-                                        }
-                                    };
-                                    if res.is_some() {
-                                        final_res.push(res.unwrap())
-                                    }
-                                }
-                            }
+                "llen" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_llen(&mut lk, &all_lines[1]));
+                }
 
-                            let full_stream_bytes = get_xread_resp_array(&final_res);
-                            eprintln!("FINAL xread res:{:?}", full_stream_bytes);
-                            if !full_block {
-                                response_to_write = full_stream_bytes;
-                            }
-                        }
+                "lindex" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_lindex(&mut lk, &all_lines[1], &all_lines[2]));
+                }
 
-                        "incr" => {
-                            let mut lk = new_db.lock().unwrap();
-                            let key = all_lines[1].clone();
+                "lpos" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_lpos(
+                        &mut lk,
+                        &all_lines[1],
+                        &all_lines[2],
+                        &all_lines[3..],
+                    ));
+                }
+
+                "lpop" | "rpop" => {
+                    let key = &all_lines[1];
+                    let left = spec.name == "lpop";
+                    let res =
+                        run_write(ctx, write_command, &all_lines, |lk| match &all_lines[2..] {
+                            [] => handle_pop(lk, key, None, left),
+                            [count] => handle_pop(lk, key, Some(count), left),
+                            _ => RespValue::error(SYNTAX_ERROR),
+                        });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            let rv = lk.data.entry(key).or_insert(RedisValue {
-                                value: "0".to_string(),
-                                expires_at: None,
-                            });
+                "lset" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_lset(lk, &all_lines[1], &all_lines[2], &all_lines[3])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            if let Ok(val) = rv.value.parse::<i32>() {
-                                let new_val = val + 1;
-                                rv.value = new_val.to_string();
-                                response_to_write = get_redis_int(new_val);
-                            } else {
-                                response_to_write = NOT_INT_ERROR.to_string();
+                "linsert" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_linsert(
+                            lk,
+                            &all_lines[1],
+                            &all_lines[2],
+                            &all_lines[3],
+                            &all_lines[4],
+                        )
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
+
+                "lrem" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_lrem(lk, &all_lines[1], &all_lines[2], &all_lines[3])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
+
+                "ltrim" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_ltrim(lk, &all_lines[1], &all_lines[2], &all_lines[3])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
+
+                "lmove" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_lmove(
+                            lk,
+                            &all_lines[1],
+                            &all_lines[2],
+                            &all_lines[3],
+                            &all_lines[4],
+                        )
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
+
+                "blpop" | "brpop" | "blmove" | "brpoplpush" | "blmpop" | "lmpop" => {
+                    // nothing blocks inside MULTI, and our master is never kept waiting
+                    let can_block = spec.name != "lmpop" && !*is_exec_mode && !sent_by_main;
+                    let served = match parse_blocking_pop(spec.name, &all_lines[1..]) {
+                        Ok(pop) => handle_blocking_pop(
+                            &mut new_db.lock().unwrap(),
+                            conn.id,
+                            pop,
+                            can_block,
+                        ),
+                        Err(e) => Some(Served {
+                            reply: e,
+                            propagate: None,
+                        }),
+                    };
+                    match served {
+                        Some(Served {
+                            reply,
+                            propagate: command,
+                        }) => {
+                            // replicas are sent the pop that happened, not the blocking command
+                            if let Some(command) = command {
+                                propagate(info_fields, broadcast_info, &command);
+                                *write_command = command;
+                            }
+                            if !sent_by_main {
+                                response_to_write = Some(reply);
                             }
                         }
+                        None => *blocked_on_keys = true,
+                    }
+                }
 
-                        "multi" => {
-                            conn.multi_waiting = true;
-                            conn.write_to_stream(RESP_OK.as_bytes());
-                            continue;
-                        }
+                "subscribe" => {
+                    eprintln!("received subscription!");
+                    let chan_name = &all_lines[1];
+                    let mut chan_lk = channels_db.lock().unwrap();
+                    let chan = chan_lk
+                        .channels
+                        .entry(chan_name.clone())
+                        .or_insert(Channel::new(chan_name));
+                    //let add_chan = chan_lk.get(&chan_name.clone()).unwrap();
+                    //if let Some(port) = get_port(&conn.stream) {
+                    //let mut sub_lk = subscribers_db.lock().unwrap();
+                    // let subber =
+                    //     sub_lk.entry(port.clone()).or_insert(Subscriber::new(port));
+                    //TODO: check if channel has the subber first before adding
+
+                    if !chan.subscribers.iter().any(|sb| sb.id == conn.id) {
+                        eprintln!("different subber");
+                        //subber.channel_count += 1;
+                        //conn.subbed_channels.push(chan_name.clone());
+                        conn.num_channels += 1;
+                        let num_chans = conn.num_channels as i64;
+                        chan.subscribers.push(Subscriber { id: conn.id });
+                        response_to_write = Some(RespValue::Push(vec![
+                            RespValue::bulk("subscribe"),
+                            RespValue::bulk(chan_name),
+                            RespValue::Integer(num_chans),
+                        ]));
+                        conn.in_sub_mode = true;
+                    } else {
+                        eprintln!("\n\nALREADY SUBBED!!\n");
+                    }
+                    //}
+                }
 
-                        "exec" => {
-                            if is_exec_mode {
-                                conn.multi_waiting = false;
-                                is_exec_mode = false;
-                                let mut exec_resp = format!("*{}\r\n", hold_all_exec_reponse.len());
-                                hold_all_exec_reponse
-                                    .iter()
-                                    .for_each(|e| exec_resp.push_str(&e));
-                                //conn.write_to_stream(exec_resp.as_bytes());
-                                response_to_write = exec_resp;
-                                hold_all_exec_reponse = Vec::new();
-                            } else {
-                                response_to_write = EXEC_WITHOUT_MULTI.to_string();
+                "publish" => {
+                    let chan_name = &all_lines[1];
+                    let msg = &all_lines[2];
+                    let mut lk = channels_db.lock().unwrap();
+                    if lk.channels.contains_key(chan_name) {
+                        // the event loop queues the messages on the subscribers' connections
+                        let num_subs = lk.publish(chan_name, msg);
+                        response_to_write = Some(RespValue::Integer(num_subs as i64));
+                    }
+                }
+
+                "unsubscribe" => {
+                    let chan_name = &all_lines[1];
+                    let mut lk = channels_db.lock().unwrap();
+                    if let Some(curr_chan) = lk.channels.get_mut(chan_name) {
+                        for i in 0..curr_chan.subscribers.len() {
+                            if curr_chan.subscribers[i].id == conn.id {
+                                curr_chan.subscribers.remove(i);
+                                conn.num_channels -= 1;
+                                let num_chans = conn.num_channels as i64;
+                                response_to_write = Some(RespValue::Push(vec![
+                                    RespValue::bulk("unsubscribe"),
+                                    RespValue::bulk(chan_name),
+                                    RespValue::Integer(num_chans),
+                                ]));
+
+                                break;
                             }
                         }
-                        "discard" => {
-                            conn.write_to_stream(b"-ERR DISCARD without MULTI\r\n");
+                    }
+                }
+
+                /*
+                 * HASHES
+                 * */
+                "hset" => {
+                    let pairs = &all_lines[2..];
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        if pairs.len() % 2 != 0 {
+                            wrong_args_error("hset")
+                        } else {
+                            handle_hset(lk, &all_lines[1], pairs)
                         }
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                        "rpush" => {
-                            let key = &all_lines[1];
-                            let mut lk = lists_map.lock().unwrap();
-                            let use_list =
-                                lk.entry(key.clone()).or_insert(RedisList::new(key.clone()));
-                            all_lines[2..].iter().for_each(|e| {
-                                use_list.values.push(e.clone());
-                            });
+                "hsetnx" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_hsetnx(lk, &all_lines[1], &all_lines[2], &all_lines[3])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            let num_vals = use_list.values.len();
-                            use_list.check_waiting_streams();
+                "hdel" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_hdel(lk, &all_lines[1], &all_lines[2..])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            response_to_write = get_redis_int(num_vals as i32);
-                        }
+                "hincrby" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_hincrby(lk, &all_lines[1], &all_lines[2], &all_lines[3])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                        "lpush" => {
-                            let key = &all_lines[1];
-                            let mut lk = lists_map.lock().unwrap();
-                            let use_list =
-                                lk.entry(key.clone()).or_insert(RedisList::new(key.clone()));
-                            all_lines[2..].iter().for_each(|e| {
-                                use_list.values.splice(0..0, [e.clone()]);
-                            });
+                "hincrbyfloat" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_hincrbyfloat(lk, &all_lines[1], &all_lines[2], &all_lines[3])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            let num_vals = use_list.values.len();
-                            use_list.check_waiting_streams();
+                "hget" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_hget(&mut lk, &all_lines[1], &all_lines[2]));
+                }
 
-                            response_to_write = get_redis_int(num_vals as i32);
-                        }
+                "hmget" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_hmget(&mut lk, &all_lines[1], &all_lines[2..]));
+                }
 
-                        "lrange" => {
-                            let key = &all_lines[1];
-                            let mut start = all_lines[2].parse::<i32>().unwrap();
-                            let mut end = all_lines[3].parse::<i32>().unwrap();
-
-                            let lk = lists_map.lock().unwrap();
-                            let search_opt = lk.get(key);
-                            match search_opt {
-                                Some(use_list) => {
-                                    let list_size = use_list.values.len() as i32;
-                                    if start < 0 {
-                                        if list_size + start < 0 {
-                                            start = 0
-                                        } else {
-                                            start = list_size + start
-                                        };
-                                    }
-                                    if end < 0 {
-                                        end = list_size + end;
-                                    }
-                                    eprintln!("HANDLING lrange with start:{start}, end:{end}");
-
-                                    if start >= list_size || start > end || start < 0 || end < 0 {
-                                        response_to_write = EMPTY_ARRAY.to_string();
-                                    } else {
-                                        if end >= list_size {
-                                            end = list_size - 1;
-                                        }
-                                        let start = start as usize;
-                                        let end = end as usize;
-                                        response_to_write = conn.format_resp_array(
-                                            use_list.values[start..end + 1]
-                                                .iter()
-                                                .map(|e| e.as_str())
-                                                .collect::<Vec<&str>>()
-                                                .as_slice(),
-                                        )
-                                    }
-                                }
-                                None => {
-                                    response_to_write = EMPTY_ARRAY.to_string();
-                                }
-                            }
-                        }
+                "hgetall" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_hgetall(&mut lk, &all_lines[1]));
+                }
 
-                        "llen" => {
-                            let key = &all_lines[1];
-                            let lk = lists_map.lock().unwrap();
-                            let search_opt = lk.get(key);
-                            match search_opt {
-                                Some(use_list) => {
-                                    response_to_write = get_redis_int(use_list.values.len() as i32);
-                                }
-                                None => response_to_write = get_redis_int(0),
-                            }
-                        }
+                "hkeys" | "hvals" => {
+                    let values = cmd.eq_ignore_ascii_case("hvals");
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_hkeys(&mut lk, &all_lines[1], values));
+                }
 
-                        "lpop" => {
-                            eprintln!("in lpop");
-                            let key = &all_lines[1];
-                            let num_to_remove = {
-                                if all_lines.len() > 2 {
-                                    all_lines[2].parse().unwrap()
-                                } else {
-                                    1
-                                }
-                            };
+                "hlen" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_hlen(&mut lk, &all_lines[1]));
+                }
 
-                            let mut lk = lists_map.lock().unwrap();
-                            let search_opt = lk.get_mut(key);
-                            match search_opt {
-                                Some(use_list) => {
-                                    if num_to_remove == 1 {
-                                        response_to_write =
-                                            get_bulk_string(&use_list.values.remove(0));
-                                    } else {
-                                        let mut use_nums = Vec::new();
-                                        for _ in 0..num_to_remove {
-                                            use_nums.push(use_list.values.remove(0));
-                                        }
-                                        response_to_write =
-                                            get_resp_from_string(use_nums.as_slice());
-                                    }
-                                }
-                                None => response_to_write = RESP_NULL.to_string(),
-                            }
-                        }
+                "hexists" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_hexists(&mut lk, &all_lines[1], &all_lines[2]));
+                }
 
-                        "blpop" => {
-                            let key = all_lines[1].clone();
-                            let blocking_time = all_lines[2].parse::<f64>().unwrap();
-                            let mut timed_block = None;
+                "hstrlen" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_hstrlen(&mut lk, &all_lines[1], &all_lines[2]));
+                }
 
-                            // scope to make sure lock is dropped after match
-                            {
-                                let mut lk = lists_map.lock().unwrap();
-                                let use_list =
-                                    lk.entry(key.clone()).or_insert(RedisList::new(key.clone()));
-
-                                if blocking_time == 0.0 && !use_list.values.is_empty() {
-                                    response_to_write = get_resp_from_string(&[
-                                        key.clone(),
-                                        use_list.values.remove(0),
-                                    ]);
-                                } else if blocking_time == 0.0 {
-                                    use_list
-                                        .blocking_pop_streams
-                                        .push(conn.stream.try_clone().unwrap());
-                                } else {
-                                    let blocking_ms = (blocking_time * 1_000.0) as u64;
-                                    let blocking_dur = Duration::from_millis(blocking_ms);
-                                    timed_block =
-                                        Some((conn.stream.try_clone().unwrap(), blocking_dur));
-                                }
-                            }
+                /*
+                 * SETS
+                 * */
+                "sadd" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_sadd(lk, &all_lines[1], &all_lines[2..])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            if let Some((mut st, blocking_dur)) = timed_block {
-                                let blocking_until = SystemTime::now() + blocking_dur;
-                                let use_lkd_db = Arc::clone(&lists_map);
-                                thread::spawn(move || loop {
-                                    //sleep(blocking_dur / 5);
-                                    sleep(Duration::from_millis(50));
-                                    if SystemTime::now() > blocking_until {
-                                        let _ = st.write_all(RESP_NULL.as_bytes());
-                                        break;
-                                    }
-                                    let mut lk = use_lkd_db.lock().unwrap();
-                                    let use_list = lk
-                                        .entry(key.clone())
-                                        .or_insert(RedisList::new(key.clone()));
-                                    if !use_list.values.is_empty() {
-                                        let _ = st.write_all(
-                                            get_resp_from_string(&[
-                                                key.clone(),
-                                                use_list.values.remove(0),
-                                            ])
-                                            .as_bytes(),
-                                        );
-                                        break;
-                                    }
-                                });
-                            }
-                        }
+                "srem" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_srem(lk, &all_lines[1], &all_lines[2..])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                        "subscribe" => {
-                            eprintln!("received subscription!");
-                            let chan_name = &all_lines[1];
-                            let mut chan_lk = channels_db.lock().unwrap();
-                            let chan = chan_lk
-                                .entry(chan_name.clone())
-                                .or_insert(Channel::new(chan_name));
-                            //let add_chan = chan_lk.get(&chan_name.clone()).unwrap();
-                            //if let Some(port) = get_port(&conn.stream) {
-                            //let mut sub_lk = subscribers_db.lock().unwrap();
-                            // let subber =
-                            //     sub_lk.entry(port.clone()).or_insert(Subscriber::new(port));
-                            //TODO: check if channel has the subber first before adding
-
-                            if !chan
-                                .subscribers
-                                .iter()
-                                .any(|sb| get_port(sb) == get_port(&conn.stream))
-                            {
-                                eprintln!("different subber");
-                                //subber.channel_count += 1;
-                                //conn.subbed_channels.push(chan_name.clone());
-                                conn.num_channels += 1;
-                                let num_chans = (conn.num_channels + 0) as i32;
-                                chan.subscribers.push(conn.stream.try_clone().unwrap());
-                                response_to_write = format!(
-                                    "*3\r\n{}{}{}",
-                                    get_bulk_string("subscribe"),
-                                    get_bulk_string(chan_name),
-                                    get_redis_int(num_chans)
-                                );
-                                conn.in_sub_mode = true;
-                            } else {
-                                eprintln!("\n\nALREADY SUBBED!!\n");
-                            }
-                            //}
-                        }
+                "spop" => {
+                    let key = &all_lines[1];
+                    let (res, popped) = match &all_lines[2..] {
+                        [] => handle_spop(&mut new_db.lock().unwrap(), key, None),
+                        [count] => handle_spop(&mut new_db.lock().unwrap(), key, Some(count)),
+                        _ => (RespValue::error(SYNTAX_ERROR), Vec::new()),
+                    };
+                    // the members are picked at random, so replicas are told which ones went
+                    if !popped.is_empty() {
+                        let mut srem_command = vec![b"SREM".to_vec(), key.clone()];
+                        srem_command.extend(popped);
+                        propagate(info_fields, broadcast_info, &srem_command);
+                        *write_command = srem_command;
+                    }
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                        "publish" => {
-                            let chan_name = &all_lines[1];
-                            let msg = &all_lines[2];
-                            let lk = channels_db.lock().unwrap();
-                            if let Some(curr_chan) = lk.get(chan_name) {
-                                let num_subs = curr_chan.subscribers.len();
-                                response_to_write = get_redis_int(num_subs as i32);
-
-                                for mut st in &curr_chan.subscribers {
-                                    let _ = st.write_all(
-                                        conn.format_resp_array(&[
-                                            "message",
-                                            chan_name,
-                                            msg.as_str(),
-                                        ])
-                                        .as_bytes(),
-                                    );
-                                }
-                            }
-                        }
+                "smove" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_smove(lk, &all_lines[1], &all_lines[2], &all_lines[3])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                        "unsubscribe" => {
-                            let chan_name = &all_lines[1];
-                            let mut lk = channels_db.lock().unwrap();
-                            if let Some(curr_chan) = lk.get_mut(chan_name) {
-                                for i in 0..curr_chan.subscribers.len() {
-                                    let curr_port = get_port(&conn.stream);
-                                    if get_port(&curr_chan.subscribers[i]) == curr_port {
-                                        curr_chan.subscribers.remove(i);
-                                        conn.num_channels -= 1;
-                                        let num_chans = (conn.num_channels + 0) as i32;
-                                        response_to_write = format!(
-                                            "*3\r\n{}{}{}",
-                                            get_bulk_string("unsubscribe"),
-                                            get_bulk_string(chan_name),
-                                            get_redis_int(num_chans)
-                                        );
-
-                                        break;
-                                    }
-                                }
-                            }
-                        }
+                "sinterstore" | "sunionstore" | "sdiffstore" => {
+                    let op = match cmd.as_str() {
+                        "sinterstore" => SetOp::Inter,
+                        "sunionstore" => SetOp::Union,
+                        _ => SetOp::Diff,
+                    };
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_set_algebra_store(lk, op, &all_lines[1], &all_lines[2..])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                        "zadd" => {
-                            let set_name = &all_lines[1];
-                            let score = &all_lines[2];
-                            let name = &all_lines[3];
-                            let mut lk = sets_map.lock().unwrap();
-                            let curr_set =
-                                lk.entry(set_name.clone()).or_insert(RedisSortedSet::new());
-                            let is_new = curr_set.insert(&score, &name);
-                            eprintln!("\n\nSETS MAP LOCK RELEASED\n\n");
-                            let use_num = {
-                                if is_new {
-                                    1
-                                } else {
-                                    0
-                                }
-                            };
-                            response_to_write = get_redis_int(use_num);
-                        }
+                "sinter" | "sunion" | "sdiff" => {
+                    let op = match cmd.as_str() {
+                        "sinter" => SetOp::Inter,
+                        "sunion" => SetOp::Union,
+                        _ => SetOp::Diff,
+                    };
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_set_algebra(&mut lk, op, &all_lines[1..]));
+                }
 
-                        "zrank" => {
-                            let set_name = &all_lines[1];
-                            let member_name = &all_lines[2];
-                            let lk = sets_map.lock().unwrap();
-                            if let Some(found_set) = lk.get(set_name) {
-                                if let Some(rank_res) = found_set.rank(member_name) {
-                                    response_to_write = get_redis_int(rank_res as i32)
-                                } else {
-                                    response_to_write = RESP_NULL.to_string();
-                                }
-                            } else {
-                                response_to_write = RESP_NULL.to_string();
-                            }
-                        }
+                "sintercard" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_sintercard(&mut lk, &all_lines[1..]));
+                }
 
-                        "zrange" => {
-                            let set_name = &all_lines[1];
-                            let start = all_lines[2].parse::<i32>().unwrap();
-                            let end = all_lines[3].parse::<i32>().unwrap();
-                            let lk = sets_map.lock().unwrap();
+                "smembers" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_smembers(&mut lk, &all_lines[1]));
+                }
 
-                            if let Some(found_set) = lk.get(set_name) {
-                                response_to_write = found_set.range_resp_array(start, end)
-                            } else {
-                                response_to_write = EMPTY_ARRAY.into()
-                            }
-                        }
+                "sismember" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write =
+                        Some(handle_sismember(&mut lk, &all_lines[1], &all_lines[2]));
+                }
 
-                        "zcard" => {
-                            let set_name = &all_lines[1];
-                            let lk = sets_map.lock().unwrap();
+                "smismember" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write =
+                        Some(handle_smismember(&mut lk, &all_lines[1], &all_lines[2..]));
+                }
 
-                            if let Some(found_set) = lk.get(set_name) {
-                                response_to_write = get_redis_int(found_set.len() as i32);
-                            } else {
-                                response_to_write = ZERO_INT.into();
-                            }
-                        }
+                "scard" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_scard(&mut lk, &all_lines[1]));
+                }
 
-                        "zscore" => {
-                            let set_name = &all_lines[1];
-                            let member_name = &all_lines[2];
-                            let lk = sets_map.lock().unwrap();
+                "srandmember" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(match &all_lines[2..] {
+                        [] => handle_srandmember(&mut lk, &all_lines[1], None),
+                        [count] => handle_srandmember(&mut lk, &all_lines[1], Some(count)),
+                        _ => RespValue::error(SYNTAX_ERROR),
+                    });
+                }
 
-                            if let Some(found_set) = lk.get(set_name) {
-                                if let Some(score) = found_set.get_member(&member_name) {
-                                    response_to_write = get_bulk_string(&score.to_string());
-                                } else {
-                                    response_to_write = RESP_NULL.into();
-                                }
-                            } else {
-                                response_to_write = RESP_NULL.into();
-                            }
-                        }
+                "zadd" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_zadd(lk, &all_lines[1..])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                        "zrem" => {
-                            let set_name = &all_lines[1];
-                            let member_name = &all_lines[2];
-                            let mut lk = sets_map.lock().unwrap();
+                "zincrby" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_zincrby(lk, &all_lines[1..])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
 
-                            if let Some(found_set) = lk.get_mut(set_name) {
-                                if found_set.remove_member(&member_name) {
-                                    response_to_write = get_redis_int(1);
-                                } else {
-                                    response_to_write = ZERO_INT.into()
-                                }
-                            } else {
-                                response_to_write = ZERO_INT.into()
-                            }
-                        }
+                "zrank" => {
+                    let set_name = &all_lines[1];
+                    let member_name = &all_lines[2];
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(match lk.get_sorted_set(set_name) {
+                        Ok(Some(found_set)) => match found_set.rank(member_name) {
+                            Some(rank_res) => RespValue::Integer(rank_res as i64),
+                            None => RespValue::Null,
+                        },
+                        Ok(None) => RespValue::Null,
+                        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+                    });
+                }
+
+                "zrange" | "zrangebyscore" | "zrevrangebyscore" | "zrangebylex"
+                | "zrevrangebylex" | "zrevrange" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write = Some(handle_zrange(
+                        &mut lk,
+                        spec.name,
+                        &all_lines[1..],
+                        conn.protocol,
+                    ));
+                }
+
+                "zrangestore" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_zrangestore(lk, &all_lines[1..])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
+
+                "zcard" => {
+                    let set_name = &all_lines[1];
+                    let mut lk = new_db.lock().unwrap();
+
+                    response_to_write = Some(match lk.get_sorted_set(set_name) {
+                        Ok(Some(found_set)) => RespValue::Integer(found_set.len() as i64),
+                        Ok(None) => RespValue::Integer(0),
+                        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+                    });
+                }
+
+                "zscore" => {
+                    let set_name = &all_lines[1];
+                    let member_name = &all_lines[2];
+                    let mut lk = new_db.lock().unwrap();
+
+                    response_to_write = Some(match lk.get_sorted_set(set_name) {
+                        Ok(Some(found_set)) => match found_set.get_member(member_name) {
+                            Some(score) => RespValue::Double(*score),
+                            None => RespValue::Null,
+                        },
+                        Ok(None) => RespValue::Null,
+                        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+                    });
+                }
+
+                "zrem" => {
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_zrem(lk, &all_lines[1], &all_lines[2..])
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
+
+                /*
+                 * SORTED SET MEMBER EXPIRY
+                 * */
+                "expiremember" | "pexpiremember" | "expirememberat" | "pexpirememberat" => {
+                    let lower_cmd = cmd.to_lowercase();
+                    let unit_ms = if lower_cmd.starts_with('p') { 1 } else { 1000 };
+                    let absolute = lower_cmd.ends_with("at");
+                    let res = run_write(ctx, write_command, &all_lines, |lk| {
+                        handle_expire_member(lk, &all_lines, unit_ms, absolute)
+                    });
+                    if !sent_by_main {
+                        response_to_write = Some(res);
+                    }
+                }
+
+                "memberttl" => {
+                    let mut lk = new_db.lock().unwrap();
+                    response_to_write =
+                        Some(handle_member_ttl(&mut lk, &all_lines[1], &all_lines[2]));
+                }
 
-                        _unrecognized_cmd => {
-                            return Err(Box::new(RdbError::UnsupportedFeature(
-                                "UNRECOGNIZED COMMAND",
-                            )))
+                _ => match registry.custom(spec.name) {
+                    Some(custom) => {
+                        let protocol = conn.protocol;
+                        let res = run_write(ctx, write_command, &all_lines, |lk| {
+                            let mut command_ctx = CommandContext { db: lk, protocol };
+                            custom.execute(&mut command_ctx, &all_lines[1..])
+                        });
+                        if !sent_by_main {
+                            response_to_write = Some(res);
                         }
                     }
-                    /*
-                     * HANDLE COMMAND RESPONSES
-                     */
-                    if is_exec_mode {
-                        hold_all_exec_reponse.push(response_to_write.clone());
-                        //eprintln!("EXEC MODE!! with resps:{:?}", hold_all_exec_reponse);
-                        continue;
-                    } else if !response_to_write.is_empty() {
-                        conn.write_to_stream(response_to_write.as_bytes());
+                    None => {
+                        response_to_write = Some(unknown_command_error(&all_lines));
                     }
+                },
+            }
+            propagate_expired(ctx);
+            /*
+             * HANDLE COMMAND RESPONSES
+             */
+            // commands inside MULTI never block, they reply with what is there now
+            if *is_exec_mode {
+                if let Some(mut blocked) = pending.take() {
+                    response_to_write = Some(blocked.resolve_now());
                 }
             }
-            Ok(None) => {}
-            Err(e) => {
-                eprintln!("Connection error: {}", e);
-                break;
+            if let Some(reply) = response_to_write {
+                if *is_exec_mode {
+                    hold_all_exec_reponse.push(reply);
+                    //eprintln!("EXEC MODE!! with resps:{:?}", hold_all_exec_reponse);
+                    continue;
+                }
+                conn.write_value(&reply);
             }
         }
+        Ok(true)
     }
-    Ok(())
+}
+
+/// Runs a write command under the database lock
+/// Replicas are sent the command only when it changed the keyspace, as counted by dirty, and
+/// after the deletions of the keys it found expired
+fn run_write(
+    ctx: &ServerContext,
+    write_command: &mut Vec<Vec<u8>>,
+    command: &[Vec<u8>],
+    write: impl FnOnce(&mut RedisDatabase) -> RespValue,
+) -> RespValue {
+    let mut lk = ctx.new_db.lock().unwrap();
+    let dirty = lk.dirty;
+    let res = write(&mut lk);
+    let changed = lk.dirty != dirty;
+    let rewritten = lk.propagate_as.take();
+    let expired = lk.take_expired();
+    drop(lk);
+    for command in expired {
+        propagate(&ctx.info_fields, &ctx.broadcast_info, &command);
+    }
+    if changed {
+        let command = rewritten.unwrap_or_else(|| command.to_vec());
+        propagate(&ctx.info_fields, &ctx.broadcast_info, &command);
+        *write_command = command;
+    }
+    res
+}
+
+/// Sends replicas a DEL for every key found expired by the last command
+fn propagate_expired(ctx: &ServerContext) {
+    let expired = ctx.new_db.lock().unwrap().take_expired();
+    for command in expired {
+        propagate(&ctx.info_fields, &ctx.broadcast_info, &command);
+    }
+}
+
+/// Serves clients blocked on keys the last command made ready
+/// Their replies are left in the blocked clients' outbox for the event loop to deliver
+pub fn serve_blocked_clients(ctx: &ServerContext) {
+    let served = ctx.new_db.lock().unwrap().serve_blocked();
+    propagate_expired(ctx);
+    for command in served {
+        propagate(&ctx.info_fields, &ctx.broadcast_info, &command);
+    }
+}
+
+/// Replica side of the handshake, PING, REPLCONF twice and PSYNC
+/// The replies are left for the session to skip, the RDB that follows PSYNC included
+fn handshake(conn: &mut RedisConnection, replica_port: &str) {
+    conn.write_value(&RespValue::bulk_array(["PING"]));
+    let _ = conn.flush();
+    let _res = conn.try_read_command();
+    sleep(Duration::from_millis(10));
+    //eprintln!("Read result: {:?}", res);
+
+    conn.write_value(&RespValue::bulk_array([
+        REPL_CONF,
+        LISTENING_PORT,
+        replica_port,
+    ]));
+    let _ = conn.flush();
+    let _res = conn.try_read_command();
+    sleep(Duration::from_millis(10));
+
+    conn.write_value(&RespValue::bulk_array([REPL_CONF, "capa", "psync2"]));
+    let _ = conn.flush();
+    let _res = conn.try_read_command();
+    sleep(Duration::from_millis(10));
+
+    conn.write_value(&RespValue::bulk_array([PSYNC, "?", "-1"]));
+    let _ = conn.flush();
+    //ignore the last sent after psync
 }
@@ -0,0 +1,307 @@
+use std::collections::HashSet;
+
+use crate::constants::*;
+use crate::redis_database::{RedisData, RedisDatabase, RedisValue, WrongType};
+use crate::redis_set::RedisSet;
+use crate::resp::RespValue;
+use crate::utils::parse_redis_int;
+
+/// Runs a read only command on the set under key, `missing` is the reply when there is none
+fn read_set(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    missing: RespValue,
+    read: impl FnOnce(&RedisSet) -> RespValue,
+) -> RespValue {
+    match db.get_set(key) {
+        Ok(Some(set)) => read(set),
+        Ok(None) => missing,
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
+
+fn member_set(members: Vec<Vec<u8>>) -> RespValue {
+    RespValue::Set(members.into_iter().map(RespValue::bulk).collect())
+}
+
+/// SADD key member [member ...], returns how many members were added
+pub fn handle_sadd(db: &mut RedisDatabase, key: &[u8], members: &[Vec<u8>]) -> RespValue {
+    match db.get_or_create_set(key) {
+        Ok(set) => {
+            let added = members.iter().filter(|m| set.insert(m)).count();
+            db.dirty += added as u64;
+            RespValue::Integer(added as i64)
+        }
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
+
+/// SREM key member [member ...], the key goes away with its last member
+pub fn handle_srem(db: &mut RedisDatabase, key: &[u8], members: &[Vec<u8>]) -> RespValue {
+    let set = match db.get_set(key) {
+        Ok(Some(set)) => set,
+        Ok(None) => return RespValue::Integer(0),
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    let removed = members.iter().filter(|m| set.remove(m)).count();
+    if set.is_empty() {
+        db.remove(key);
+    }
+    db.dirty += removed as u64;
+    RespValue::Integer(removed as i64)
+}
+
+pub fn handle_smembers(db: &mut RedisDatabase, key: &[u8]) -> RespValue {
+    read_set(db, key, RespValue::Set(Vec::new()), |set| {
+        member_set(set.members())
+    })
+}
+
+pub fn handle_sismember(db: &mut RedisDatabase, key: &[u8], member: &[u8]) -> RespValue {
+    read_set(db, key, RespValue::Integer(0), |set| {
+        RespValue::Integer(set.contains(member) as i64)
+    })
+}
+
+/// SMISMEMBER key member [member ...], 1 or 0 for each member
+pub fn handle_smismember(db: &mut RedisDatabase, key: &[u8], members: &[Vec<u8>]) -> RespValue {
+    let none_found = RespValue::Array(vec![RespValue::Integer(0); members.len()]);
+    read_set(db, key, none_found, |set| {
+        RespValue::Array(
+            members
+                .iter()
+                .map(|m| RespValue::Integer(set.contains(m) as i64))
+                .collect(),
+        )
+    })
+}
+
+pub fn handle_scard(db: &mut RedisDatabase, key: &[u8]) -> RespValue {
+    read_set(db, key, RespValue::Integer(0), |set| {
+        RespValue::Integer(set.len() as i64)
+    })
+}
+
+/// SPOP key [count], gives back the reply and the members that were popped
+/// The members are what replicas are sent, as an SREM, since the pick is random
+pub fn handle_spop(
+    db: &mut RedisDatabase,
+    key: &[u8],
+    count: Option<&[u8]>,
+) -> (RespValue, Vec<Vec<u8>>) {
+    let count = match count.map(parse_redis_int) {
+        None => None,
+        Some(Some(n)) if n >= 0 => Some(n as usize),
+        Some(_) => return (RespValue::error(NOT_POSITIVE_ERROR), Vec::new()),
+    };
+    let set = match db.get_set(key) {
+        Ok(Some(set)) => set,
+        Ok(None) => {
+            let missing = match count {
+                Some(_) => RespValue::Set(Vec::new()),
+                None => RespValue::Null,
+            };
+            return (missing, Vec::new());
+        }
+        Err(WrongType) => return (RespValue::error(WRONGTYPE_ERROR), Vec::new()),
+    };
+    let popped = set.pop(count.unwrap_or(1));
+    if set.is_empty() {
+        db.remove(key);
+    }
+    db.dirty += popped.len() as u64;
+    let res = match count {
+        Some(_) => member_set(popped.clone()),
+        None => popped.first().map_or(RespValue::Null, RespValue::bulk),
+    };
+    (res, popped)
+}
+
+/// SRANDMEMBER key [count], a negative count may return the same member more than once
+pub fn handle_srandmember(db: &mut RedisDatabase, key: &[u8], count: Option<&[u8]>) -> RespValue {
+    let count = match count.map(parse_redis_int) {
+        None => None,
+        Some(Some(n)) => Some(n),
+        Some(None) => return RespValue::error(NOT_INT_ERROR),
+    };
+    // as redis, so a negative count always has a magnitude
+    if count.is_some_and(|n| n < -(i64::MAX / 2)) {
+        return RespValue::error(OUT_OF_RANGE_ERROR);
+    }
+    let missing = match count {
+        Some(_) => RespValue::empty_array(),
+        None => RespValue::Null,
+    };
+    read_set(db, key, missing, |set| match count {
+        None => set
+            .random_members(1)
+            .first()
+            .map_or(RespValue::Null, RespValue::bulk),
+        Some(n) if n >= 0 => RespValue::bulk_array(set.random_members(n as usize)),
+        Some(n) => {
+            RespValue::bulk_array(set.random_members_with_repeats(n.unsigned_abs() as usize))
+        }
+    })
+}
+
+/// SMOVE source destination member
+pub fn handle_smove(
+    db: &mut RedisDatabase,
+    source: &[u8],
+    destination: &[u8],
+    member: &[u8],
+) -> RespValue {
+    // both keys are checked first so a wrong type never leaves the member removed
+    if let Err(WrongType) = db.get_set(destination) {
+        return RespValue::error(WRONGTYPE_ERROR);
+    }
+    let set = match db.get_set(source) {
+        Ok(Some(set)) => set,
+        Ok(None) => return RespValue::Integer(0),
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    // moving onto the same set changes nothing, the key keeps its TTL and replicas hear nothing
+    if source == destination {
+        return RespValue::Integer(set.contains(member) as i64);
+    }
+    if !set.remove(member) {
+        return RespValue::Integer(0);
+    }
+    if set.is_empty() {
+        db.remove(source);
+    }
+    match db.get_or_create_set(destination) {
+        Ok(dest) => {
+            dest.insert(member);
+        }
+        Err(WrongType) => unreachable!("checked above"),
+    }
+    db.dirty += 1;
+    RespValue::Integer(1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// The sets under keys all borrowed at once, None for a missing key
+fn sets_at<'a>(
+    db: &'a mut RedisDatabase,
+    keys: &[Vec<u8>],
+) -> Result<Vec<Option<&'a RedisSet>>, WrongType> {
+    for key in keys {
+        db.remove_if_expired(key);
+    }
+    let db = &*db;
+    keys.iter()
+        .map(|key| match db.data.get(key) {
+            Some(RedisValue {
+                value: RedisData::Set(set),
+                ..
+            }) => Ok(Some(set)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        })
+        .collect()
+}
+
+/// Members of the intersection, union or difference of the sets under keys
+/// A missing key counts as an empty set
+fn set_algebra(
+    db: &mut RedisDatabase,
+    op: SetOp,
+    keys: &[Vec<u8>],
+) -> Result<Vec<Vec<u8>>, WrongType> {
+    let sets = sets_at(db, keys)?;
+    let members = match op {
+        SetOp::Inter => {
+            if sets.iter().any(Option::is_none) {
+                return Ok(Vec::new());
+            }
+            let mut sets: Vec<&RedisSet> = sets.into_iter().flatten().collect();
+            // the smallest set bounds the result, so only its members are checked
+            sets.sort_by_key(|s| s.len());
+            let (smallest, rest) = sets.split_first().expect("at least one key");
+            smallest
+                .members()
+                .into_iter()
+                .filter(|m| rest.iter().all(|s| s.contains(m)))
+                .collect()
+        }
+        SetOp::Union => {
+            let mut union: HashSet<Vec<u8>> = HashSet::new();
+            for set in sets.into_iter().flatten() {
+                union.extend(set.members());
+            }
+            union.into_iter().collect()
+        }
+        SetOp::Diff => {
+            let Some(first) = sets[0] else {
+                return Ok(Vec::new());
+            };
+            first
+                .members()
+                .into_iter()
+                .filter(|m| sets[1..].iter().flatten().all(|s| !s.contains(m)))
+                .collect()
+        }
+    };
+    Ok(members)
+}
+
+/// SINTER, SUNION or SDIFF key [key ...]
+pub fn handle_set_algebra(db: &mut RedisDatabase, op: SetOp, keys: &[Vec<u8>]) -> RespValue {
+    match set_algebra(db, op, keys) {
+        Ok(members) => member_set(members),
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
+
+/// SINTERSTORE, SUNIONSTORE or SDIFFSTORE destination key [key ...]
+/// The destination is overwritten whatever it held, and deleted when the result is empty
+pub fn handle_set_algebra_store(
+    db: &mut RedisDatabase,
+    op: SetOp,
+    destination: &[u8],
+    keys: &[Vec<u8>],
+) -> RespValue {
+    let members = match set_algebra(db, op, keys) {
+        Ok(members) => members,
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    let len = members.len();
+    db.remove(destination);
+    if len > 0 {
+        let set = RedisSet::from_members(members);
+        db.insert(destination.to_vec(), RedisValue::new(RedisData::Set(set)));
+    }
+    RespValue::Integer(len as i64)
+}
+
+/// SINTERCARD numkeys key [key ...] [LIMIT limit], a limit of 0 means no limit
+pub fn handle_sintercard(db: &mut RedisDatabase, args: &[Vec<u8>]) -> RespValue {
+    let numkeys = match parse_redis_int(&args[0]) {
+        Some(n) if n > 0 => n as usize,
+        Some(_) => return RespValue::error("ERR numkeys should be greater than 0"),
+        None => return RespValue::error(NOT_INT_ERROR),
+    };
+    let Some(keys) = args.get(1..1 + numkeys) else {
+        return RespValue::error("ERR Number of keys can't be greater than number of args");
+    };
+    let limit = match &args[1 + numkeys..] {
+        [] => 0,
+        [option, limit] if option.eq_ignore_ascii_case(b"limit") => match parse_redis_int(limit) {
+            Some(l) if l >= 0 => l as usize,
+            _ => return RespValue::error("ERR LIMIT can't be negative"),
+        },
+        _ => return RespValue::error(SYNTAX_ERROR),
+    };
+    match set_algebra(db, SetOp::Inter, keys) {
+        Ok(members) if limit > 0 => RespValue::Integer(members.len().min(limit) as i64),
+        Ok(members) => RespValue::Integer(members.len() as i64),
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
@@ -0,0 +1,344 @@
+use std::ops::Range;
+
+use crate::constants::*;
+use crate::redis_database::{RedisData, RedisDatabase, RedisValue, WrongType};
+use crate::redis_sorted_set::{LexBound, RedisSortedSet, ScoreBound};
+use crate::resp::{Protocol, RespValue};
+use crate::utils::{arg_str, parse_redis_float, parse_redis_int};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RangeBy {
+    Rank,
+    Score,
+    Lex,
+}
+
+/// Options of a ZRANGE family command once parsed
+#[derive(Debug)]
+struct RangeQuery {
+    by: RangeBy,
+    rev: bool,
+    /// Offset and count, a negative count takes everything past the offset
+    limit: Option<(i64, i64)>,
+    withscores: bool,
+}
+
+/// The bounds of a range, min first whatever the direction
+enum Window {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+/// Parses the options after `key start stop`
+/// The legacy commands fix the range type and direction, so only ZRANGE and ZRANGESTORE take
+/// BYSCORE, BYLEX and REV, as in redis
+fn parse_range_query(name: &str, options: &[Vec<u8>]) -> Result<RangeQuery, RespValue> {
+    let (mut by, mut rev) = match name {
+        "zrangebyscore" => (Some(RangeBy::Score), Some(false)),
+        "zrevrangebyscore" => (Some(RangeBy::Score), Some(true)),
+        "zrangebylex" => (Some(RangeBy::Lex), Some(false)),
+        "zrevrangebylex" => (Some(RangeBy::Lex), Some(true)),
+        "zrevrange" => (Some(RangeBy::Rank), Some(true)),
+        _ => (None, None),
+    };
+    let store = name == "zrangestore";
+    let mut limit = None;
+    let mut withscores = false;
+    let mut i = 0;
+    while i < options.len() {
+        match arg_str(&options[i]).to_lowercase().as_str() {
+            "withscores" if !store => withscores = true,
+            "limit" if i + 2 < options.len() => {
+                let offset = parse_redis_int(&options[i + 1]);
+                let count = parse_redis_int(&options[i + 2]);
+                match (offset, count) {
+                    (Some(offset), Some(count)) => limit = Some((offset, count)),
+                    _ => return Err(RespValue::error(NOT_INT_ERROR)),
+                }
+                i += 2;
+            }
+            "rev" if rev.is_none() => rev = Some(true),
+            "byscore" if by.is_none() => by = Some(RangeBy::Score),
+            "bylex" if by.is_none() => by = Some(RangeBy::Lex),
+            _ => return Err(RespValue::error(SYNTAX_ERROR)),
+        }
+        i += 1;
+    }
+    let by = by.unwrap_or(RangeBy::Rank);
+    if limit.is_some() && by == RangeBy::Rank {
+        return Err(RespValue::error(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+        ));
+    }
+    if withscores && by == RangeBy::Lex {
+        return Err(RespValue::error(
+            "ERR syntax error, WITHSCORES not supported in combination with BYLEX",
+        ));
+    }
+    Ok(RangeQuery {
+        by,
+        rev: rev.unwrap_or(false),
+        limit,
+        withscores,
+    })
+}
+
+/// Parses start and stop, which a reversed score or lex range gives max first
+fn parse_window(query: &RangeQuery, start: &[u8], stop: &[u8]) -> Result<Window, RespValue> {
+    let (min, max) = if query.rev && query.by != RangeBy::Rank {
+        (stop, start)
+    } else {
+        (start, stop)
+    };
+    match query.by {
+        RangeBy::Rank => match (parse_redis_int(min), parse_redis_int(max)) {
+            (Some(start), Some(stop)) => Ok(Window::Rank(start, stop)),
+            _ => Err(RespValue::error(NOT_INT_ERROR)),
+        },
+        RangeBy::Score => match (ScoreBound::parse(min), ScoreBound::parse(max)) {
+            (Some(min), Some(max)) => Ok(Window::Score(min, max)),
+            _ => Err(RespValue::error("ERR min or max is not a float")),
+        },
+        RangeBy::Lex => match (LexBound::parse(min), LexBound::parse(max)) {
+            (Some(min), Some(max)) => Ok(Window::Lex(min, max)),
+            _ => Err(RespValue::error(
+                "ERR min or max not valid string range item",
+            )),
+        },
+    }
+}
+
+/// Members in the window with their scores, in the order the query asks for
+fn select(set: &RedisSortedSet, window: &Window, query: &RangeQuery) -> Vec<(Vec<u8>, f64)> {
+    let range: Range<usize> = match window {
+        // REV ranks count from the highest score
+        Window::Rank(start, stop) if query.rev => {
+            let reversed = set.rank_range(*start, *stop);
+            set.len() - reversed.end..set.len() - reversed.start
+        }
+        Window::Rank(start, stop) => set.rank_range(*start, *stop),
+        Window::Score(min, max) => set.score_range(*min, *max),
+        Window::Lex(min, max) => set.lex_range(min, max),
+    };
+    let entries = set.entries(range);
+    let ordered: Box<dyn Iterator<Item = (&[u8], f64)>> = if query.rev {
+        Box::new(entries.rev())
+    } else {
+        Box::new(entries)
+    };
+    let (offset, count) = match query.limit {
+        Some((offset, _)) if offset < 0 => return Vec::new(),
+        Some((offset, count)) if count >= 0 => (offset as usize, count as usize),
+        Some((offset, _)) => (offset as usize, usize::MAX),
+        None => (0, usize::MAX),
+    };
+    ordered
+        .skip(offset)
+        .take(count)
+        .map(|(member, score)| (member.to_vec(), score))
+        .collect()
+}
+
+/// RESP3 pairs each member with its score, RESP2 flattens them
+fn range_reply(entries: Vec<(Vec<u8>, f64)>, withscores: bool, protocol: Protocol) -> RespValue {
+    if !withscores {
+        return RespValue::bulk_array(entries.into_iter().map(|(member, _)| member));
+    }
+    let pairs = entries
+        .into_iter()
+        .map(|(member, score)| (RespValue::bulk(member), RespValue::Double(score)));
+    match protocol {
+        Protocol::Resp3 => RespValue::Array(
+            pairs
+                .map(|(member, score)| RespValue::Array(vec![member, score]))
+                .collect(),
+        ),
+        Protocol::Resp2 => {
+            RespValue::Array(pairs.flat_map(|(member, score)| [member, score]).collect())
+        }
+    }
+}
+
+/// ZRANGE key start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count] [WITHSCORES]
+/// and the legacy ZRANGEBYSCORE, ZREVRANGEBYSCORE, ZRANGEBYLEX, ZREVRANGEBYLEX and ZREVRANGE
+pub fn handle_zrange(
+    db: &mut RedisDatabase,
+    name: &str,
+    args: &[Vec<u8>],
+    protocol: Protocol,
+) -> RespValue {
+    let query = match parse_range_query(name, &args[3..]) {
+        Ok(query) => query,
+        Err(err) => return err,
+    };
+    let window = match parse_window(&query, &args[1], &args[2]) {
+        Ok(window) => window,
+        Err(err) => return err,
+    };
+    match db.get_sorted_set(&args[0]) {
+        Ok(Some(set)) => range_reply(select(set, &window, &query), query.withscores, protocol),
+        Ok(None) => RespValue::empty_array(),
+        Err(WrongType) => RespValue::error(WRONGTYPE_ERROR),
+    }
+}
+
+/// ZRANGESTORE dst src min max [BYSCORE|BYLEX] [REV] [LIMIT offset count]
+/// Replaces dst with the range, or deletes it when the range is empty
+pub fn handle_zrangestore(db: &mut RedisDatabase, args: &[Vec<u8>]) -> RespValue {
+    let query = match parse_range_query("zrangestore", &args[4..]) {
+        Ok(query) => query,
+        Err(err) => return err,
+    };
+    let window = match parse_window(&query, &args[2], &args[3]) {
+        Ok(window) => window,
+        Err(err) => return err,
+    };
+    let entries = match db.get_sorted_set(&args[1]) {
+        Ok(Some(set)) => select(set, &window, &query),
+        Ok(None) => Vec::new(),
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    let len = entries.len();
+    db.remove(&args[0]);
+    if len > 0 {
+        let mut set = RedisSortedSet::new();
+        for (member, score) in entries {
+            set.add(score, &member);
+        }
+        db.insert(args[0].clone(), RedisValue::new(RedisData::SortedSet(set)));
+    }
+    RespValue::Integer(len as i64)
+}
+
+/// ZADD options, which come before the first score
+#[derive(Debug, Default)]
+struct ZaddFlags {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+    ch: bool,
+    incr: bool,
+}
+
+/// ZADD key [NX|XX] [GT|LT] [CH] [INCR] score member [score member ...]
+/// Replies how many members were added, or added and changed with CH,
+/// and with INCR the new score or null when the options kept it from being updated
+pub fn handle_zadd(db: &mut RedisDatabase, args: &[Vec<u8>]) -> RespValue {
+    let mut flags = ZaddFlags::default();
+    let mut rest = &args[1..];
+    while let Some((option, tail)) = rest.split_first() {
+        match arg_str(option).to_lowercase().as_str() {
+            "nx" => flags.nx = true,
+            "xx" => flags.xx = true,
+            "gt" => flags.gt = true,
+            "lt" => flags.lt = true,
+            "ch" => flags.ch = true,
+            "incr" => flags.incr = true,
+            _ => break,
+        }
+        rest = tail;
+    }
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return RespValue::error(SYNTAX_ERROR);
+    }
+    if flags.nx && flags.xx {
+        return RespValue::error("ERR XX and NX options at the same time are not compatible");
+    }
+    if (flags.gt || flags.lt) && flags.nx || flags.gt && flags.lt {
+        return RespValue::error(
+            "ERR GT, LT, and/or NX options at the same time are not compatible",
+        );
+    }
+    if flags.incr && rest.len() > 2 {
+        return RespValue::error("ERR INCR option supports a single increment-element pair");
+    }
+    zadd(db, &args[0], &flags, rest)
+}
+
+/// ZINCRBY key increment member, ZADD INCR without the options
+pub fn handle_zincrby(db: &mut RedisDatabase, args: &[Vec<u8>]) -> RespValue {
+    let flags = ZaddFlags {
+        incr: true,
+        ..ZaddFlags::default()
+    };
+    zadd(db, &args[0], &flags, &args[1..])
+}
+
+/// ZREM key member [member ...], the key goes once its last member does
+pub fn handle_zrem(db: &mut RedisDatabase, key: &[u8], members: &[Vec<u8>]) -> RespValue {
+    let set = match db.get_sorted_set(key) {
+        Ok(Some(set)) => set,
+        Ok(None) => return RespValue::Integer(0),
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    };
+    let removed = members.iter().filter(|m| set.remove_member(m)).count();
+    if set.is_empty() {
+        db.remove(key);
+    }
+    db.dirty += removed as u64;
+    RespValue::Integer(removed as i64)
+}
+
+fn zadd(db: &mut RedisDatabase, key: &[u8], flags: &ZaddFlags, pairs: &[Vec<u8>]) -> RespValue {
+    // every score is checked before anything is added
+    let mut scores = Vec::with_capacity(pairs.len() / 2);
+    for pair in pairs.chunks_exact(2) {
+        match parse_redis_float(&pair[0]) {
+            Some(score) => scores.push((score, &pair[1])),
+            None => return RespValue::error(NOT_FLOAT_ERROR),
+        }
+    }
+    let skipped = if flags.incr {
+        RespValue::Null
+    } else {
+        RespValue::Integer(0)
+    };
+    // XX only updates, so a missing key is not created
+    match db.get_sorted_set(key) {
+        Ok(None) if flags.xx => return skipped,
+        Ok(_) => {}
+        Err(WrongType) => return RespValue::error(WRONGTYPE_ERROR),
+    }
+    let Ok(set) = db.get_or_create_sorted_set(key) else {
+        return RespValue::error(WRONGTYPE_ERROR);
+    };
+    let (mut added, mut changed) = (0, 0);
+    let mut incr_score = None;
+    for (score, member) in scores {
+        match set.get_member(member).copied() {
+            Some(current) => {
+                if flags.nx {
+                    continue;
+                }
+                let new_score = if flags.incr { current + score } else { score };
+                if new_score.is_nan() {
+                    return RespValue::error("ERR resulting score is not a number (NaN)");
+                }
+                if flags.gt && new_score <= current || flags.lt && new_score >= current {
+                    continue;
+                }
+                if new_score != current {
+                    set.add(new_score, member);
+                    changed += 1;
+                }
+                incr_score = Some(new_score);
+            }
+            None if flags.xx => {}
+            None => {
+                set.add(score, member);
+                added += 1;
+                incr_score = Some(score);
+            }
+        }
+    }
+    db.dirty += (added + changed) as u64;
+    if flags.incr {
+        incr_score.map_or(skipped, RespValue::Double)
+    } else if flags.ch {
+        RespValue::Integer(added + changed)
+    } else {
+        RespValue::Integer(added)
+    }
+}
@@ -1,10 +1,14 @@
 pub mod constants;
 pub mod entry_stream;
+pub mod event_loop;
 pub mod handler;
 pub mod redis_channel;
 pub mod redis_connection;
 pub mod redis_database;
+pub mod redis_hash;
 pub mod redis_list;
+pub mod redis_set;
 pub mod redis_sorted_set;
-pub mod threadpool;
+pub mod resp;
+pub mod server;
 pub mod utils;
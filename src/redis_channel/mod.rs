@@ -1,34 +1,57 @@
-use std::net::TcpStream;
+use std::collections::HashMap;
+
+use crate::resp::RespValue;
+
+/// Every channel along with the messages that still have to reach their subscribers
+#[derive(Debug, Default)]
+pub struct PubSub {
+    pub channels: HashMap<Vec<u8>, Channel>,
+    /// Messages by client id, the event loop queues them on the subscribers' connections
+    pub outbox: Vec<(u64, RespValue)>,
+}
+
+impl PubSub {
+    /// Queues the message for every subscriber of the channel and returns how many there are
+    pub fn publish(&mut self, chan_name: &[u8], msg: &[u8]) -> usize {
+        let Some(chan) = self.channels.get(chan_name) else {
+            return 0;
+        };
+        let message = RespValue::Push(vec![
+            RespValue::bulk("message"),
+            RespValue::bulk(chan_name),
+            RespValue::bulk(msg),
+        ]);
+        for sb in &chan.subscribers {
+            self.outbox.push((sb.id, message.clone()));
+        }
+        chan.subscribers.len()
+    }
+
+    /// Drops a client that went away from every channel it subscribed to
+    pub fn unsubscribe_all(&mut self, id: u64) {
+        for chan in self.channels.values_mut() {
+            chan.subscribers.retain(|sb| sb.id != id);
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Channel {
-    pub name: String,
-    //pub subscribers: Vec<Subscriber>,
-    pub subscribers: Vec<TcpStream>,
+    pub name: Vec<u8>,
+    pub subscribers: Vec<Subscriber>,
 }
 
 impl Channel {
-    pub fn new(name: &String) -> Self {
+    pub fn new(name: &[u8]) -> Self {
         Channel {
-            name: name.clone(),
+            name: name.to_vec(),
             subscribers: Vec::new(),
         }
     }
 }
 
-//#[derive(Debug, Clone)]
-//pub struct Subscriber {
-//    pub sub_id: String,
-//    //pub channels: Vec<Channel>,
-//    // store channel names
-//    pub channel_count: Vec<String>,
-//}
-//
-//impl Subscriber {
-//    pub fn new(port: String) -> Self {
-//        Subscriber {
-//            sub_id: port,
-//            channel_count: 0,
-//        }
-//    }
-//}
+/// A subscribed client, messages are encoded in the protocol its connection negotiated
+#[derive(Debug)]
+pub struct Subscriber {
+    pub id: u64,
+}
@@ -1,24 +1,30 @@
-use std::net::TcpStream;
 use std::time::SystemTime;
-use std::usize;
 
-use crate::redis_connection::RedisConnection;
+use crate::resp::RespValue;
 
 #[derive(Debug)]
 pub struct BroadCastInfo {
-    pub connections: Vec<RedisConnection>,
-    next_id: usize,
+    /// Client ids of the replica links, the event loop serves them like any other client
+    pub replicas: Vec<u64>,
+    /// Commands by replica id, the event loop queues them on the replica connections
+    pub outbox: Vec<(u64, RespValue)>,
     pub ports: Vec<String>,
     pub num_waiting_for: usize,
     pub waiting_until: SystemTime,
     pub num_acks: usize,
 }
 
+impl Default for BroadCastInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BroadCastInfo {
     pub fn new() -> Self {
         BroadCastInfo {
-            connections: Vec::new(),
-            next_id: 0,
+            replicas: Vec::new(),
+            outbox: Vec::new(),
             ports: Vec::new(),
             num_waiting_for: 0,
             num_acks: 0,
@@ -26,17 +32,18 @@ impl BroadCastInfo {
         }
     }
 
-    //pub fn add_connection(&mut self, stream: Arc<Mutex<TcpStream>>) -> usize {
-    pub fn add_connection(&mut self, stream: TcpStream) -> usize {
-        let id = self.next_id;
-        self.connections.push(RedisConnection::new(stream));
-        self.next_id += 1;
-        id
+    pub fn add_replica(&mut self, id: u64) {
+        self.replicas.push(id);
     }
-    pub fn broadcast_command(&mut self, command: &[String]) {
-        for conn in &mut self.connections {
-            conn.broadcast_command(command)
-            // Handle disconnection if needed
+
+    pub fn remove_replica(&mut self, id: u64) {
+        self.replicas.retain(|r| *r != id);
+    }
+
+    pub fn broadcast_command(&mut self, command: &[Vec<u8>]) {
+        let value = RespValue::bulk_array(command);
+        for id in &self.replicas {
+            self.outbox.push((*id, value.clone()));
         }
     }
 }
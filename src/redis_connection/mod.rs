@@ -1,13 +1,21 @@
 use std::io::{prelude::*, Write};
 use std::net::TcpStream;
-use std::usize;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use std::io::ErrorKind;
 
+use crate::resp::decode::{read_line, FrameScanner};
+use crate::resp::inline::decode_inline;
+use crate::resp::{Protocol, RespError, RespValue};
+
 pub mod broadcast_info;
 
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug)]
 pub struct RedisConnection {
+    pub id: u64,
+    pub name: Option<Vec<u8>>,
     pub stream: TcpStream,
     pub buffer: Vec<u8>,
     pub position: usize,
@@ -18,6 +26,10 @@ pub struct RedisConnection {
     pub in_sub_mode: bool,
     //pub subbed_channels: Vec<String>,
     pub num_channels: usize,
+    pub protocol: Protocol,
+    write_buf: Vec<u8>,
+    // progress through a RESP value that has not fully arrived yet
+    frames: FrameScanner,
 }
 
 impl RedisConnection {
@@ -25,7 +37,9 @@ impl RedisConnection {
     pub fn new(stream: TcpStream) -> Self {
         stream.set_nonblocking(true).unwrap();
         RedisConnection {
-            stream: stream,
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            name: None,
+            stream,
             buffer: Vec::new(),
             position: 0,
             offset: 0,
@@ -35,260 +49,153 @@ impl RedisConnection {
             in_sub_mode: false,
             //subbed_channels: Vec::new(),
             num_channels: 0,
+            protocol: Protocol::default(),
+            write_buf: Vec::new(),
+            frames: FrameScanner::default(),
         }
     }
 
-    pub fn try_read_command(&mut self) -> std::io::Result<Option<Vec<Vec<String>>>> {
-        // Read available data
-
-        ////eprintln!("stream in read");
-        let mut temp_buf = [0; 4096];
-
-        match self.stream.read(&mut temp_buf) {
-            Ok(0) => {
-                //std::thread::sleep(Duration::from_millis(5));
-                return Ok(None);
-            }
-            Ok(n) => {
-                eprintln!(
-                    "Found {n} bytes, {:?}",
-                    String::from_utf8(temp_buf[..n].into())
-                );
-                //if !self.is_master {
-                self.prev_offset = self.offset + 0;
-                self.offset += n;
-                eprintln!("current offset:{}", self.offset);
-                //}
-                self.buffer.extend_from_slice(&temp_buf[..n]);
-            }
-            Err(e) if e.kind() == ErrorKind::WouldBlock => {
-                return Ok(None);
-            }
+    /// Reads whatever the socket has ready into the buffer and parses every complete command
+    /// Used for the replica handshake, the event loop reads with read_available instead
+    pub fn try_read_command(&mut self) -> std::io::Result<Option<Vec<Vec<Vec<u8>>>>> {
+        match self.read_available() {
+            Ok(true) => {}
+            //std::thread::sleep(Duration::from_millis(5));
+            Ok(false) => return Ok(None),
             Err(e) => {
                 //eprintln!("BIG ERROR:{e}");
                 return Err(e);
             }
         }
-        //eprintln!("AFTER stream in read");
 
         // Parse complete commands from buffer
-        self.parse_buffer()
+        let mut commands = Vec::new();
+        while let Some(command) = self.next_command()? {
+            commands.push(command);
+        }
+        Ok(Some(commands))
     }
 
-    fn parse_buffer(&mut self) -> std::io::Result<Option<Vec<Vec<String>>>> {
-        eprintln!(
-            "buffer as str:{:?}",
-            String::from_utf8_lossy(&self.buffer[self.position..])
-        );
-        let mut commands = Vec::new();
+    /// Drains the socket into the read buffer until it would block
+    /// Returns false once the peer has closed its end
+    pub fn read_available(&mut self) -> std::io::Result<bool> {
+        // drop what has been parsed so the buffer does not grow forever
+        if self.position > 0 {
+            self.buffer.drain(..self.position);
+            self.position = 0;
+        }
 
+        let mut temp_buf = [0; 4096];
         loop {
-            eprintln!(
-                "\n\n LOOP: handling buffer starting at pos:{}\n\n",
-                self.position
-            );
-            eprintln!(
-                "buffer as str:{:?}",
-                String::from_utf8_lossy(&self.buffer[self.position..])
-            );
-            let mut lines = self.buffer[self.position..].split(|&b| b == b'\n');
+            match self.stream.read(&mut temp_buf) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.buffer.extend_from_slice(&temp_buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
 
-            while let Some(line) = lines.next() {
-                if line.is_empty() {
-                    eprintln!("empty_line:{:?}", line);
-                    continue;
+    /// Decodes the next complete command in the buffer into its arguments
+    /// Clients may send RESP arrays or inline commands, the master only sends RESP
+    /// An incomplete command is left in the buffer until the rest of it arrives
+    /// The replication offset counts every byte consumed, so it matches what the master sent
+    pub fn next_command(&mut self) -> std::io::Result<Option<Vec<Vec<u8>>>> {
+        loop {
+            let buf = &self.buffer[self.position..];
+            match buf.first() {
+                Some(b'$') if self.is_master => {
+                    // AT START OF RDB TRANSFER, the payload has no trailing CRLF
+                    eprintln!("ACTUAL RDB SECTION");
+                    let Some((header, header_len)) = read_line(buf) else {
+                        return Ok(None);
+                    };
+                    let rdb_len = std::str::from_utf8(&header[1..])
+                        .ok()
+                        .and_then(|d| d.parse::<usize>().ok())
+                        .ok_or_else(|| RespError("invalid RDB length".to_string()))?;
+                    eprintln!("found length {rdb_len}");
+                    if buf.len() < header_len + rdb_len {
+                        return Ok(None);
+                    }
+                    // Skip RDB data
+                    self.position += header_len + rdb_len;
+                    eprintln!("POSITION AFTER RDB:{}", self.position);
+
+                    //reset offset after handshake
+                    self.prev_offset = self.offset;
+                    self.offset = 0;
+                    eprintln!("after reset OFFSET:{}\n\n", self.offset);
                 }
-                self.position += line.len() + 1;
-                let line_str = match String::from_utf8(line.to_vec()) {
-                    Ok(s) => s,
-                    Err(_) => {
-                        continue;
-                    } //if not valid utf8 keep going
-                };
-
-                match line_str.chars().next() {
-                    // Resp array section
-                    Some('*') => {
-                        let arr_length = match line_str[1..].trim().parse::<usize>() {
-                            Ok(n) => n,
-                            Err(_) => {
-                                continue;
-                            }
-                        };
-                        let mut elements = Vec::with_capacity(arr_length);
-                        let mut valid = true;
-
-                        for _ in 0..arr_length {
-                            let size_line = match lines.next() {
-                                Some(line) => match String::from_utf8(line.into()) {
-                                    Ok(s) => s,
-                                    Err(_) => {
-                                        valid = false;
-                                        break;
-                                    }
-                                },
-                                None => {
-                                    valid = false;
-                                    break;
-                                }
-                            };
-
-                            //NOTE: might need to add + 1 for \n
-                            self.position += line.len();
-
-                            if !size_line.starts_with('$') {
-                                valid = false;
-                                break;
-                            }
-
-                            let size = match size_line[1..].trim().parse::<usize>() {
-                                Ok(n) => n,
-                                Err(_) => {
-                                    valid = false;
-                                    break;
-                                }
-                            };
-
-                            let mut content = match lines.next() {
-                                Some(line) => {
-                                    self.position += line.len() + 1;
-
-                                    match String::from_utf8(line.to_vec()) {
-                                        Ok(s) => s,
-                                        Err(_) => {
-                                            valid = false;
-                                            break;
-                                        }
-                                    }
-                                }
-                                None => {
-                                    valid = false;
-                                    break;
-                                }
-                            };
-
-                            content = content.trim().to_string();
-                            ////eprintln!("GOT content:{:?}", content);
-                            //RESP ARRAY DECODED WRONG
-                            if content.len() != size {
-                                //    //eprintln!("breaking because content is not the same size");
-                                break;
-                            }
-
-                            elements.push(content.to_string());
+                Some(&first) if first == b'*' || self.is_master => match self.frames.decode(buf)? {
+                    Some((value, consumed)) => {
+                        self.consume(consumed);
+                        match value {
+                            RespValue::Array(items) => return Ok(Some(command_args(items)?)),
+                            // replies from the master during the handshake, e.g. +FULLRESYNC
+                            other => eprintln!("ignoring non command value {other:?}"),
                         }
-                        if valid && elements.len() == arr_length {
-                            commands.push(elements);
-                        }
-                        //eprintln!(
-                        //     "end of resp section, buf len:{}, pos:{}",
-                        //     self.buffer.len(),
-                        //     self.position
-                        // );
-                    }
-
-                    Some('$') => {
-                        // AT START OF RDB TRANSFER
-                        eprintln!("ACTUAL RDB SECTION");
-                        let rdb_len = match line_str[1..].trim().parse::<usize>() {
-                            Ok(n) => n,
-                            Err(_) => {
-                                continue;
-                            }
-                        };
-                        // Skip RDB data
-
-                        eprintln!("found length {rdb_len}");
-                        let rdb_start = self.position; // + line_str.len() + 2; // +1 for \r
-                                                       //eprintln!("after, pos:{}", self.position);
-                        let rdb_end = rdb_start + rdb_len;
-                        eprintln!(
-                            "rdb start:{rdb_start} rdb_end:{rdb_end}, buffer length:{}",
-                            self.buffer.len()
-                        );
-
-                        let _rdb_bytes: Vec<_> = self.buffer[rdb_start..rdb_end].into();
-                        //eprintln!(
-                        //    "PARSED RDB IN STR:{:?}",
-                        //    String::from_utf8_lossy(&rdb_bytes)
-                        //);
-                        //self.decode_rdb(rdb_bytes);
-
-                        eprintln!("POSITION AFTER RDB:{}", self.position);
-                        self.position = rdb_end;
-
-                        eprintln!("\n\nafter rdb OFFSET:{}", self.offset);
-                        //reset offset after handshake
-                        self.offset = self.buffer.len() - self.position;
-                        eprintln!("after reset OFFSET:{}\n\n", self.offset);
-
-                        break;
                     }
-                    Some(_) => {
-                        continue;
+                    None => return Ok(None),
+                },
+                Some(_) => match decode_inline(buf)? {
+                    Some((args, consumed)) => {
+                        self.consume(consumed);
+                        // empty lines are ignored like redis-server does
+                        if !args.is_empty() {
+                            return Ok(Some(args));
+                        }
                     }
-                    _ => continue,
-                }
-            }
-
-            if (self.buffer.len() as i32) - (self.position as i32) <= 1 {
-                eprintln!(
-                    "breaking with length:{}, curr pos:{}",
-                    self.buffer.len(),
-                    self.position
-                );
-                break;
-            } else {
-                eprintln!(
-                    "LOOPING AGAIN len:{}, pos:{}",
-                    self.buffer.len(),
-                    self.position
-                );
+                    None => return Ok(None),
+                },
+                None => return Ok(None),
             }
         }
-        //eprintln!(
-        //    "AFTER PARSE commands?{:?}, pos:{}, buffer len:{}",
-        //    commands,
-        //    self.position,
-        //    self.buffer.len()
-        //);
+    }
 
-        Ok(Some(commands))
+    fn consume(&mut self, n: usize) {
+        self.position += n;
+        self.prev_offset = self.offset;
+        self.offset += n;
     }
 
-    pub fn broadcast_command(&mut self, command: &[String]) {
-        let s: Vec<&str> = command.iter().map(|e| e.as_str()).collect();
-        let resp = self.format_resp_array(&s);
-        self.write_to_stream(resp.as_bytes());
+    /// Encodes a reply in the protocol this connection speaks into the write buffer
+    /// Nothing is sent until the buffer is flushed
+    pub fn write_value(&mut self, value: &RespValue) {
+        value.encode(self.protocol, &mut self.write_buf);
     }
 
-    pub fn format_resp_array(&self, elements: &[&str]) -> String {
-        let mut resp = format!("*{}\r\n", elements.len()); //.into_bytes();
-        for element in elements {
-            //resp.extend(format!("${}\r\n{}\r\n", element.len(), element).into_bytes());
-            resp.push_str(&format!("${}\r\n{}\r\n", element.len(), element));
-        }
-        resp
+    /// Queues raw bytes, like the RDB payload, sent along with the replies
+    pub fn write_raw(&mut self, buf: &[u8]) {
+        self.write_buf.extend_from_slice(buf);
     }
 
-    pub fn write_to_stream(&mut self, buf: &[u8]) {
-        //eprintln!(" stream in write");
-        if let Ok(r) = String::from_utf8(buf.into()) {
-            eprintln!("writing to stream: {r}")
-        } else {
-            eprintln!("WRITING RDB");
-        };
-        self.stream
-            .write_all(&buf)
-            .expect("in RespConn failed to write to steam");
-        eprintln!("AFTER STREAM in write");
-        //NOTE: removed int debugging xread block 0
-        //
-        // sleep(Duration::from_millis(5));
+    /// Writes as much of the write buffer as the socket accepts without blocking
+    /// Whatever is left stays buffered until the socket is writable again
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        let mut written = 0;
+        while written < self.write_buf.len() {
+            match self.stream.write(&self.write_buf[written..]) {
+                Ok(0) => {
+                    self.write_buf.drain(..written);
+                    return Err(ErrorKind::WriteZero.into());
+                }
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.write_buf.drain(..written);
+                    return Err(e);
+                }
+            }
+        }
+        self.write_buf.drain(..written);
+        Ok(())
     }
 
-    pub fn get_simple_str(&self, s: &str) -> String {
-        format!("+{s}\r\n")
+    pub fn has_pending_output(&self) -> bool {
+        !self.write_buf.is_empty()
     }
 
     // pub fn decode_rdb(&self, received_rdb: Vec<u8>) {
@@ -311,3 +218,17 @@ impl RedisConnection {
     //     //eprintln!("RECEIVED RDB:{:?}", final_rdb);
     // }
 }
+
+/// Flattens a decoded command array into its arguments
+/// Clients send bulk strings, anything else is taken by its text
+fn command_args(items: Vec<RespValue>) -> Result<Vec<Vec<u8>>, RespError> {
+    items
+        .into_iter()
+        .map(|item| match item {
+            RespValue::BulkString(b) => Ok(b),
+            RespValue::SimpleString(s) => Ok(s.into_bytes()),
+            RespValue::Integer(n) => Ok(n.to_string().into_bytes()),
+            other => Err(RespError(format!("unexpected argument {other:?}"))),
+        })
+        .collect()
+}
@@ -0,0 +1,448 @@
+/*
+* Clients blocked on keys
+*
+* BLPOP, XREAD BLOCK and friends park the client here, queued under every key it waits on.
+* Creating a key someone waits on, or adding to a stream, marks it ready, and once the command
+* that did it is done the ready keys are served in the order they became ready, the longest
+* waiting client first.
+*
+* What a blocked client gets, served or timed out, goes to an outbox the event loop delivers to
+* the connection, the registry itself never touches a socket. Deadlines sit in a timer wheel so
+* the event loop can sleep until the next one instead of polling.
+*/
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::redis_database::RedisDatabase;
+use crate::resp::RespValue;
+
+/// What a blocked client gets once one of its keys could be served
+#[derive(Debug, Clone)]
+pub struct Served {
+    pub reply: RespValue,
+    /// The command replicas are sent in place of the blocking one, e.g. LPOP for BLPOP
+    pub propagate: Option<Vec<Vec<u8>>>,
+}
+
+/// Tries the blocked operation on a key that became ready, None to keep waiting
+pub type ServeFn = Box<dyn FnMut(&mut RedisDatabase, &[u8]) -> Option<Served> + Send>;
+
+struct BlockedClient {
+    keys: Vec<Vec<u8>>,
+    deadline: Option<Instant>,
+    timeout_reply: RespValue,
+    serve: ServeFn,
+}
+
+pub struct BlockedClients {
+    clients: HashMap<u64, BlockedClient>,
+    /// Client ids waiting on each key, longest waiting first
+    queues: HashMap<Vec<u8>, VecDeque<u64>>,
+    ready: VecDeque<Vec<u8>>,
+    ready_set: HashSet<Vec<u8>>,
+    timers: TimerWheel,
+    outbox: Vec<(u64, RespValue)>,
+}
+
+impl Default for BlockedClients {
+    fn default() -> Self {
+        BlockedClients {
+            clients: HashMap::new(),
+            queues: HashMap::new(),
+            ready: VecDeque::new(),
+            ready_set: HashSet::new(),
+            timers: TimerWheel::new(Instant::now()),
+            outbox: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for BlockedClients {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockedClients")
+            .field("clients", &self.clients.keys().collect::<Vec<_>>())
+            .field("queues", &self.queues)
+            .field("ready", &self.ready)
+            .finish()
+    }
+}
+
+impl BlockedClients {
+    /// Parks the client on keys until `serve` succeeds on one of them or the deadline passes
+    pub fn block(
+        &mut self,
+        id: u64,
+        keys: &[Vec<u8>],
+        deadline: Option<Instant>,
+        timeout_reply: RespValue,
+        serve: ServeFn,
+    ) {
+        let mut unique = Vec::new();
+        for key in keys {
+            if !unique.contains(key) {
+                unique.push(key.clone());
+                self.queues.entry(key.clone()).or_default().push_back(id);
+            }
+        }
+        if let Some(deadline) = deadline {
+            self.timers.insert(deadline, id);
+        }
+        self.clients.insert(
+            id,
+            BlockedClient {
+                keys: unique,
+                deadline,
+                timeout_reply,
+                serve,
+            },
+        );
+    }
+
+    pub fn is_blocked(&self, id: u64) -> bool {
+        self.clients.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Marks key as worth serving, a no-op when nobody waits on it
+    pub fn signal(&mut self, key: &[u8]) {
+        if self.queues.contains_key(key) && self.ready_set.insert(key.to_vec()) {
+            self.ready.push_back(key.to_vec());
+        }
+    }
+
+    /// Wakes the client up early with `reply`, or as if it timed out when there is none
+    /// Returns false when the client was not blocked
+    pub fn unblock(&mut self, id: u64, reply: Option<RespValue>) -> bool {
+        match self.remove(id) {
+            Some(client) => {
+                let reply = reply.unwrap_or(client.timeout_reply);
+                self.outbox.push((id, reply));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drops a client that disconnected, so nothing is popped for it anymore
+    pub fn forget_client(&mut self, id: u64) {
+        self.remove(id);
+        self.outbox.retain(|(waiting, _)| *waiting != id);
+    }
+
+    /// Takes the client out of every queue it is in and cancels its deadline
+    fn remove(&mut self, id: u64) -> Option<BlockedClient> {
+        let client = self.clients.remove(&id)?;
+        self.forget(id, &client);
+        Some(client)
+    }
+
+    fn forget(&mut self, id: u64, client: &BlockedClient) {
+        for key in &client.keys {
+            if let Some(queue) = self.queues.get_mut(key) {
+                queue.retain(|waiting| *waiting != id);
+                if queue.is_empty() {
+                    self.queues.remove(key);
+                }
+            }
+        }
+        if let Some(deadline) = client.deadline {
+            self.timers.cancel(deadline, id);
+        }
+    }
+
+    /// Moves every client whose deadline passed to the outbox with its timeout reply
+    pub fn expire(&mut self, now: Instant) {
+        for id in self.timers.expire(now) {
+            if let Some(client) = self.remove(id) {
+                self.outbox.push((id, client.timeout_reply));
+            }
+        }
+    }
+
+    /// When the event loop has to wake up for the next timeout
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.next_expiry()
+    }
+
+    /// Replies for clients that are no longer blocked, by client id
+    pub fn take_unblocked(&mut self) -> Vec<(u64, RespValue)> {
+        std::mem::take(&mut self.outbox)
+    }
+}
+
+impl RedisDatabase {
+    /// Serves the clients waiting on keys that became ready, first come first served
+    /// A client that can't be served keeps its place without holding up the others
+    /// Returns the commands to propagate for what was served
+    pub fn serve_blocked(&mut self) -> Vec<Vec<Vec<u8>>> {
+        let mut propagate = Vec::new();
+        while let Some(key) = self.blocked.ready.pop_front() {
+            self.blocked.ready_set.remove(&key);
+            let waiting: Vec<u64> = self
+                .blocked
+                .queues
+                .get(&key)
+                .map(|queue| queue.iter().copied().collect())
+                .unwrap_or_default();
+            for id in waiting {
+                // an exhausted key has nothing left for the clients further back
+                if !self.contains_key(&key) {
+                    break;
+                }
+                // the client is taken out while it runs since serving needs the whole database
+                let Some(mut client) = self.blocked.clients.remove(&id) else {
+                    continue;
+                };
+                match (client.serve)(self, &key) {
+                    Some(served) => {
+                        self.blocked.forget(id, &client);
+                        self.blocked.outbox.push((id, served.reply));
+                        propagate.extend(served.propagate);
+                    }
+                    // keeps its place, the ones behind it may still be served
+                    None => {
+                        self.blocked.clients.insert(id, client);
+                    }
+                }
+            }
+        }
+        propagate
+    }
+}
+
+/// Width of a timer wheel slot
+const TICK: Duration = Duration::from_millis(1);
+
+/// Slots in the wheel, deadlines further out than a full turn wait in their slot for more turns
+const SLOTS: u64 = 1024;
+
+/// Hashed timer wheel, a deadline goes in the slot of its tick so inserting and cancelling only
+/// touch that slot, and expiring walks the slots passed since the last call
+struct TimerWheel {
+    origin: Instant,
+    slots: Vec<Vec<(Instant, u64)>>,
+    /// Last tick that was expired
+    current: u64,
+    len: usize,
+}
+
+impl TimerWheel {
+    fn new(origin: Instant) -> Self {
+        TimerWheel {
+            origin,
+            slots: vec![Vec::new(); SLOTS as usize],
+            current: 0,
+            len: 0,
+        }
+    }
+
+    /// Tick a deadline fires on, rounded up so nothing fires early
+    fn tick_of(&self, at: Instant) -> u64 {
+        let nanos = at.saturating_duration_since(self.origin).as_nanos();
+        nanos.div_ceil(TICK.as_nanos()) as u64
+    }
+
+    fn slot(tick: u64) -> usize {
+        (tick % SLOTS) as usize
+    }
+
+    fn insert(&mut self, deadline: Instant, id: u64) {
+        // a deadline already due fires on the next expire
+        let tick = self.tick_of(deadline).max(self.current + 1);
+        self.slots[Self::slot(tick)].push((deadline, id));
+        self.len += 1;
+    }
+
+    fn cancel(&mut self, deadline: Instant, id: u64) {
+        let tick = self.tick_of(deadline).max(self.current + 1);
+        let slot = &mut self.slots[Self::slot(tick)];
+        if let Some(pos) = slot.iter().position(|e| *e == (deadline, id)) {
+            slot.swap_remove(pos);
+            self.len -= 1;
+        }
+    }
+
+    /// Ids whose deadline is at or before now
+    fn expire(&mut self, now: Instant) -> Vec<u64> {
+        // only ticks that are fully over, a slot holds deadlines up to the end of its tick
+        let now_tick =
+            (now.saturating_duration_since(self.origin).as_nanos() / TICK.as_nanos()) as u64;
+        let mut expired = Vec::new();
+        if self.len > 0 && now_tick > self.current {
+            // past a full turn every slot has been passed at least once
+            let passed = (now_tick - self.current).min(SLOTS);
+            for tick in now_tick + 1 - passed..=now_tick {
+                let slot = &mut self.slots[Self::slot(tick)];
+                let mut i = 0;
+                while i < slot.len() {
+                    if slot[i].0 <= now {
+                        expired.push(slot.swap_remove(i).1);
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            self.len -= expired.len();
+        }
+        self.current = self.current.max(now_tick);
+        expired
+    }
+
+    /// Start of the first slot ahead holding a deadline, which may be for a later turn
+    fn next_expiry(&self) -> Option<Instant> {
+        if self.len == 0 {
+            return None;
+        }
+        let tick = (self.current + 1..=self.current + SLOTS)
+            .find(|tick| !self.slots[Self::slot(*tick)].is_empty())?;
+        Some(self.origin + Duration::from_nanos(tick * TICK.as_nanos() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis_database::{RedisData, RedisValue};
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn timers_fire_once_their_deadline_passed() {
+        let origin = Instant::now();
+        let mut wheel = TimerWheel::new(origin);
+        wheel.insert(origin + ms(5), 1);
+        wheel.insert(origin + ms(3), 2);
+        wheel.insert(origin + Duration::from_micros(5500), 3);
+        assert_eq!(wheel.next_expiry(), Some(origin + ms(3)));
+
+        assert!(wheel.expire(origin + ms(2)).is_empty());
+        assert_eq!(wheel.expire(origin + ms(3)), [2]);
+        assert_eq!(wheel.expire(origin + ms(5)), [1]);
+        // rounded up to its tick, so not early within the tick either
+        assert!(wheel
+            .expire(origin + Duration::from_micros(5900))
+            .is_empty());
+        assert_eq!(wheel.expire(origin + ms(6)), [3]);
+        assert_eq!(wheel.next_expiry(), None);
+    }
+
+    #[test]
+    fn cancelled_timers_never_fire() {
+        let origin = Instant::now();
+        let mut wheel = TimerWheel::new(origin);
+        wheel.insert(origin + ms(4), 1);
+        wheel.insert(origin + ms(4), 2);
+        wheel.cancel(origin + ms(4), 1);
+        wheel.cancel(origin + ms(4), 7);
+        assert_eq!(wheel.expire(origin + ms(10)), [2]);
+        assert_eq!(wheel.len, 0);
+    }
+
+    #[test]
+    fn deadlines_past_a_full_turn_wait_for_it() {
+        let origin = Instant::now();
+        let mut wheel = TimerWheel::new(origin);
+        let far = origin + ms(SLOTS * 2 + 7);
+        wheel.insert(far, 1);
+        // same slot as the far deadline, a turn too early
+        assert!(wheel.expire(origin + ms(SLOTS + 7)).is_empty());
+        assert!(wheel.expire(far - ms(1)).is_empty());
+        assert_eq!(wheel.expire(far), [1]);
+    }
+
+    #[test]
+    fn overdue_deadlines_fire_on_the_next_expire() {
+        let origin = Instant::now();
+        let mut wheel = TimerWheel::new(origin);
+        wheel.expire(origin + ms(50));
+        wheel.insert(origin + ms(10), 1);
+        assert_eq!(wheel.next_expiry(), Some(origin + ms(51)));
+        assert_eq!(wheel.expire(origin + ms(51)), [1]);
+    }
+
+    fn serve_with(result: Option<RespValue>) -> ServeFn {
+        Box::new(move |_, _| {
+            result.clone().map(|reply| Served {
+                reply,
+                propagate: None,
+            })
+        })
+    }
+
+    fn create(db: &mut RedisDatabase, key: &[u8]) {
+        db.insert(
+            key.to_vec(),
+            RedisValue::new(RedisData::String(b"v".to_vec())),
+        );
+    }
+
+    #[test]
+    fn unserved_clients_do_not_hold_up_the_queue() {
+        let mut db = RedisDatabase::new();
+        let keys = [b"k".to_vec()];
+        db.blocked
+            .block(1, &keys, None, RespValue::Null, serve_with(None));
+        let served = Some(RespValue::Integer(2));
+        db.blocked
+            .block(2, &keys, None, RespValue::Null, serve_with(served));
+        create(&mut db, &keys[0]);
+        db.serve_blocked();
+
+        assert_eq!(db.blocked.take_unblocked(), [(2, RespValue::Integer(2))]);
+        assert!(db.blocked.is_blocked(1));
+        assert_eq!(db.blocked.queues[&keys[0]], [1]);
+    }
+
+    #[test]
+    fn serving_stops_once_the_key_is_gone() {
+        let mut db = RedisDatabase::new();
+        let keys = [b"k".to_vec()];
+        for id in 1..=3 {
+            let take = Box::new(move |db: &mut RedisDatabase, key: &[u8]| {
+                db.remove(key).map(|_| Served {
+                    reply: RespValue::Integer(id as i64),
+                    propagate: Some(vec![b"DEL".to_vec(), key.to_vec()]),
+                })
+            });
+            db.blocked.block(id, &keys, None, RespValue::Null, take);
+        }
+        create(&mut db, &keys[0]);
+        let propagate = db.serve_blocked();
+
+        assert_eq!(propagate, [vec![b"DEL".to_vec(), keys[0].clone()]]);
+        assert_eq!(db.blocked.take_unblocked(), [(1, RespValue::Integer(1))]);
+        assert_eq!(db.blocked.len(), 2);
+    }
+
+    #[test]
+    fn timed_out_clients_get_their_timeout_reply() {
+        let mut db = RedisDatabase::new();
+        let deadline = Instant::now() + ms(5);
+        db.blocked.block(
+            1,
+            &[b"a".to_vec(), b"b".to_vec()],
+            Some(deadline),
+            RespValue::NullArray,
+            serve_with(None),
+        );
+        // the loop sleeps until the next deadline, which is never before the real one
+        let wake_up = db.blocked.next_deadline().unwrap();
+        assert!(wake_up >= deadline);
+        db.blocked.expire(wake_up - Duration::from_nanos(1));
+        assert!(db.blocked.take_unblocked().is_empty());
+        db.blocked.expire(wake_up);
+        assert_eq!(db.blocked.take_unblocked(), [(1, RespValue::NullArray)]);
+        assert!(db.blocked.is_empty());
+        assert!(db.blocked.queues.is_empty());
+    }
+}
@@ -13,8 +13,12 @@ Here's a more formal description of how each key-value pair is stored:
     Key (string encoded)
     Value (encoding depends on value type)
 * */
-use crate::redis_database::encoding::{read_string, write_string};
-use crate::redis_database::{Expiration, RdbError, RedisDatabase, RedisValue, Result};
+use crate::redis_database::encoding::{read_size, read_string, write_size, write_string};
+use crate::redis_database::{Expiration, RdbError, RedisData, RedisDatabase, RedisValue, Result};
+use crate::redis_hash::RedisHash;
+use crate::redis_list::RedisList;
+use crate::redis_set::RedisSet;
+use crate::redis_sorted_set::RedisSortedSet;
 use std::io::{Read, Write};
 
 pub const DB_SELECTOR: u8 = 0xFE;
@@ -23,6 +27,12 @@ pub const EXPIRY_MILLISECONDS: u8 = 0xFC;
 pub const RESIZEDB: u8 = 0xFB;
 pub const EOF: u8 = 0xFF;
 pub const STRING_TYPE: u8 = 0x00;
+pub const LIST_TYPE: u8 = 0x01;
+pub const SET_TYPE: u8 = 0x02;
+pub const ZSET_TYPE: u8 = 0x03;
+pub const HASH_TYPE: u8 = 0x04;
+pub const ZSET_2_TYPE: u8 = 0x05;
+pub const INTSET_TYPE: u8 = 0x0B;
 
 pub fn read_db<R: Read>(reader: &mut R) -> Result<(RedisDatabase, bool)> {
     //eprintln!("READING DB");
@@ -36,22 +46,15 @@ pub fn read_db<R: Read>(reader: &mut R) -> Result<(RedisDatabase, bool)> {
     //if we're resizing, skip hash table sizes
     if buf[0] == RESIZEDB {
         //eprintln!("IN RESIZE OPERATION, skipping bytes");
-        //let num_keys = read_bytes(reader)?; // keys size
-        reader.read_exact(&mut buf)?;
-        //eprintln!("read db, numkeys in hex: {:#04X?}:", buf[0],);
-        reader.read_exact(&mut buf)?;
-        //eprintln!(" numexpiry in hex {:#04X?}", buf[0]);
-        //let num_expiry = read_bytes(reader)?; // expires size
-        // //eprintln!(
-        //     "read db, numkeys in hex: {:#04X?}, numexpiry in hex {:#04X?}",
-        //     num_keys, num_expiry
-        // );
+        read_size(reader)?; // keys size
+        read_size(reader)?; // expires size
         reader.read_exact(&mut buf)?;
     }
 
     let mut reached_eof = false;
     loop {
         //eprintln!("IN READ DB buf[0]: {:#04X?}", buf[0]);
+        let mut expires_at = None;
         match buf[0] {
             /* The expire timestamp, expressed in Unix time,
             stored as an 4-byte unsigned long, in little-endian (read right-to-left).*/
@@ -59,19 +62,8 @@ pub fn read_db<R: Read>(reader: &mut R) -> Result<(RedisDatabase, bool)> {
             EXPIRY_SECONDS => {
                 let mut expiry_bytes = [0u8; 4];
                 reader.read_exact(&mut expiry_bytes)?;
-                let expiry = u32::from_le_bytes(expiry_bytes);
+                expires_at = Some(Expiration::Seconds(u32::from_le_bytes(expiry_bytes)));
                 reader.read_exact(&mut buf)?; // read value type
-
-                let k = read_string(reader)?;
-                let v = read_string(reader)?;
-                //eprintln!("Sec timeout K:{}, v:{}", &k, &v);
-                db.insert(
-                    k,
-                    RedisValue {
-                        value: v,
-                        expires_at: Some(Expiration::Seconds(expiry)),
-                    },
-                );
             }
             /*The expire timestamp, expressed in Unix time,
              * stored as an 8-byte unsigned integer, in little-endian (read right-to-left).*/
@@ -79,42 +71,21 @@ pub fn read_db<R: Read>(reader: &mut R) -> Result<(RedisDatabase, bool)> {
             EXPIRY_MILLISECONDS => {
                 let mut expiry_bytes = [0u8; 8];
                 reader.read_exact(&mut expiry_bytes)?;
-                let expiry = u64::from_le_bytes(expiry_bytes);
-                let _ = reader.read_exact(&mut buf);
-
-                let key = read_string(reader)?;
-                let value = read_string(reader)?;
-                //eprintln!("MS timeout K:{}, v:{}", key, value);
-                db.insert(
-                    key,
-                    RedisValue {
-                        value,
-                        expires_at: Some(Expiration::Milliseconds(expiry)),
-                    },
-                );
-            }
-            /* Here, the flag is 0, which means "string without expiry" */
-            STRING_TYPE => {
-                //eprintln!("IN match string tpe no timeout");
-                let key = read_string(reader)?;
-                let value = read_string(reader)?;
-
-                //eprintln!("STRING no timeout, k:{key}, v:{value}");
-                db.insert(
-                    key,
-                    RedisValue {
-                        value,
-                        expires_at: None,
-                    },
-                );
+                expires_at = Some(Expiration::Milliseconds(u64::from_le_bytes(expiry_bytes)));
+                reader.read_exact(&mut buf)?; // read value type
             }
             EOF => {
                 //eprintln!("REACHED EOF");
                 reached_eof = true;
                 break;
             }
-            _ => return Err(RdbError::InvalidValueType(buf[0])),
+            _ => {}
         }
+
+        let key = read_string(reader)?;
+        let value = read_value(reader, buf[0])?;
+        db.insert(key, RedisValue { value, expires_at });
+
         // Read next byte to determine what comes next
         // if next is an error deal with it
         if let Err(e) = reader.read_exact(&mut buf) {
@@ -129,27 +100,125 @@ pub fn read_db<R: Read>(reader: &mut R) -> Result<(RedisDatabase, bool)> {
     Ok((db, reached_eof))
 }
 
+/// Reads a single value whose encoding is given by the value type flag
+fn read_value<R: Read>(reader: &mut R, value_type: u8) -> Result<RedisData> {
+    match value_type {
+        /* Here, the flag is 0, which means "string" */
+        STRING_TYPE => Ok(RedisData::String(read_string(reader)?)),
+        /* A size-encoded number of elements, followed by each element string encoded */
+        LIST_TYPE => {
+            let (len, _) = read_size(reader)?;
+            let mut list = RedisList::new();
+            for _ in 0..len {
+                list.push(read_string(reader)?, false);
+            }
+            Ok(RedisData::List(list))
+        }
+        /* A size-encoded number of members, followed by each member string encoded */
+        SET_TYPE => {
+            let (len, _) = read_size(reader)?;
+            let mut set = RedisSet::new();
+            for _ in 0..len {
+                set.insert(&read_string(reader)?);
+            }
+            Ok(RedisData::Set(set))
+        }
+        /* A string holding the intset: the integer width in bytes (2, 4 or 8) and the number
+         * of members as 4-byte little-endian integers, then the sorted members, little-endian */
+        INTSET_TYPE => {
+            let blob = read_string(reader)?;
+            let ints = decode_intset(&blob).ok_or(RdbError::InvalidStringEncoding)?;
+            Ok(RedisData::Set(RedisSet::from_members(
+                ints.iter().map(|i| i.to_string()),
+            )))
+        }
+        /* Member string, then the score as a string prefixed with a one byte length,
+         * where 253, 254 and 255 stand for NaN, +inf and -inf */
+        ZSET_TYPE => {
+            let (len, _) = read_size(reader)?;
+            let mut set = RedisSortedSet::new();
+            for _ in 0..len {
+                let member = read_string(reader)?;
+                let mut score_len = [0u8; 1];
+                reader.read_exact(&mut score_len)?;
+                let score = match score_len[0] {
+                    253 => f64::NAN,
+                    254 => f64::INFINITY,
+                    255 => f64::NEG_INFINITY,
+                    n => {
+                        let mut score_bytes = vec![0u8; n as usize];
+                        reader.read_exact(&mut score_bytes)?;
+                        std::str::from_utf8(&score_bytes)
+                            .ok()
+                            .and_then(|s| s.parse::<f64>().ok())
+                            .ok_or(RdbError::InvalidStringEncoding)?
+                    }
+                };
+                set.add(score, &member);
+            }
+            Ok(RedisData::SortedSet(set))
+        }
+        /* A size-encoded number of fields, followed by each field and its value string encoded */
+        HASH_TYPE => {
+            let (len, _) = read_size(reader)?;
+            let mut hash = RedisHash::new();
+            for _ in 0..len {
+                let field = read_string(reader)?;
+                let value = read_string(reader)?;
+                hash.insert(&field, &value);
+            }
+            Ok(RedisData::Hash(hash))
+        }
+        /* Member string, then the score as an 8-byte little-endian double */
+        ZSET_2_TYPE => {
+            let (len, _) = read_size(reader)?;
+            let mut set = RedisSortedSet::new();
+            for _ in 0..len {
+                let member = read_string(reader)?;
+                let mut score_bytes = [0u8; 8];
+                reader.read_exact(&mut score_bytes)?;
+                set.add(f64::from_le_bytes(score_bytes), &member);
+            }
+            Ok(RedisData::SortedSet(set))
+        }
+        _ => Err(RdbError::InvalidValueType(value_type)),
+    }
+}
+
+/// A key to write along with its value, and the members left to write for a sorted set
+type PersistedEntry<'a> = (&'a Vec<u8>, &'a RedisValue, Option<Vec<(&'a [u8], f64)>>);
+
 /// Write database to an RDB file
+/// Streams are kept in memory only, the listpack based stream encoding is not supported
+/// Neither are sorted set member expiries, members not expired yet are written without theirs
+/// and live on after a restart
 pub fn write_database<W: Write>(writer: &mut W, db_index: u8, db: &RedisDatabase) -> Result<()> {
     ////eprintln!("")
     // start with a selector and the provided index
     writer.write_all(&[DB_SELECTOR, db_index])?;
 
-    // Write RESIZEDB info (we don't track sizes, so just write 0)
-    writer.write_all(&[RESIZEDB])?;
-    let num_keys = db.data.len().to_string();
-    //eprintln!("Writing to db with num keys:{num_keys}");
-    writer.write_all(num_keys.as_bytes())?;
-    let expires_len = db
+    // a sorted set is written with the members that have not expired, and not at all without any
+    let persisted: Vec<PersistedEntry> = db
         .data
         .iter()
-        .filter(|(_, v)| v.expires_at.is_some())
-        .count()
-        .to_string();
-    //eprintln!("Writing to db with num_expiry:{expires_len}");
-    writer.write_all(expires_len.as_bytes())?;
+        .filter(|(_, v)| !v.is_expired() && !matches!(v.value, RedisData::Stream(_)))
+        .map(|(k, v)| match &v.value {
+            RedisData::SortedSet(set) => (k, v, Some(set.live_entries())),
+            _ => (k, v, None),
+        })
+        .filter(|(_, _, members)| members.as_ref().is_none_or(|m| !m.is_empty()))
+        .collect();
+
+    // Write RESIZEDB info, the number of keys and the number of keys with an expiry
+    writer.write_all(&[RESIZEDB])?;
+    write_size(writer, persisted.len())?;
+    let expires_len = persisted
+        .iter()
+        .filter(|(_, v, _)| v.expires_at.is_some())
+        .count();
+    write_size(writer, expires_len)?;
 
-    for (k, v) in &db.data {
+    for (k, v, zset_members) in persisted {
         //eprintln!("in write key value for loop");
         // if there is an expiry time
         // write expiry
@@ -166,10 +235,94 @@ pub fn write_database<W: Write>(writer: &mut W, db_index: u8, db: &RedisDatabase
             }
         }
 
-        // write string
-        writer.write_all(&[STRING_TYPE])?;
-        write_string(writer, k)?;
-        write_string(writer, &v.value)?;
+        match &v.value {
+            RedisData::String(s) => {
+                writer.write_all(&[STRING_TYPE])?;
+                write_string(writer, k)?;
+                write_string(writer, s)?;
+            }
+            RedisData::List(list) => {
+                writer.write_all(&[LIST_TYPE])?;
+                write_string(writer, k)?;
+                write_size(writer, list.len())?;
+                for element in list.iter() {
+                    write_string(writer, element)?;
+                }
+            }
+            RedisData::Set(set) => match set.as_intset() {
+                Some(ints) => {
+                    writer.write_all(&[INTSET_TYPE])?;
+                    write_string(writer, k)?;
+                    write_string(writer, &encode_intset(ints))?;
+                }
+                None => {
+                    writer.write_all(&[SET_TYPE])?;
+                    write_string(writer, k)?;
+                    let members = set.members();
+                    write_size(writer, members.len())?;
+                    for member in &members {
+                        write_string(writer, member)?;
+                    }
+                }
+            },
+            RedisData::SortedSet(_) => {
+                let members = zset_members.unwrap_or_default();
+                writer.write_all(&[ZSET_2_TYPE])?;
+                write_string(writer, k)?;
+                write_size(writer, members.len())?;
+                for (member, score) in members {
+                    write_string(writer, member)?;
+                    writer.write_all(&score.to_le_bytes())?;
+                }
+            }
+            RedisData::Hash(hash) => {
+                writer.write_all(&[HASH_TYPE])?;
+                write_string(writer, k)?;
+                write_size(writer, hash.len())?;
+                for (field, value) in hash.iter() {
+                    write_string(writer, field)?;
+                    write_string(writer, value)?;
+                }
+            }
+            RedisData::Stream(_) => unreachable!("streams are filtered out above"),
+        }
     }
     Ok(())
 }
+
+fn decode_intset(blob: &[u8]) -> Option<Vec<i64>> {
+    let width = u32::from_le_bytes(blob.get(0..4)?.try_into().ok()?) as usize;
+    let len = u32::from_le_bytes(blob.get(4..8)?.try_into().ok()?) as usize;
+    if ![2, 4, 8].contains(&width) {
+        return None;
+    }
+    let contents = blob.get(8..8 + width * len)?;
+    let ints = contents
+        .chunks_exact(width)
+        .map(|c| match width {
+            2 => i16::from_le_bytes([c[0], c[1]]) as i64,
+            4 => i32::from_le_bytes([c[0], c[1], c[2], c[3]]) as i64,
+            _ => i64::from_le_bytes(c.try_into().unwrap()),
+        })
+        .collect();
+    Some(ints)
+}
+
+/// Sorted ints in the narrowest width that fits all of them, as redis lays out an intset
+fn encode_intset(ints: &[i64]) -> Vec<u8> {
+    let fits = |min: i64, max: i64| ints.iter().all(|i| (min..=max).contains(i));
+    let width: usize = if fits(i16::MIN as i64, i16::MAX as i64) {
+        2
+    } else if fits(i32::MIN as i64, i32::MAX as i64) {
+        4
+    } else {
+        8
+    };
+    let mut blob = Vec::with_capacity(8 + width * ints.len());
+    blob.extend_from_slice(&(width as u32).to_le_bytes());
+    blob.extend_from_slice(&(ints.len() as u32).to_le_bytes());
+    for i in ints {
+        blob.extend_from_slice(&i.to_le_bytes()[..width]);
+    }
+    blob
+}
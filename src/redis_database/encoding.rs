@@ -119,11 +119,7 @@ pub fn read_size<R: Read>(reader: &mut R) -> Result<(usize, Option<Vec<u8>>)> {
         /* If the first two bits are 0b11:
         The remaining 6 bits specify a type of string encoding.
         See string encoding section. */
-        0b11 => {
-            let mut v = Vec::new();
-            v.push(buf[0]);
-            Ok((usize::MAX, Some(v)))
-        }
+        0b11 => Ok((usize::MAX, Some(vec![buf[0]]))),
         _ => unreachable!(),
     }
 }
@@ -148,7 +144,8 @@ pub fn write_size<W: Write>(writer: &mut W, size: usize) -> Result<()> {
     Ok(())
 }
 
-pub fn read_string<R: Read>(reader: &mut R) -> Result<String> {
+/// Reads a string encoded value, strings are binary safe so no UTF-8 check is done
+pub fn read_string<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
     let res = read_size(reader)?;
     let size = res.0;
     if size == usize::MAX {
@@ -159,16 +156,16 @@ pub fn read_string<R: Read>(reader: &mut R) -> Result<String> {
     //eprintln!("READ SIZE of a STRING, {size}");
     print!("STRING IN HEX:");
     print_hex(&buf);
-    String::from_utf8(buf).map_err(|_| RdbError::InvalidStringEncoding)
+    Ok(buf)
 }
 
-pub fn write_string<W: Write>(writer: &mut W, s: &str) -> Result<()> {
+pub fn write_string<W: Write>(writer: &mut W, s: &[u8]) -> Result<()> {
     write_size(writer, s.len())?;
-    writer.write_all(s.as_bytes())?;
+    writer.write_all(s)?;
     Ok(())
 }
 
-/// Reads a length-prefixed byte array
+// Reads a length-prefixed byte array
 //pub fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
 //    //eprintln!("READING SIZE of a byte array");
 //    let size = read_size(reader)?.0;
@@ -177,7 +174,7 @@ pub fn write_string<W: Write>(writer: &mut W, s: &str) -> Result<()> {
 //    Ok(buf)
 //}
 
-pub fn read_special_int<R: Read>(reader: &mut R, v: Option<Vec<u8>>) -> Result<String> {
+pub fn read_special_int<R: Read>(reader: &mut R, v: Option<Vec<u8>>) -> Result<Vec<u8>> {
     let mut buf = [0u8; 1];
     // reader.read_exact(&mut buf)?;
 
@@ -189,7 +186,7 @@ pub fn read_special_int<R: Read>(reader: &mut R, v: Option<Vec<u8>>) -> Result<S
             reader.read_exact(&mut buf)?;
             let ret = buf[0].to_string();
             //eprintln!("returning string from special int: {ret}");
-            Ok(ret)
+            Ok(ret.into_bytes())
         }
         0xC1 => {
             // 16-bit
@@ -197,7 +194,7 @@ pub fn read_special_int<R: Read>(reader: &mut R, v: Option<Vec<u8>>) -> Result<S
             reader.read_exact(&mut bytes)?;
             let ret = u16::from_le_bytes(bytes).to_string();
             //eprintln!("returning string from special int: {ret}");
-            Ok(ret)
+            Ok(ret.into_bytes())
         }
         0xC2 => {
             // 32-bit
@@ -205,7 +202,7 @@ pub fn read_special_int<R: Read>(reader: &mut R, v: Option<Vec<u8>>) -> Result<S
             reader.read_exact(&mut bytes)?;
             let ret = u32::from_le_bytes(bytes).to_string();
             //eprintln!("returning string from special int: {ret}");
-            Ok(ret)
+            Ok(ret.into_bytes())
         }
         _ => {
             //eprintln!("FAILED special int, with buf:{:#04X?}", buf[0]);
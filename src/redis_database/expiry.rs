@@ -0,0 +1,107 @@
+/*
+* Active expiry, modelled on the Redis expire cycle:
+*
+*   Every 100ms sample 20 random keys out of the ones carrying an expiry and delete the expired ones.
+*   If more than 25% of the sample had expired, assume many more are waiting and sample again,
+*   stopping once the cycle has used up its time budget so clients are not starved of the lock.
+*
+* Keys that are never read again would otherwise only be reclaimed lazily, i.e. never.
+* Sorted set members given their own expiry are swept in the same pass, there are few enough
+* sets carrying them that every one is checked.
+*/
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::redis_database::RedisDatabase;
+
+pub const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+pub const ACTIVE_EXPIRE_SAMPLES: usize = 20;
+pub const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_millis(25);
+
+/// Keys that carry an expiry, indexed so a random one can be picked in O(1)
+#[derive(Debug, Default, Clone)]
+pub struct VolatileKeys {
+    keys: Vec<Vec<u8>>,
+    positions: HashMap<Vec<u8>, usize>,
+}
+
+impl VolatileKeys {
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn add(&mut self, key: &[u8]) {
+        if !self.positions.contains_key(key) {
+            self.positions.insert(key.to_vec(), self.keys.len());
+            self.keys.push(key.to_vec());
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8]) {
+        if let Some(pos) = self.positions.remove(key) {
+            self.keys.swap_remove(pos);
+            // the previous last key now lives where the removed one was
+            if let Some(moved) = self.keys.get(pos) {
+                self.positions.insert(moved.clone(), pos);
+            }
+        }
+    }
+
+    pub fn random(&self) -> Option<&Vec<u8>> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        let idx = rand::rng().random_range(0..self.keys.len());
+        self.keys.get(idx)
+    }
+}
+
+/// Runs the active expiry cycle in the background for as long as the keyspace lives
+/// `on_expired` gets a command replaying each removal, DEL for keys and ZREM for members,
+/// e.g. to propagate them to replicas
+pub fn spawn_active_expiry<F>(db: &Arc<Mutex<RedisDatabase>>, on_expired: F)
+where
+    F: Fn(&[Vec<u8>]) + Send + 'static,
+{
+    // a weak handle so a stopped server does not leave this thread behind
+    let db = Arc::downgrade(db);
+    thread::spawn(move || loop {
+        thread::sleep(ACTIVE_EXPIRE_INTERVAL);
+        let Some(db) = db.upgrade() else {
+            break;
+        };
+        let cycle_start = Instant::now();
+        loop {
+            let (sampled, expired) = {
+                let mut lk = db.lock().unwrap();
+                lk.active_expire_cycle(ACTIVE_EXPIRE_SAMPLES)
+            };
+            if !expired.is_empty() {
+                let mut del_command = vec![b"DEL".to_vec()];
+                del_command.extend(expired.iter().cloned());
+                on_expired(&del_command);
+            }
+            if sampled == 0
+                || expired.len() * 4 <= sampled
+                || cycle_start.elapsed() >= ACTIVE_EXPIRE_BUDGET
+            {
+                break;
+            }
+        }
+
+        let expired_members = db.lock().unwrap().active_expire_members();
+        for (key, members) in expired_members {
+            let mut zrem_command = vec![b"ZREM".to_vec(), key];
+            zrem_command.extend(members);
+            on_expired(&zrem_command);
+        }
+    });
+}
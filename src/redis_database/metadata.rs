@@ -8,7 +8,7 @@ The metadata name and value are always string encoded.
 
 */
 use crate::redis_database::encoding::{read_string, write_string};
-use crate::redis_database::Result;
+use crate::redis_database::{RdbError, Result};
 use std::collections::HashMap;
 use std::io::{Read, Write};
 
@@ -33,9 +33,12 @@ pub fn read_metada<R: Read>(reader: &mut R) -> Result<(HashMap<String, String>,
             return Ok((metadata, buf));
         }
 
-        let k = read_string(reader)?;
+        // metadata is always text, unlike keys and values
+        let k =
+            String::from_utf8(read_string(reader)?).map_err(|_| RdbError::InvalidStringEncoding)?;
         //eprintln!("read key:{k}");
-        let v = read_string(reader)?;
+        let v =
+            String::from_utf8(read_string(reader)?).map_err(|_| RdbError::InvalidStringEncoding)?;
         //eprintln!("read value:{v}");
         //eprintln!("read metadata key:{k}, value{v}");
         metadata.insert(k, v);
@@ -47,8 +50,8 @@ pub fn write_metadata<W: Write>(writer: &mut W, metadata: &HashMap<String, Strin
     //eprintln!("Writing metadata with hashmap:{:?}", metadata);
     for (k, v) in metadata {
         writer.write_all(&[METADATA_START])?;
-        write_string(writer, k)?;
-        write_string(writer, v)?;
+        write_string(writer, k.as_bytes())?;
+        write_string(writer, v.as_bytes())?;
     }
     Ok(())
 }
@@ -1,6 +1,8 @@
+pub mod blocking;
 pub mod database;
 pub mod encoding;
 pub mod error;
+pub mod expiry;
 pub mod header;
 pub mod metadata;
 pub mod print_hex;
@@ -11,7 +13,7 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::Path;
-pub use types::{Expiration, RdbFile, RedisDatabase, RedisValue};
+pub use types::{Expiration, RdbFile, RedisData, RedisDatabase, RedisValue, WrongType};
 
 /// Reads an RDB file from disk
 pub fn read_rdb_file<P: AsRef<Path>>(path: P) -> Result<RdbFile> {
@@ -1,12 +1,62 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::entry_stream::RedisEntryStream;
+use crate::redis_database::blocking::BlockedClients;
+use crate::redis_database::expiry::VolatileKeys;
+use crate::redis_hash::RedisHash;
+use crate::redis_list::RedisList;
+use crate::redis_set::RedisSet;
+use crate::redis_sorted_set::RedisSortedSet;
+
+/// The value stored under a key, one variant per Redis data type
+#[derive(Debug, Clone)]
+pub enum RedisData {
+    String(Vec<u8>),
+    List(RedisList),
+    Set(RedisSet),
+    SortedSet(RedisSortedSet),
+    Hash(RedisHash),
+    Stream(RedisEntryStream),
+}
+
+impl RedisData {
+    /// Name reported by the TYPE command
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RedisData::String(_) => "string",
+            RedisData::List(_) => "list",
+            RedisData::Set(_) => "set",
+            RedisData::SortedSet(_) => "zset",
+            RedisData::Hash(_) => "hash",
+            RedisData::Stream(_) => "stream",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RedisValue {
-    pub value: String,
+    pub value: RedisData,
     pub expires_at: Option<Expiration>,
 }
 
+impl RedisValue {
+    pub fn new(value: RedisData) -> Self {
+        RedisValue {
+            value,
+            expires_at: None,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.as_ref().is_some_and(|e| e.is_expired())
+    }
+}
+
+/// Returned when a command touches a key holding a different data type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrongType;
+
 #[derive(Debug, Clone)]
 pub enum Expiration {
     Seconds(u32),
@@ -21,6 +71,13 @@ impl Expiration {
         }
     }
 
+    pub fn as_millis(&self) -> u64 {
+        match self {
+            Expiration::Seconds(secs) => *secs as u64 * 1000,
+            Expiration::Milliseconds(ms) => *ms,
+        }
+    }
+
     pub fn is_expired(&self) -> bool {
         let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
         match self {
@@ -56,24 +113,395 @@ impl Expiration {
     }
 }
 
-/// Represents a Redis database
-#[derive(Debug, Default, Clone)]
+/// Represents a Redis database, the single keyspace shared by every data type
+/// Expiries must be changed through `insert`/`set_expiry` so the active expiry cycle sees them
+#[derive(Debug, Default)]
 pub struct RedisDatabase {
-    pub data: HashMap<String, RedisValue>,
+    pub data: HashMap<Vec<u8>, RedisValue>,
+    // keys carrying an expiry, sampled by the active expiry cycle
+    volatile_keys: VolatileKeys,
+    // sorted sets with members carrying an expiry, swept by the active expiry cycle
+    volatile_zsets: HashSet<Vec<u8>>,
+    // clients blocked on keys by the blocking list commands and XREAD BLOCK
+    pub blocked: BlockedClients,
+    /// Changes made to the keyspace, as server.dirty in redis
+    /// Insert, remove and set_expiry count themselves, writes made in place are counted by the
+    /// command, which is only propagated when this moved
+    pub dirty: u64,
+    /// What replicas are sent instead of the command being run, set by commands that have to be
+    /// replayed differently, as rewriteClientCommandVector in redis
+    pub propagate_as: Option<Vec<Vec<u8>>>,
+    // DEL and ZREM commands replaying the lazy expiries since the last take_expired
+    expired: Vec<Vec<Vec<u8>>>,
 }
 
 impl RedisDatabase {
     pub fn new() -> Self {
+        RedisDatabase::default()
+    }
+
+    /// Copy of the stored data without any connection state, used for persistence
+    pub fn snapshot(&self) -> Self {
         RedisDatabase {
-            data: HashMap::new(),
+            data: self.data.clone(),
+            volatile_keys: self.volatile_keys.clone(),
+            volatile_zsets: self.volatile_zsets.clone(),
+            ..Default::default()
         }
     }
-    pub fn insert(&mut self, key: String, value: RedisValue) {
+
+    pub fn insert(&mut self, key: Vec<u8>, value: RedisValue) {
+        if value.expires_at.is_some() {
+            self.volatile_keys.add(&key);
+        } else {
+            self.volatile_keys.remove(&key);
+        }
+        if matches!(&value.value, RedisData::SortedSet(s) if s.has_member_expiry()) {
+            self.volatile_zsets.insert(key.clone());
+        }
+        self.blocked.signal(&key);
+        self.dirty += 1;
         self.data.insert(key, value);
     }
-    pub fn get(&self, key: &str) -> Option<&RedisValue> {
+
+    /// Sets or clears the expiry of an existing key, returns false if the key is missing
+    pub fn set_expiry(&mut self, key: &[u8], expires_at: Option<Expiration>) -> bool {
+        let Some(value) = self.get_mut(key) else {
+            return false;
+        };
+        let is_volatile = expires_at.is_some();
+        value.expires_at = expires_at;
+        self.dirty += 1;
+        if is_volatile {
+            self.volatile_keys.add(key);
+        } else {
+            self.volatile_keys.remove(key);
+        }
+        true
+    }
+
+    /// Drops the key if its expiry has passed, returns true if it was removed
+    /// The removal is recorded as a DEL for take_expired
+    pub fn remove_if_expired(&mut self, key: &[u8]) -> bool {
+        if self.data.get(key).is_some_and(|v| v.is_expired()) {
+            self.expire_key(key);
+            self.expired.push(vec![b"DEL".to_vec(), key.to_vec()]);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Commands replaying what expired lazily since the last call, for the replicas
+    pub fn take_expired(&mut self) -> Vec<Vec<Vec<u8>>> {
+        std::mem::take(&mut self.expired)
+    }
+
+    fn expire_key(&mut self, key: &[u8]) {
+        self.data.remove(key);
+        self.volatile_keys.remove(key);
+    }
+
+    /// One sampling round of the active expiry cycle
+    /// Returns how many keys were sampled and the keys that were removed
+    pub fn active_expire_cycle(&mut self, samples: usize) -> (usize, Vec<Vec<u8>>) {
+        let mut expired = Vec::new();
+        let num_samples = samples.min(self.volatile_keys.len());
+        for _ in 0..num_samples {
+            let Some(key) = self.volatile_keys.random().cloned() else {
+                break;
+            };
+            match self.data.get(&key) {
+                Some(value) if value.is_expired() => {
+                    self.expire_key(&key);
+                    expired.push(key);
+                }
+                Some(value) if value.expires_at.is_some() => {}
+                // the expiry was dropped without going through set_expiry
+                _ => self.volatile_keys.remove(&key),
+            }
+        }
+        (num_samples, expired)
+    }
+
+    /// Drops the expired members of the sorted set under key, and the key once it is left empty
+    fn remove_expired_members(&mut self, key: &[u8]) -> Vec<Vec<u8>> {
+        let Some(RedisValue {
+            value: RedisData::SortedSet(set),
+            ..
+        }) = self.data.get_mut(key)
+        else {
+            return Vec::new();
+        };
+        let expired = set.remove_expired_members();
+        if !expired.is_empty() && set.is_empty() {
+            self.expire_key(key);
+        }
+        expired
+    }
+
+    /// remove_expired_members for a read or write of the key, recorded as a ZREM for take_expired
+    fn remove_expired_members_lazily(&mut self, key: &[u8]) {
+        let expired = self.remove_expired_members(key);
+        if !expired.is_empty() {
+            let mut zrem_command = vec![b"ZREM".to_vec(), key.to_vec()];
+            zrem_command.extend(expired);
+            self.expired.push(zrem_command);
+        }
+    }
+
+    /// Sweeps the sorted sets that have members with an expiry
+    /// Returns each key with the members that were removed from it
+    pub fn active_expire_members(&mut self) -> Vec<(Vec<u8>, Vec<Vec<u8>>)> {
+        let mut removed = Vec::new();
+        let keys: Vec<Vec<u8>> = self.volatile_zsets.iter().cloned().collect();
+        for key in keys {
+            let expired = self.remove_expired_members(&key);
+            if !expired.is_empty() {
+                removed.push((key.clone(), expired));
+            }
+            // forget keys that were deleted, overwritten or have no expiring members left
+            let still_volatile = matches!(
+                self.data.get(&key),
+                Some(RedisValue { value: RedisData::SortedSet(s), .. }) if s.has_member_expiry()
+            );
+            if !still_volatile {
+                self.volatile_zsets.remove(&key);
+            }
+        }
+        removed
+    }
+
+    /// Sets when a member of the sorted set under key expires
+    /// Returns false when the key or the member does not exist
+    pub fn expire_member(
+        &mut self,
+        key: &[u8],
+        member: &[u8],
+        expires_at: Expiration,
+    ) -> Result<bool, WrongType> {
+        let Some(set) = self.get_sorted_set(key)? else {
+            return Ok(false);
+        };
+        if !set.set_member_expiry(member, expires_at) {
+            return Ok(false);
+        }
+        if set.is_empty() {
+            self.remove(key);
+        } else if set.has_member_expiry() {
+            self.volatile_zsets.insert(key.to_vec());
+        }
+        self.dirty += 1;
+        Ok(true)
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Option<&RedisValue> {
+        self.remove_if_expired(key);
         self.data.get(key)
     }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut RedisValue> {
+        self.remove_if_expired(key);
+        self.data.get_mut(key)
+    }
+
+    pub fn contains_key(&mut self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<RedisValue> {
+        if self.remove_if_expired(key) {
+            return None;
+        }
+        self.volatile_keys.remove(key);
+        let removed = self.data.remove(key);
+        if removed.is_some() {
+            self.dirty += 1;
+        }
+        removed
+    }
+
+    /// Moves the value and expiry under `from` to `to`, replacing anything stored at `to`
+    pub fn rename(&mut self, from: &[u8], to: &[u8]) -> bool {
+        match self.remove(from) {
+            Some(value) => {
+                self.insert(to.to_vec(), value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Copies the value and expiry under `from` to `to`, only overwriting `to` if `replace` is set
+    pub fn copy(&mut self, from: &[u8], to: &[u8], replace: bool) -> bool {
+        let Some(value) = self.get(from).cloned() else {
+            return false;
+        };
+        if !replace && self.contains_key(to) {
+            return false;
+        }
+        self.insert(to.to_vec(), value);
+        true
+    }
+
+    pub fn type_of(&mut self, key: &[u8]) -> Option<&'static str> {
+        self.get(key).map(|v| v.value.type_name())
+    }
+
+    /// All live keys matching a KEYS style glob pattern
+    pub fn keys(&mut self, pattern: &[u8]) -> Vec<Vec<u8>> {
+        let expired: Vec<Vec<u8>> = self
+            .data
+            .iter()
+            .filter(|(_, v)| v.is_expired())
+            .map(|(k, _)| k.clone())
+            .collect();
+        expired.iter().for_each(|k| {
+            self.remove_if_expired(k);
+        });
+        self.data
+            .keys()
+            .filter(|k| crate::utils::glob_match(pattern, k))
+            .cloned()
+            .collect()
+    }
+
+    pub fn get_string(&mut self, key: &[u8]) -> Result<Option<&Vec<u8>>, WrongType> {
+        match self.get(key) {
+            Some(RedisValue {
+                value: RedisData::String(s),
+                ..
+            }) => Ok(Some(s)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_list(&mut self, key: &[u8]) -> Result<Option<&mut RedisList>, WrongType> {
+        match self.get_mut(key) {
+            Some(RedisValue {
+                value: RedisData::List(l),
+                ..
+            }) => Ok(Some(l)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_set(&mut self, key: &[u8]) -> Result<Option<&mut RedisSet>, WrongType> {
+        match self.get_mut(key) {
+            Some(RedisValue {
+                value: RedisData::Set(s),
+                ..
+            }) => Ok(Some(s)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// The sorted set under key, with its expired members already gone
+    pub fn get_sorted_set(&mut self, key: &[u8]) -> Result<Option<&mut RedisSortedSet>, WrongType> {
+        self.remove_expired_members_lazily(key);
+        match self.get_mut(key) {
+            Some(RedisValue {
+                value: RedisData::SortedSet(s),
+                ..
+            }) => Ok(Some(s)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_hash(&mut self, key: &[u8]) -> Result<Option<&mut RedisHash>, WrongType> {
+        match self.get_mut(key) {
+            Some(RedisValue {
+                value: RedisData::Hash(h),
+                ..
+            }) => Ok(Some(h)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_stream(&mut self, key: &[u8]) -> Result<Option<&mut RedisEntryStream>, WrongType> {
+        match self.get_mut(key) {
+            Some(RedisValue {
+                value: RedisData::Stream(s),
+                ..
+            }) => Ok(Some(s)),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the value under key, inserting the result of `create` if the key is missing
+    fn get_or_insert_with(
+        &mut self,
+        key: &[u8],
+        create: impl FnOnce() -> RedisData,
+    ) -> &mut RedisValue {
+        self.remove_if_expired(key);
+        if !self.data.contains_key(key) {
+            self.blocked.signal(key);
+        }
+        self.data
+            .entry(key.to_vec())
+            .or_insert_with(|| RedisValue::new(create()))
+    }
+
+    pub fn get_or_create_list(&mut self, key: &[u8]) -> Result<&mut RedisList, WrongType> {
+        match &mut self
+            .get_or_insert_with(key, || RedisData::List(RedisList::new()))
+            .value
+        {
+            RedisData::List(l) => Ok(l),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn get_or_create_set(&mut self, key: &[u8]) -> Result<&mut RedisSet, WrongType> {
+        match &mut self
+            .get_or_insert_with(key, || RedisData::Set(RedisSet::new()))
+            .value
+        {
+            RedisData::Set(s) => Ok(s),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn get_or_create_sorted_set(
+        &mut self,
+        key: &[u8],
+    ) -> Result<&mut RedisSortedSet, WrongType> {
+        self.remove_expired_members_lazily(key);
+        match &mut self
+            .get_or_insert_with(key, || RedisData::SortedSet(RedisSortedSet::new()))
+            .value
+        {
+            RedisData::SortedSet(s) => Ok(s),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn get_or_create_hash(&mut self, key: &[u8]) -> Result<&mut RedisHash, WrongType> {
+        match &mut self
+            .get_or_insert_with(key, || RedisData::Hash(RedisHash::new()))
+            .value
+        {
+            RedisData::Hash(h) => Ok(h),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn get_or_create_stream(&mut self, key: &[u8]) -> Result<&mut RedisEntryStream, WrongType> {
+        match &mut self
+            .get_or_insert_with(key, || RedisData::Stream(RedisEntryStream::new()))
+            .value
+        {
+            RedisData::Stream(s) => Ok(s),
+            _ => Err(WrongType),
+        }
+    }
 }
 
 /// Represents the complete RDB file structure
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use crate::constants::{
+    HASH_NOT_FLOAT_ERROR, HASH_NOT_INT_ERROR, NAN_OR_INF_ERROR, OVERFLOW_ERROR,
+};
+use crate::utils::{format_redis_float, parse_redis_float, parse_redis_int};
+
+/// Field to value map stored under a single key
+/// A hash left without fields is removed from the keyspace by the command that emptied it
+#[derive(Debug, Default, Clone)]
+pub struct RedisHash {
+    fields: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl RedisHash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&Vec<u8>> {
+        self.fields.get(field)
+    }
+
+    pub fn contains(&self, field: &[u8]) -> bool {
+        self.fields.contains_key(field)
+    }
+
+    /// Sets the field, returns true if it did not exist before
+    pub fn insert(&mut self, field: &[u8], value: &[u8]) -> bool {
+        self.fields.insert(field.to_vec(), value.to_vec()).is_none()
+    }
+
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        self.fields.remove(field).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &Vec<u8>)> {
+        self.fields.iter()
+    }
+
+    /// HINCRBY, a missing field counts as 0
+    pub fn incr_by(&mut self, field: &[u8], delta: i64) -> Result<i64, &'static str> {
+        let current = match self.fields.get(field) {
+            Some(value) => parse_redis_int(value).ok_or(HASH_NOT_INT_ERROR)?,
+            None => 0,
+        };
+        let new_val = current.checked_add(delta).ok_or(OVERFLOW_ERROR)?;
+        self.fields
+            .insert(field.to_vec(), new_val.to_string().into_bytes());
+        Ok(new_val)
+    }
+
+    /// HINCRBYFLOAT, a missing field counts as 0, returns the new value as stored
+    pub fn incr_by_float(&mut self, field: &[u8], increment: f64) -> Result<String, &'static str> {
+        let current = match self.fields.get(field) {
+            Some(value) => parse_redis_float(value).ok_or(HASH_NOT_FLOAT_ERROR)?,
+            None => 0.0,
+        };
+        // adding 0.0 turns -0 into 0 so we never store "-0"
+        let new_val = current + increment + 0.0;
+        if !new_val.is_finite() {
+            return Err(NAN_OR_INF_ERROR);
+        }
+        let formatted = format_redis_float(new_val);
+        self.fields
+            .insert(field.to_vec(), formatted.clone().into_bytes());
+        Ok(formatted)
+    }
+}
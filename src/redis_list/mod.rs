@@ -1,38 +1,166 @@
-use std::io::Write;
-use std::{net::TcpStream, time::SystemTime};
+use std::collections::VecDeque;
 
-use crate::utils::get_resp_from_string;
-
-#[derive(Debug)]
+/// Elements live in a ring buffer so pushes and pops at either end are O(1)
+/// and indexing, as LINDEX and LRANGE do, is still a direct lookup
+/// Indexes follow LRANGE: 0 is the head, -1 the tail, and out of range ones are clamped or ignored
+/// A list left empty is removed from the keyspace by the command that emptied it
+#[derive(Debug, Default, Clone)]
 pub struct RedisList {
-    pub name_key: String,
-    pub values: Vec<String>,
-    pub blocking_pop_streams: Vec<TcpStream>,
-    pub blocking_until: SystemTime,
+    values: VecDeque<Vec<u8>>,
 }
 
 impl RedisList {
-    pub fn new(name_key: String) -> Self {
-        Self {
-            name_key,
-            values: Vec::new(),
-            blocking_pop_streams: Vec::new(),
-            blocking_until: SystemTime::now(),
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn push(&mut self, value: Vec<u8>, left: bool) {
+        if left {
+            self.values.push_front(value);
+        } else {
+            self.values.push_back(value);
+        }
+    }
+
+    /// Removes up to `count` elements from the head or the tail, in the order they were popped
+    pub fn pop(&mut self, count: usize, left: bool) -> Vec<Vec<u8>> {
+        let count = count.min(self.values.len());
+        if left {
+            self.values.drain(..count).collect()
+        } else {
+            let start = self.values.len() - count;
+            self.values.drain(start..).rev().collect()
+        }
+    }
+
+    /// Position of a possibly negative index, None when it is outside the list
+    fn position(&self, index: i64) -> Option<usize> {
+        let len = self.values.len() as i64;
+        let index = if index < 0 { index + len } else { index };
+        (0..len).contains(&index).then_some(index as usize)
+    }
+
+    pub fn get(&self, index: i64) -> Option<&Vec<u8>> {
+        self.position(index).map(|i| &self.values[i])
+    }
+
+    /// Replaces the element at index, returns false when the index is out of range
+    pub fn set(&mut self, index: i64, value: Vec<u8>) -> bool {
+        match self.position(index) {
+            Some(i) => {
+                self.values[i] = value;
+                true
+            }
+            None => false,
         }
     }
 
-    pub fn check_waiting_streams(&mut self) {
-        for _ in 0..self.blocking_pop_streams.len() {
-            if !self.values.is_empty() {
-                let mut bl_stream = self.blocking_pop_streams.remove(0);
-                let bl_response =
-                    get_resp_from_string(&[self.name_key.clone(), self.values.remove(0)]);
+    /// Inclusive start and stop clamped to the list, None when the range is empty
+    fn clamp_range(&self, start: i64, stop: i64) -> Option<(usize, usize)> {
+        let len = self.values.len() as i64;
+        let start = if start < 0 {
+            (start + len).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            stop + len
+        } else {
+            stop.min(len - 1)
+        };
+        (start <= stop && start < len).then_some((start as usize, stop as usize))
+    }
+
+    pub fn range(&self, start: i64, stop: i64) -> impl Iterator<Item = &Vec<u8>> {
+        let (start, end) = self
+            .clamp_range(start, stop)
+            .map_or((0, 0), |(start, stop)| (start, stop + 1));
+        self.values.range(start..end)
+    }
+
+    /// Every element from head to tail
+    pub fn iter(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.values.iter()
+    }
 
-                eprintln!("\nwriting to blpop:{bl_response}\n");
-                let _ = bl_stream.write_all(bl_response.as_bytes());
-            } else {
-                break;
+    /// Keeps only the elements between start and stop
+    pub fn trim(&mut self, start: i64, stop: i64) {
+        match self.clamp_range(start, stop) {
+            Some((start, stop)) => {
+                self.values.truncate(stop + 1);
+                self.values.drain(..start);
             }
+            None => self.values.clear(),
+        }
+    }
+
+    /// Inserts value next to the first occurrence of pivot
+    /// Returns the new length, or None when the pivot is not in the list
+    pub fn insert(&mut self, pivot: &[u8], value: Vec<u8>, before: bool) -> Option<usize> {
+        let pos = self.values.iter().position(|v| v == pivot)?;
+        let at = if before { pos } else { pos + 1 };
+        self.values.insert(at, value);
+        Some(self.values.len())
+    }
+
+    /// Removes up to `count` occurrences of value, from the tail when count is negative
+    /// and all of them when it is 0, returns how many were removed
+    pub fn remove(&mut self, count: i64, value: &[u8]) -> usize {
+        let limit = if count == 0 {
+            usize::MAX
+        } else {
+            count.unsigned_abs() as usize
+        };
+        let rank = if count < 0 { -1 } else { 1 };
+        let mut matches = self.positions(value, rank, limit, 0);
+        matches.sort_unstable();
+        // one pass over the list rather than shifting it once per removed element
+        let mut index = 0;
+        let mut next = matches.iter().peekable();
+        self.values.retain(|_| {
+            let keep = next.next_if_eq(&&index).is_none();
+            index += 1;
+            keep
+        });
+        matches.len()
+    }
+
+    /// Indexes of value as LPOS finds them
+    /// `rank` picks the first match to report, counting from the tail when negative,
+    /// up to `count` matches are returned and at most `maxlen` elements compared, 0 for no limit
+    pub fn positions(&self, value: &[u8], rank: i64, count: usize, maxlen: usize) -> Vec<usize> {
+        let maxlen = if maxlen == 0 { usize::MAX } else { maxlen };
+        let skip = (rank.unsigned_abs() - 1) as usize;
+        let matching = |(_, v): &(usize, &Vec<u8>)| v.as_slice() == value;
+        if rank > 0 {
+            self.values
+                .iter()
+                .enumerate()
+                .take(maxlen)
+                .filter(matching)
+                .skip(skip)
+                .take(count)
+                .map(|(i, _)| i)
+                .collect()
+        } else {
+            self.values
+                .iter()
+                .enumerate()
+                .rev()
+                .take(maxlen)
+                .filter(matching)
+                .skip(skip)
+                .take(count)
+                .map(|(i, _)| i)
+                .collect()
         }
     }
 }
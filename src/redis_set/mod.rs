@@ -0,0 +1,169 @@
+/*
+* Unordered set of unique members
+*
+* A set holding nothing but integers is kept as a sorted Vec<i64>, the intset encoding,
+* which is a fraction of the size of a hash table of byte strings. It turns into a hash
+* table for good once a member is not an integer or it grows past SET_MAX_INTSET_ENTRIES.
+*/
+use std::collections::HashSet;
+
+use rand::seq::index::sample;
+use rand::Rng;
+
+use crate::utils::parse_redis_int;
+
+/// Largest set kept in the intset encoding, as set-max-intset-entries in redis
+pub const SET_MAX_INTSET_ENTRIES: usize = 512;
+
+#[derive(Debug, Clone)]
+enum Encoding {
+    IntSet(Vec<i64>),
+    HashTable(HashSet<Vec<u8>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct RedisSet {
+    encoding: Encoding,
+}
+
+impl Default for RedisSet {
+    fn default() -> Self {
+        RedisSet {
+            encoding: Encoding::IntSet(Vec::new()),
+        }
+    }
+}
+
+impl RedisSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_members<T: AsRef<[u8]>>(members: impl IntoIterator<Item = T>) -> Self {
+        let mut set = Self::new();
+        for member in members {
+            set.insert(member.as_ref());
+        }
+        set
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::IntSet(ints) => ints.len(),
+            Encoding::HashTable(members) => members.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name reported by OBJECT ENCODING in redis
+    pub fn encoding_name(&self) -> &'static str {
+        match &self.encoding {
+            Encoding::IntSet(_) => "intset",
+            Encoding::HashTable(_) => "hashtable",
+        }
+    }
+
+    /// The members in ascending order while the set is an intset
+    pub fn as_intset(&self) -> Option<&[i64]> {
+        match &self.encoding {
+            Encoding::IntSet(ints) => Some(ints),
+            Encoding::HashTable(_) => None,
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match &self.encoding {
+            Encoding::IntSet(ints) => {
+                parse_redis_int(member).is_some_and(|i| ints.binary_search(&i).is_ok())
+            }
+            Encoding::HashTable(members) => members.contains(member),
+        }
+    }
+
+    /// Adds the member, returns false if it was already there
+    pub fn insert(&mut self, member: &[u8]) -> bool {
+        if let Encoding::IntSet(ints) = &mut self.encoding {
+            if let Some(i) = parse_redis_int(member) {
+                match ints.binary_search(&i) {
+                    Ok(_) => return false,
+                    Err(_) if ints.len() >= SET_MAX_INTSET_ENTRIES => {}
+                    Err(pos) => {
+                        ints.insert(pos, i);
+                        return true;
+                    }
+                }
+            }
+            self.convert_to_hashtable();
+        }
+        match &mut self.encoding {
+            Encoding::HashTable(members) => members.insert(member.to_vec()),
+            Encoding::IntSet(_) => unreachable!("converted above"),
+        }
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match &mut self.encoding {
+            Encoding::IntSet(ints) => match parse_redis_int(member).map(|i| ints.binary_search(&i))
+            {
+                Some(Ok(pos)) => {
+                    ints.remove(pos);
+                    true
+                }
+                _ => false,
+            },
+            Encoding::HashTable(members) => members.remove(member),
+        }
+    }
+
+    fn convert_to_hashtable(&mut self) {
+        if let Encoding::IntSet(ints) = &self.encoding {
+            let members = ints.iter().map(|i| i.to_string().into_bytes()).collect();
+            self.encoding = Encoding::HashTable(members);
+        }
+    }
+
+    /// Every member, in no particular order
+    pub fn members(&self) -> Vec<Vec<u8>> {
+        match &self.encoding {
+            Encoding::IntSet(ints) => ints.iter().map(|i| i.to_string().into_bytes()).collect(),
+            Encoding::HashTable(members) => members.iter().cloned().collect(),
+        }
+    }
+
+    /// Up to `count` distinct members picked at random
+    pub fn random_members(&self, count: usize) -> Vec<Vec<u8>> {
+        let members = self.members();
+        let count = count.min(members.len());
+        sample(&mut rand::rng(), members.len(), count)
+            .into_iter()
+            .map(|i| members[i].clone())
+            .collect()
+    }
+
+    /// `count` members picked at random, the same one may come up more than once
+    pub fn random_members_with_repeats(&self, count: usize) -> Vec<Vec<u8>> {
+        let members = self.members();
+        if members.is_empty() {
+            return Vec::new();
+        }
+        let mut rng = rand::rng();
+        // grown as it goes rather than reserved up front, the count comes from the client
+        let mut picked = Vec::new();
+        for _ in 0..count {
+            picked.push(members[rng.random_range(0..members.len())].clone());
+        }
+        picked
+    }
+
+    /// Removes and returns up to `count` members picked at random
+    pub fn pop(&mut self, count: usize) -> Vec<Vec<u8>> {
+        let popped = self.random_members(count);
+        for member in &popped {
+            self.remove(member);
+        }
+        popped
+    }
+}
@@ -1,15 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
 
-use crate::constants::EMPTY_ARRAY;
+use crate::redis_database::Expiration;
+use crate::redis_sorted_set::skiplist::SkipList;
+use crate::utils::{parse_redis_float, unix_time_ms};
+
+mod skiplist;
 
 #[derive(Debug, Default, Clone)]
 pub struct UserScore {
     score: f64,
-    name: String,
+    name: Vec<u8>,
 }
 
 impl UserScore {
-    pub fn new(score: f64, name: String) -> Self {
+    pub fn new(score: f64, name: Vec<u8>) -> Self {
         Self { score, name }
     }
 }
@@ -24,72 +29,85 @@ impl PartialOrd for UserScore {
     }
 }
 
+// the same total order as partial_cmp, so a NaN score loaded from an RDB file is still found
 impl PartialEq for UserScore {
     fn eq(&self, other: &Self) -> bool {
-        self.score == other.score && self.name == other.name
+        self.score.total_cmp(&other.score).is_eq() && self.name == other.name
     }
 }
 
+#[derive(Debug, Default, Clone)]
 pub struct RedisSortedSet {
-    collection: Vec<UserScore>,
-    user_map: HashMap<String, f64>,
+    /// Members by score, with ranks and index ranges in O(log n)
+    collection: SkipList,
+    /// Score of every member, for ZSCORE and finding a member in the skip list
+    user_map: HashMap<Vec<u8>, f64>,
+    // members set to go away on their own, a new score keeps the expiry
+    member_expiry: HashMap<Vec<u8>, Expiration>,
+    // the same expiries in unix ms ordered by time, so the due ones are found without a scan
+    expiry_queue: BTreeSet<(u64, Vec<u8>)>,
 }
 
 impl RedisSortedSet {
     pub fn new() -> Self {
-        Self {
-            collection: Vec::new(),
-            user_map: HashMap::new(),
-        }
+        Self::default()
     }
 
     pub fn len(&self) -> usize {
         self.collection.len()
     }
 
-    pub fn insert(&mut self, score: &str, name: &str) -> bool {
-        let new_score = score.parse::<f64>().unwrap();
+    pub fn is_empty(&self) -> bool {
+        self.collection.len() == 0
+    }
+
+    /// Members with their scores in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], f64)> {
+        self.entries(0..self.len())
+    }
 
+    /// Members with their scores in ascending order, leaving out those whose expiry passed
+    pub fn live_entries(&self) -> Vec<(&[u8], f64)> {
+        self.iter()
+            .filter(|(name, _)| !self.member_expiry(name).is_some_and(|e| e.is_expired()))
+            .collect()
+    }
+
+    pub fn add(&mut self, new_score: f64, name: &[u8]) -> bool {
         if let Some((name, old_score)) = self.user_map.remove_entry(name) {
-            eprintln!("FOUND EXISTING");
             let user_score = UserScore {
                 name: name.clone(),
                 score: old_score,
             };
             self.collection_remove(&user_score);
-            self.insert(score, &name);
+            self.add(new_score, &name);
             false
         } else {
-            let name = name.to_string();
+            let name = name.to_vec();
             let user_score = UserScore {
                 score: new_score,
                 name: name.clone(),
             };
-            let pos = bin_search(&self.collection, &user_score);
-            eprintln!("pos:{pos}");
-            self.collection.splice(pos..pos, [user_score]);
-            eprintln!("after insert, collection:{:?}", self.collection);
+            self.collection.insert(user_score);
             self.user_map.insert(name, new_score);
-            eprintln!("map after insert:{:?}", self.user_map);
             true
         }
     }
 
     pub fn collection_remove(&mut self, user_score: &UserScore) {
-        let pos = get_pos(&self.collection, &user_score);
-        eprintln!("REMOVING AT:{pos}");
-        self.collection.remove(pos);
+        self.collection.remove(user_score);
     }
-    pub fn get_member(&self, name: &str) -> Option<&f64> {
+    pub fn get_member(&self, name: &[u8]) -> Option<&f64> {
         self.user_map.get(name)
     }
 
-    pub fn remove_member(&mut self, name: &str) -> bool {
+    pub fn remove_member(&mut self, name: &[u8]) -> bool {
         let rem_score = self.user_map.remove(name);
         let ret = rem_score.is_some();
         if ret {
+            self.clear_member_expiry(name);
             let user_score = UserScore {
-                name: name.to_string(),
+                name: name.to_vec(),
                 score: rem_score.unwrap(),
             };
             self.collection_remove(&user_score);
@@ -97,93 +115,157 @@ impl RedisSortedSet {
         ret
     }
 
-    pub fn rank(&self, member_name: &str) -> Option<usize> {
+    /// Sets when a member expires, an expiry already passed removes it right away
+    /// Returns false if there is no such member
+    pub fn set_member_expiry(&mut self, name: &[u8], expires_at: Expiration) -> bool {
+        if !self.user_map.contains_key(name) {
+            return false;
+        }
+        if expires_at.is_expired() {
+            self.remove_member(name);
+        } else {
+            self.clear_member_expiry(name);
+            self.expiry_queue
+                .insert((expires_at.as_millis(), name.to_vec()));
+            self.member_expiry.insert(name.to_vec(), expires_at);
+        }
+        true
+    }
+
+    fn clear_member_expiry(&mut self, name: &[u8]) {
+        if let Some(expires_at) = self.member_expiry.remove(name) {
+            self.expiry_queue
+                .remove(&(expires_at.as_millis(), name.to_vec()));
+        }
+    }
+
+    pub fn member_expiry(&self, name: &[u8]) -> Option<&Expiration> {
+        self.member_expiry.get(name)
+    }
+
+    pub fn has_member_expiry(&self) -> bool {
+        !self.member_expiry.is_empty()
+    }
+
+    /// Drops every member whose expiry has passed and returns them
+    /// Only the due members are looked at, so this is cheap enough to run before every access
+    pub fn remove_expired_members(&mut self) -> Vec<Vec<u8>> {
+        // everything at or before now, whatever the member
+        let due = (unix_time_ms() + 1, Vec::new());
+        let expired: Vec<Vec<u8>> = self
+            .expiry_queue
+            .range(..due)
+            .map(|(_, name)| name.clone())
+            .collect();
+        for name in &expired {
+            self.remove_member(name);
+        }
+        expired
+    }
+
+    pub fn rank(&self, member_name: &[u8]) -> Option<usize> {
         if let Some(score) = self.user_map.get(member_name) {
             let user_score = UserScore {
                 score: *score,
-                name: member_name.to_string(),
+                name: member_name.to_vec(),
             };
-            let pos = get_pos(&self.collection, &user_score);
-            Some(pos)
+            self.collection.rank(&user_score)
         } else {
             None
         }
     }
 
-    pub fn range_resp_array(&self, start: i32, end: i32) -> String {
-        let set_len = self.len() as i32;
-        let mut end = end;
-        let mut start = start;
-        /*An index of -1 refers to the last element, -2 to the second last, and so on.
-         * If a absolute value of the negative index is out of range (i.e. >= the cardinality of the sorted set),
-         * it is treated as 0 (start of the sorted set).*/
-        if end < 0 {
-            end = set_len + end
+    /// Positions between start and stop, both inclusive and negative ones counted from the end
+    pub fn rank_range(&self, start: i64, stop: i64) -> Range<usize> {
+        let len = self.len() as i64;
+        let start = if start < 0 {
+            (start + len).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            stop + len
+        } else {
+            stop.min(len - 1)
+        };
+        if start > stop || start >= len {
+            return 0..0;
         }
+        start as usize..stop as usize + 1
+    }
 
-        if start < 0 {
-            start = set_len + start
-        }
+    /// Positions of the members scored between min and max
+    pub fn score_range(&self, min: ScoreBound, max: ScoreBound) -> Range<usize> {
+        let start = self
+            .collection
+            .partition_point(|e| e.score < min.value || (min.exclusive && e.score == min.value));
+        let end = self
+            .collection
+            .partition_point(|e| e.score < max.value || (!max.exclusive && e.score == max.value));
+        start..end.max(start)
+    }
 
-        //IF still negative
-        if end < 0 {
-            end = 0
-        }
-        if start < 0 {
-            start = 0
-        }
+    /// Positions of the members between min and max in byte order
+    /// Only meaningful when every member has the same score, as in redis
+    pub fn lex_range(&self, min: &LexBound, max: &LexBound) -> Range<usize> {
+        let start = self.collection.partition_point(|e| match min {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(m) => e.name < *m,
+            LexBound::Exclusive(m) => e.name <= *m,
+        });
+        let end = self.collection.partition_point(|e| match max {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(m) => e.name <= *m,
+            LexBound::Exclusive(m) => e.name < *m,
+        });
+        start..end.max(start)
+    }
 
-        if start > end {
-            EMPTY_ARRAY.into()
-        } else if start > set_len {
-            EMPTY_ARRAY.into()
-        } else {
-            eprintln!("getting range, ALL members:{:?}", self.collection);
-            let end = end as usize;
-            let start = start as usize;
-            let end = if end >= self.len() {
-                self.len()
-            } else {
-                end + 1
-            };
+    /// Members with their scores at the given positions, in ascending order
+    pub fn entries(&self, range: Range<usize>) -> impl DoubleEndedIterator<Item = (&[u8], f64)> {
+        self.collection
+            .range(range.start, range.end)
+            .map(|e| (e.name.as_slice(), e.score))
+    }
+}
 
-            let mut resp = format!("*{}\r\n", end - start);
-            eprintln!("start:{start}, last(not included):{end}");
-            self.collection[start..end].iter().for_each(|element| {
-                resp.push_str(&format!("${}\r\n{}\r\n", element.name.len(), element.name));
-            });
-            resp
-        }
+/// End of a score range, `(` in front makes it exclusive and `-inf`/`+inf` are accepted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreBound {
+    pub value: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    pub fn parse(arg: &[u8]) -> Option<Self> {
+        let (exclusive, value) = match arg.strip_prefix(b"(") {
+            Some(rest) => (true, rest),
+            None => (false, arg),
+        };
+        let value = parse_redis_float(value)?;
+        Some(ScoreBound { value, exclusive })
     }
 }
 
-fn bin_search(sub_vec: &[UserScore], search_item: &UserScore) -> usize {
-    eprintln!("in bin search sub vec{sub_vec:?},");
-    let use_len = sub_vec.len();
-    if use_len == 0 {
-        return 0;
-    }
-
-    let mid = use_len / 2;
-    let mid_elem = &sub_vec[mid];
-    eprintln!(" using mid:{mid}, mid elem:{mid_elem:?}");
-    let res = {
-        if mid_elem > search_item {
-            eprintln!("{:?} is greater than {:?}", sub_vec[mid], search_item);
-            bin_search(&sub_vec[0..mid], search_item)
-        } else if mid_elem == search_item {
-            mid
-        } else {
-            eprintln!("{:?} is less than {:?}", sub_vec[mid], search_item);
-            mid + 1 + bin_search(&sub_vec[mid + 1..], search_item)
-        }
-    };
-    eprintln!("final return res:{res}");
-    res
+/// End of a lexicographic range, `[` or `(` in front of a member, `-` and `+` for the extremes
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
 }
 
-fn get_pos(sub_vec: &[UserScore], search_item: &UserScore) -> usize {
-    let pos = bin_search(sub_vec, search_item);
-    eprintln!("IN GET POS returning position: {pos}");
-    pos
+impl LexBound {
+    pub fn parse(arg: &[u8]) -> Option<Self> {
+        match arg {
+            b"-" => Some(LexBound::Min),
+            b"+" => Some(LexBound::Max),
+            [b'[', rest @ ..] => Some(LexBound::Inclusive(rest.to_vec())),
+            [b'(', rest @ ..] => Some(LexBound::Exclusive(rest.to_vec())),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,411 @@
+/*
+* Skip list ordered by score then member, as zskiplist in redis
+*
+* Every link also records its span, how many elements it jumps over, so the rank of an element
+* and the element at a rank are found on the way down in O(log n) like a lookup. Nodes live in
+* an arena and link to each other by index, freed slots are reused by the next insert.
+*/
+use rand::Rng;
+
+use super::UserScore;
+
+/// Levels a node can have, enough for 2^64 elements with P = 1/4
+const MAX_LEVEL: usize = 32;
+
+/// Chance a node is promoted to the next level
+const P: f64 = 0.25;
+
+/// Index of the header node, which holds no element
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Level {
+    forward: Option<usize>,
+    /// Elements between this node and forward, forward included
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    entry: UserScore,
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    len: usize,
+    /// Levels in use, at least 1
+    level: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node {
+            entry: UserScore::default(),
+            backward: None,
+            levels: vec![Level::default(); MAX_LEVEL],
+        };
+        SkipList {
+            nodes: vec![head],
+            free: Vec::new(),
+            tail: None,
+            len: 0,
+            level: 1,
+        }
+    }
+}
+
+impl SkipList {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    fn forward(&self, node: usize, level: usize) -> Option<usize> {
+        self.nodes[node].levels[level].forward
+    }
+
+    fn random_level() -> usize {
+        let mut rng = rand::rng();
+        let mut level = 1;
+        while level < MAX_LEVEL && rng.random_bool(P) {
+            level += 1;
+        }
+        level
+    }
+
+    /// Adds an element, which must not be in the list already
+    pub fn insert(&mut self, entry: UserScore) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.forward(x, i).filter(|n| self.nodes[*n].entry < entry) {
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+        let level = Self::random_level();
+        if level > self.level {
+            for i in self.level..level {
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+        let node = Node {
+            entry,
+            backward: (update[0] != HEAD).then_some(update[0]),
+            levels: vec![Level::default(); level],
+        };
+        let new = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        for i in 0..level {
+            let prev = self.nodes[update[i]].levels[i];
+            self.nodes[new].levels[i] = Level {
+                forward: prev.forward,
+                span: prev.span - (rank[0] - rank[i]),
+            };
+            self.nodes[update[i]].levels[i] = Level {
+                forward: Some(new),
+                span: rank[0] - rank[i] + 1,
+            };
+        }
+        for (i, prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*prev].levels[i].span += 1;
+        }
+        match self.forward(new, 0) {
+            Some(next) => self.nodes[next].backward = Some(new),
+            None => self.tail = Some(new),
+        }
+        self.len += 1;
+    }
+
+    /// Removes the element, returns false if it was not there
+    pub fn remove(&mut self, entry: &UserScore) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i).filter(|n| self.nodes[*n].entry < *entry) {
+                x = next;
+            }
+            update[i] = x;
+        }
+        let Some(target) = self
+            .forward(x, 0)
+            .filter(|n| self.nodes[*n].entry == *entry)
+        else {
+            return false;
+        };
+        for (i, prev) in update.iter().enumerate().take(self.level) {
+            if self.forward(*prev, i) == Some(target) {
+                let removed = self.nodes[target].levels[i];
+                let link = &mut self.nodes[*prev].levels[i];
+                link.span = link.span + removed.span - 1;
+                link.forward = removed.forward;
+            } else {
+                self.nodes[*prev].levels[i].span -= 1;
+            }
+        }
+        let backward = self.nodes[target].backward;
+        match self.forward(target, 0) {
+            Some(next) => self.nodes[next].backward = backward,
+            None => self.tail = backward,
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1).is_none() {
+            self.level -= 1;
+        }
+        // the slot keeps no member around while it waits to be reused
+        self.nodes[target].entry = UserScore::default();
+        self.nodes[target].levels = Vec::new();
+        self.free.push(target);
+        self.len -= 1;
+        true
+    }
+
+    /// Position of the element from 0, None if it is not there
+    pub fn rank(&self, entry: &UserScore) -> Option<usize> {
+        let mut x = HEAD;
+        let mut rank = 0;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self
+                .forward(x, i)
+                .filter(|n| self.nodes[*n].entry <= *entry)
+            {
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if x != HEAD && self.nodes[x].entry == *entry {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// Number of leading elements `before` holds for, which has to be true up to some element
+    /// and false from there on, as with slice::partition_point
+    pub fn partition_point(&self, before: impl Fn(&UserScore) -> bool) -> usize {
+        let mut x = HEAD;
+        let mut count = 0;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i).filter(|n| before(&self.nodes[*n].entry)) {
+                count += self.nodes[x].levels[i].span;
+                x = next;
+            }
+        }
+        count
+    }
+
+    /// Node at a position from 0
+    fn node_at(&self, index: usize) -> Option<usize> {
+        let rank = index + 1;
+        let mut x = HEAD;
+        let mut traversed = 0;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self
+                .forward(x, i)
+                .filter(|_| traversed + self.nodes[x].levels[i].span <= rank)
+            {
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == rank {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// Elements from position start up to but not including end, walkable from either side
+    pub fn range(&self, start: usize, end: usize) -> Iter<'_> {
+        let end = end.min(self.len);
+        if start >= end {
+            return Iter {
+                list: self,
+                front: None,
+                back: None,
+                remaining: 0,
+            };
+        }
+        let back = if end == self.len {
+            self.tail
+        } else {
+            self.node_at(end - 1)
+        };
+        Iter {
+            list: self,
+            front: self.node_at(start),
+            back,
+            remaining: end - start,
+        }
+    }
+}
+
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    front: Option<usize>,
+    back: Option<usize>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a UserScore;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.front?];
+        self.front = node.levels[0].forward;
+        self.remaining -= 1;
+        Some(&node.entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.back?];
+        self.back = node.backward;
+        self.remaining -= 1;
+        Some(&node.entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(score: f64, name: &str) -> UserScore {
+        UserScore::new(score, name.as_bytes().to_vec())
+    }
+
+    fn names<'a>(iter: impl Iterator<Item = &'a UserScore>) -> Vec<String> {
+        iter.map(|e| String::from_utf8(e.name.clone()).unwrap())
+            .collect()
+    }
+
+    /// The list checked against a sorted vec holding the same elements
+    fn assert_matches(list: &SkipList, expected: &[UserScore]) {
+        assert_eq!(list.len(), expected.len());
+        let forward: Vec<&UserScore> = list.range(0, list.len()).collect();
+        assert!(forward.iter().copied().eq(expected.iter()));
+        let backward: Vec<&UserScore> = list.range(0, list.len()).rev().collect();
+        assert!(backward.iter().copied().eq(expected.iter().rev()));
+        for (i, e) in expected.iter().enumerate() {
+            assert_eq!(list.rank(e), Some(i));
+        }
+    }
+
+    #[test]
+    fn orders_by_score_then_name() {
+        let mut list = SkipList::default();
+        for (score, name) in [(2.0, "b"), (1.0, "z"), (2.0, "a"), (-1.5, "m")] {
+            list.insert(entry(score, name));
+        }
+        assert_eq!(names(list.range(0, 4)), ["m", "z", "a", "b"]);
+        assert_eq!(list.rank(&entry(2.0, "a")), Some(2));
+        assert_eq!(list.rank(&entry(2.0, "c")), None);
+        assert_eq!(list.rank(&entry(1.0, "a")), None);
+    }
+
+    #[test]
+    fn ranges_walk_from_either_end() {
+        let mut list = SkipList::default();
+        for i in 0..100 {
+            list.insert(entry(i as f64, &format!("m{i:03}")));
+        }
+        assert_eq!(names(list.range(10, 13)), ["m010", "m011", "m012"]);
+        assert_eq!(names(list.range(97, 200)), ["m097", "m098", "m099"]);
+        assert_eq!(names(list.range(5, 8).rev()), ["m007", "m006", "m005"]);
+        assert_eq!(list.range(50, 50).count(), 0);
+        assert_eq!(list.range(120, 130).count(), 0);
+
+        let mut both_ends = list.range(0, 4);
+        assert_eq!(both_ends.next().unwrap().score, 0.0);
+        assert_eq!(both_ends.next_back().unwrap().score, 3.0);
+        assert_eq!(both_ends.size_hint(), (2, Some(2)));
+        assert_eq!(names(both_ends), ["m001", "m002"]);
+    }
+
+    #[test]
+    fn partition_point_counts_leading_elements() {
+        let mut list = SkipList::default();
+        for i in 0..50 {
+            list.insert(entry(i as f64, "m"));
+        }
+        assert_eq!(list.partition_point(|e| e.score < 20.0), 20);
+        assert_eq!(list.partition_point(|_| false), 0);
+        assert_eq!(list.partition_point(|_| true), 50);
+    }
+
+    #[test]
+    fn remove_keeps_ranks_and_links() {
+        let mut list = SkipList::default();
+        for i in 0..10 {
+            list.insert(entry(i as f64, "m"));
+        }
+        assert!(list.remove(&entry(0.0, "m")));
+        assert!(list.remove(&entry(9.0, "m")));
+        assert!(list.remove(&entry(4.0, "m")));
+        assert!(!list.remove(&entry(4.0, "m")));
+        assert!(!list.remove(&entry(5.0, "x")));
+        let expected: Vec<UserScore> = [1, 2, 3, 5, 6, 7, 8]
+            .iter()
+            .map(|i| entry(*i as f64, "m"))
+            .collect();
+        assert_matches(&list, &expected);
+
+        // freed slots are reused without disturbing the rest
+        list.insert(entry(4.5, "m"));
+        assert_eq!(list.rank(&entry(4.5, "m")), Some(3));
+        assert_eq!(list.nodes.len(), 11);
+    }
+
+    #[test]
+    fn random_operations_match_a_sorted_vec() {
+        let mut rng = rand::rng();
+        let mut list = SkipList::default();
+        let mut expected: Vec<UserScore> = Vec::new();
+        for _ in 0..2000 {
+            let e = entry(
+                rng.random_range(0..50) as f64,
+                &rng.random_range(0..20).to_string(),
+            );
+            match expected.iter().position(|x| *x == e) {
+                Some(pos) => {
+                    assert!(list.remove(&e));
+                    expected.remove(pos);
+                }
+                None => {
+                    list.insert(e.clone());
+                    let pos = expected.partition_point(|x| *x < e);
+                    expected.insert(pos, e);
+                }
+            }
+        }
+        assert_matches(&list, &expected);
+        let start = expected.len() / 3;
+        assert!(list
+            .range(start, start + 10)
+            .eq(expected[start..start + 10].iter()));
+    }
+}
@@ -0,0 +1,382 @@
+use std::fmt;
+use std::io;
+
+use crate::constants::{MAX_NESTING, MAX_STRING_LEN};
+use crate::resp::inline::MAX_INLINE_LEN;
+use crate::resp::RespValue;
+
+/// Malformed input, the connection it came from cannot be trusted to resync
+#[derive(Debug, Clone, PartialEq)]
+pub struct RespError(pub String);
+
+impl fmt::Display for RespError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Protocol error: {}", self.0)
+    }
+}
+
+impl std::error::Error for RespError {}
+
+impl From<RespError> for io::Error {
+    fn from(err: RespError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+type DecodeResult = Result<Option<(RespValue, usize)>, RespError>;
+
+/// Decodes the value at the start of buf along with the number of bytes it took up
+/// Returns None while buf only holds part of the value so the caller can wait for more data
+pub fn decode(buf: &[u8]) -> DecodeResult {
+    FrameScanner::default().decode(buf)
+}
+
+/// Finds where the value at the start of a buffer ends without building it
+///
+/// A connection keeps one across reads, so a value arriving in many pieces is walked once
+/// rather than from its start on every read, and nothing is copied out until all of it is there.
+/// Nesting deeper than MAX_NESTING is rejected here, which bounds the recursion of decode_value,
+/// and so is a type line longer than an inline command may be.
+#[derive(Debug, Default)]
+pub struct FrameScanner {
+    // bytes of the value known to be complete
+    pos: usize,
+    // bytes after pos already searched for the CRLF ending the next line
+    searched: usize,
+    // items still expected by each open aggregate, innermost last
+    pending: Vec<usize>,
+}
+
+impl FrameScanner {
+    /// decode for a buffer that only grows at the end between calls until a value is returned
+    pub fn decode(&mut self, buf: &[u8]) -> DecodeResult {
+        match self.scan(buf)? {
+            Some(len) => decode_value(&buf[..len]),
+            None => Ok(None),
+        }
+    }
+
+    /// The line at pos along with the bytes it takes up, None until its CRLF has arrived
+    fn next_line<'a>(&mut self, buf: &'a [u8]) -> Result<Option<(&'a [u8], usize)>, RespError> {
+        let rest = &buf[self.pos..];
+        // one byte back, the last one searched may be the CR
+        let from = self.searched.saturating_sub(1);
+        match rest[from..].windows(2).position(|w| w == b"\r\n") {
+            Some(i) if from + i <= MAX_INLINE_LEN => {
+                self.searched = 0;
+                Ok(Some((&rest[..from + i], from + i + 2)))
+            }
+            None if rest.len() <= MAX_INLINE_LEN => {
+                self.searched = rest.len();
+                Ok(None)
+            }
+            _ => Err(RespError("too big header line".to_string())),
+        }
+    }
+
+    /// Returns the length of the value once buf holds all of it
+    fn scan(&mut self, buf: &[u8]) -> Result<Option<usize>, RespError> {
+        loop {
+            let Some((line, mut len)) = self.next_line(buf)? else {
+                return Ok(None);
+            };
+            let Some((&prefix, body)) = line.split_first() else {
+                return Err(RespError("empty line".to_string()));
+            };
+            match prefix {
+                b'+' | b'-' | b':' | b'_' | b'#' | b',' | b'(' => {}
+                b'$' | b'!' | b'=' => match parse_int(body)? {
+                    -1 if prefix == b'$' => {}
+                    payload => {
+                        len += bulk_len(payload)? + 2;
+                        if buf.len() < self.pos + len {
+                            return Ok(None);
+                        }
+                    }
+                },
+                b'*' | b'~' | b'>' | b'%' => match parse_int(body)? {
+                    -1 if prefix == b'*' => {}
+                    count => {
+                        let count = match prefix {
+                            b'%' => parse_count(count)?.checked_mul(2),
+                            _ => Some(parse_count(count)?),
+                        }
+                        .ok_or_else(|| RespError("invalid multibulk length".to_string()))?;
+                        if count > 0 {
+                            if self.pending.len() == MAX_NESTING {
+                                return Err(RespError("too many nested aggregates".to_string()));
+                            }
+                            self.pos += len;
+                            self.pending.push(count);
+                            continue;
+                        }
+                    }
+                },
+                other => {
+                    return Err(RespError(format!(
+                        "unexpected type byte '{}'",
+                        other.escape_ascii()
+                    )))
+                }
+            }
+            self.pos += len;
+            // the item just passed may be the last one of the aggregates around it
+            loop {
+                let Some(remaining) = self.pending.last_mut() else {
+                    let len = self.pos;
+                    self.pos = 0;
+                    return Ok(Some(len));
+                };
+                *remaining -= 1;
+                if *remaining > 0 {
+                    break;
+                }
+                self.pending.pop();
+            }
+        }
+    }
+}
+
+/// Decodes a value buf is known to hold all of
+fn decode_value(buf: &[u8]) -> DecodeResult {
+    let Some((line, mut pos)) = read_line(buf) else {
+        return Ok(None);
+    };
+    let Some((&prefix, body)) = line.split_first() else {
+        return Err(RespError("empty line".to_string()));
+    };
+
+    let value = match prefix {
+        b'+' => RespValue::SimpleString(text(body)?),
+        b'-' => RespValue::Error(text(body)?),
+        b':' => RespValue::Integer(parse_int(body)?),
+        b'_' => RespValue::Null,
+        b'#' => match body {
+            b"t" => RespValue::Boolean(true),
+            b"f" => RespValue::Boolean(false),
+            _ => return Err(RespError("invalid boolean".to_string())),
+        },
+        b',' => RespValue::Double(parse_double(body)?),
+        b'(' => RespValue::BigNumber(text(body)?),
+        b'$' | b'!' | b'=' => {
+            let len = parse_int(body)?;
+            if len == -1 && prefix == b'$' {
+                return Ok(Some((RespValue::Null, pos)));
+            }
+            let len = bulk_len(len)?;
+            if buf.len() < pos + len + 2 {
+                return Ok(None);
+            }
+            if &buf[pos + len..pos + len + 2] != b"\r\n" {
+                return Err(RespError("bulk string longer than its length".to_string()));
+            }
+            let payload = buf[pos..pos + len].to_vec();
+            pos += len + 2;
+            match prefix {
+                b'$' => RespValue::BulkString(payload),
+                b'!' => RespValue::Error(String::from_utf8_lossy(&payload).into_owned()),
+                _ => {
+                    if payload.len() < 4 || payload[3] != b':' {
+                        return Err(RespError("invalid verbatim string".to_string()));
+                    }
+                    RespValue::Verbatim {
+                        format: String::from_utf8_lossy(&payload[..3]).into_owned(),
+                        text: payload[4..].to_vec(),
+                    }
+                }
+            }
+        }
+        b'*' | b'~' | b'>' => {
+            let len = parse_int(body)?;
+            if len == -1 && prefix == b'*' {
+                return Ok(Some((RespValue::NullArray, pos)));
+            }
+            let Some(items) = decode_items(buf, &mut pos, parse_count(len)?)? else {
+                return Ok(None);
+            };
+            match prefix {
+                b'*' => RespValue::Array(items),
+                b'~' => RespValue::Set(items),
+                _ => RespValue::Push(items),
+            }
+        }
+        b'%' => {
+            let len = parse_count(parse_int(body)?)?;
+            let Some(items) = decode_items(buf, &mut pos, len * 2)? else {
+                return Ok(None);
+            };
+            let mut items = items.into_iter();
+            let mut pairs = Vec::with_capacity(len);
+            while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                pairs.push((k, v));
+            }
+            RespValue::Map(pairs)
+        }
+        other => {
+            return Err(RespError(format!(
+                "unexpected type byte '{}'",
+                other.escape_ascii()
+            )))
+        }
+    };
+    Ok(Some((value, pos)))
+}
+
+/// Decodes `count` consecutive values starting at pos, advancing it past them
+fn decode_items(
+    buf: &[u8],
+    pos: &mut usize,
+    count: usize,
+) -> Result<Option<Vec<RespValue>>, RespError> {
+    // the count comes from the peer, so don't trust it for the allocation
+    let mut items = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let Some((item, used)) = decode_value(&buf[*pos..])? else {
+            return Ok(None);
+        };
+        *pos += used;
+        items.push(item);
+    }
+    Ok(Some(items))
+}
+
+/// Returns the line at the start of buf without its CRLF, along with the bytes it takes up
+pub fn read_line(buf: &[u8]) -> Option<(&[u8], usize)> {
+    let end = buf.windows(2).position(|w| w == b"\r\n")?;
+    Some((&buf[..end], end + 2))
+}
+
+fn text(body: &[u8]) -> Result<String, RespError> {
+    String::from_utf8(body.to_vec()).map_err(|_| RespError("invalid UTF-8".to_string()))
+}
+
+fn parse_int(body: &[u8]) -> Result<i64, RespError> {
+    std::str::from_utf8(body)
+        .ok()
+        .and_then(|d| d.parse().ok())
+        .ok_or_else(|| RespError("invalid integer".to_string()))
+}
+
+fn bulk_len(len: i64) -> Result<usize, RespError> {
+    usize::try_from(len)
+        .ok()
+        .filter(|l| *l <= MAX_STRING_LEN)
+        .ok_or_else(|| RespError("invalid bulk length".to_string()))
+}
+
+fn parse_count(len: i64) -> Result<usize, RespError> {
+    usize::try_from(len).map_err(|_| RespError("invalid multibulk length".to_string()))
+}
+
+fn parse_double(body: &[u8]) -> Result<f64, RespError> {
+    match body {
+        b"inf" => Ok(f64::INFINITY),
+        b"-inf" => Ok(f64::NEG_INFINITY),
+        b"nan" => Ok(f64::NAN),
+        _ => std::str::from_utf8(body)
+            .ok()
+            .and_then(|d| d.parse().ok())
+            .ok_or_else(|| RespError("invalid double".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds buf to one scanner a piece at a time like separate reads would
+    fn decode_in_pieces(buf: &[u8], piece: usize) -> DecodeResult {
+        let mut scanner = FrameScanner::default();
+        let mut end = 0;
+        while end < buf.len() {
+            end = (end + piece).min(buf.len());
+            if let Some(decoded) = scanner.decode(&buf[..end])? {
+                return Ok(Some(decoded));
+            }
+        }
+        Ok(None)
+    }
+
+    fn nested(depth: usize) -> Vec<u8> {
+        let mut buf = b"*1\r\n".repeat(depth);
+        buf.extend_from_slice(b":1\r\n");
+        buf
+    }
+
+    #[test]
+    fn split_reads_decode_like_one_read() {
+        let buf = b"*3\r\n$3\r\nSET\r\n*2\r\n+ok\r\n:-12\r\n%1\r\n$1\r\nk\r\n,1.5\r\n";
+        let whole = decode(buf).unwrap().unwrap();
+        assert_eq!(whole.1, buf.len());
+        for piece in 1..buf.len() {
+            assert_eq!(decode_in_pieces(buf, piece).unwrap(), Some(whole.clone()));
+        }
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_value() {
+        let mut scanner = FrameScanner::default();
+        assert_eq!(scanner.decode(b"*2\r\n$5\r\nhel").unwrap(), None);
+        assert_eq!(scanner.decode(b"*2\r\n$5\r\nhello\r").unwrap(), None);
+        assert_eq!(
+            scanner.decode(b"*2\r\n$5\r\nhello\r\n$0\r\n").unwrap(),
+            None
+        );
+        let (value, len) = scanner
+            .decode(b"*2\r\n$5\r\nhello\r\n$0\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(value, RespValue::bulk_array(["hello", ""]));
+        assert_eq!(len, 21);
+    }
+
+    #[test]
+    fn starts_over_after_each_value() {
+        let buf = b"+one\r\n:2\r\n";
+        let mut scanner = FrameScanner::default();
+        let (first, len) = scanner.decode(buf).unwrap().unwrap();
+        assert_eq!(first, RespValue::SimpleString("one".to_string()));
+        let (second, _) = scanner.decode(&buf[len..]).unwrap().unwrap();
+        assert_eq!(second, RespValue::Integer(2));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        assert!(decode(&nested(MAX_NESTING)).unwrap().is_some());
+        assert!(decode(&nested(MAX_NESTING + 1)).is_err());
+        // rejected as soon as the extra level arrives, not once the value is complete
+        assert!(decode(&b"*1\r\n".repeat(MAX_NESTING + 1)).is_err());
+        assert_eq!(decode(&b"*1\r\n".repeat(MAX_NESTING)).unwrap(), None);
+    }
+
+    #[test]
+    fn header_lines_are_capped_across_reads() {
+        let long = vec![b'1'; MAX_INLINE_LEN + 1];
+        let mut buf = b"$".to_vec();
+        let mut scanner = FrameScanner::default();
+        for chunk in long.chunks(4096) {
+            buf.extend_from_slice(chunk);
+            if buf.len() <= MAX_INLINE_LEN {
+                assert_eq!(scanner.decode(&buf).unwrap(), None);
+            }
+        }
+        assert!(scanner.decode(&buf).is_err());
+    }
+
+    #[test]
+    fn crlf_split_between_reads_is_found() {
+        let mut scanner = FrameScanner::default();
+        assert_eq!(scanner.decode(b":42\r").unwrap(), None);
+        let (value, len) = scanner.decode(b":42\r\n").unwrap().unwrap();
+        assert_eq!((value, len), (RespValue::Integer(42), 5));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(decode(b"?\r\n").is_err());
+        assert!(decode(b"\r\n").is_err());
+        assert!(decode(b"*-2\r\n").is_err());
+        assert!(decode(b"$abc\r\n").is_err());
+        assert!(decode(b"$2\r\nabc\r\n").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::Write;
+
+use crate::resp::{Protocol, RespValue};
+
+impl RespValue {
+    /// Appends the wire form of the value to `out`, usually the connection's write buffer
+    pub fn encode(&self, proto: Protocol, out: &mut Vec<u8>) {
+        let resp3 = proto == Protocol::Resp3;
+        match self {
+            RespValue::SimpleString(s) => write_line(out, b'+', s.as_bytes()),
+            RespValue::Error(e) => write_line(out, b'-', e.as_bytes()),
+            RespValue::Integer(n) => write_header(out, b':', *n),
+            RespValue::BulkString(b) => write_bulk(out, b'$', b),
+            RespValue::Null if resp3 => out.extend_from_slice(b"_\r\n"),
+            RespValue::Null => out.extend_from_slice(b"$-1\r\n"),
+            RespValue::NullArray if resp3 => out.extend_from_slice(b"_\r\n"),
+            RespValue::NullArray => out.extend_from_slice(b"*-1\r\n"),
+            RespValue::Array(items) => write_aggregate(out, b'*', items, proto),
+            RespValue::Set(items) => {
+                write_aggregate(out, if resp3 { b'~' } else { b'*' }, items, proto)
+            }
+            RespValue::Push(items) => {
+                write_aggregate(out, if resp3 { b'>' } else { b'*' }, items, proto)
+            }
+            RespValue::Map(pairs) => {
+                // RESP2 clients get the pairs flattened into one array
+                if resp3 {
+                    write_header(out, b'%', pairs.len() as i64);
+                } else {
+                    write_header(out, b'*', pairs.len() as i64 * 2);
+                }
+                for (k, v) in pairs {
+                    k.encode(proto, out);
+                    v.encode(proto, out);
+                }
+            }
+            RespValue::Double(d) if resp3 => write_line(out, b',', format_double(*d).as_bytes()),
+            RespValue::Double(d) => write_bulk(out, b'$', format_double(*d).as_bytes()),
+            RespValue::Boolean(b) if resp3 => write_line(out, b'#', if *b { b"t" } else { b"f" }),
+            RespValue::Boolean(b) => write_header(out, b':', *b as i64),
+            RespValue::Verbatim { format, text } if resp3 => {
+                let payload = [format.as_bytes(), b":", text].concat();
+                write_bulk(out, b'=', &payload)
+            }
+            RespValue::Verbatim { text, .. } => write_bulk(out, b'$', text),
+            RespValue::BigNumber(n) if resp3 => write_line(out, b'(', n.as_bytes()),
+            RespValue::BigNumber(n) => write_bulk(out, b'$', n.as_bytes()),
+        }
+    }
+}
+
+/// Doubles are sent as `inf`, `-inf` and `nan` or their shortest decimal form
+pub fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        "nan".to_string()
+    } else if d.is_infinite() {
+        if d > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        d.to_string()
+    }
+}
+
+fn write_line(out: &mut Vec<u8>, prefix: u8, line: &[u8]) {
+    out.push(prefix);
+    out.extend_from_slice(line);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn write_header(out: &mut Vec<u8>, prefix: u8, n: i64) {
+    out.push(prefix);
+    // writing into a Vec cannot fail
+    let _ = write!(out, "{n}\r\n");
+}
+
+fn write_bulk(out: &mut Vec<u8>, prefix: u8, payload: &[u8]) {
+    write_header(out, prefix, payload.len() as i64);
+    out.extend_from_slice(payload);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn write_aggregate(out: &mut Vec<u8>, prefix: u8, items: &[RespValue], proto: Protocol) {
+    write_header(out, prefix, items.len() as i64);
+    for item in items {
+        item.encode(proto, out);
+    }
+}
@@ -0,0 +1,111 @@
+/*
+* Inline commands, what you get typing into telnet or netcat:
+*
+*   SET greeting "hello world"\r\n
+*
+* Arguments are separated by whitespace and may be quoted. Double quoted arguments understand
+* the escapes \n \r \t \b \a \\ \" and \xHH, single quoted ones only \'.
+* The line may end in a bare \n.
+*/
+use crate::resp::RespError;
+
+/// Longest inline command accepted before the client is considered broken
+pub const MAX_INLINE_LEN: usize = 64 * 1024;
+
+type InlineResult = Result<Option<(Vec<Vec<u8>>, usize)>, RespError>;
+
+/// Splits the inline command at the start of buf into its arguments
+/// Returns None until a whole line has arrived, an empty line gives no arguments
+pub fn decode_inline(buf: &[u8]) -> InlineResult {
+    let Some(end) = buf.iter().position(|c| *c == b'\n') else {
+        if buf.len() > MAX_INLINE_LEN {
+            return Err(RespError("too big inline request".to_string()));
+        }
+        return Ok(None);
+    };
+    let line = buf[..end].strip_suffix(b"\r").unwrap_or(&buf[..end]);
+    Ok(Some((split_args(line)?, end + 1)))
+}
+
+fn split_args(line: &[u8]) -> Result<Vec<Vec<u8>>, RespError> {
+    let unbalanced = || RespError("unbalanced quotes in request".to_string());
+    let mut args = Vec::new();
+    let mut i = 0;
+    loop {
+        while i < line.len() && line[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == line.len() {
+            return Ok(args);
+        }
+
+        let mut arg = Vec::new();
+        let mut in_double = false;
+        let mut in_single = false;
+        loop {
+            let Some(&c) = line.get(i) else {
+                if in_double || in_single {
+                    return Err(unbalanced());
+                }
+                break;
+            };
+            if in_double {
+                match c {
+                    b'\\'
+                        if line.get(i + 1) == Some(&b'x')
+                            && line.get(i + 2).is_some_and(u8::is_ascii_hexdigit)
+                            && line.get(i + 3).is_some_and(u8::is_ascii_hexdigit) =>
+                    {
+                        let hex = std::str::from_utf8(&line[i + 2..i + 4]).unwrap();
+                        arg.push(u8::from_str_radix(hex, 16).unwrap());
+                        i += 3;
+                    }
+                    b'\\' if i + 1 < line.len() => {
+                        i += 1;
+                        arg.push(match line[i] {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            other => other,
+                        });
+                    }
+                    // the closing quote must end the argument
+                    b'"' if line.get(i + 1).is_some_and(|n| !n.is_ascii_whitespace()) => {
+                        return Err(unbalanced());
+                    }
+                    b'"' => {
+                        i += 1;
+                        break;
+                    }
+                    _ => arg.push(c),
+                }
+            } else if in_single {
+                match c {
+                    b'\\' if line.get(i + 1) == Some(&b'\'') => {
+                        i += 1;
+                        arg.push(b'\'');
+                    }
+                    b'\'' if line.get(i + 1).is_some_and(|n| !n.is_ascii_whitespace()) => {
+                        return Err(unbalanced());
+                    }
+                    b'\'' => {
+                        i += 1;
+                        break;
+                    }
+                    _ => arg.push(c),
+                }
+            } else {
+                match c {
+                    c if c.is_ascii_whitespace() => break,
+                    b'"' => in_double = true,
+                    b'\'' => in_single = true,
+                    _ => arg.push(c),
+                }
+            }
+            i += 1;
+        }
+        args.push(arg);
+    }
+}
@@ -0,0 +1,86 @@
+/*
+* Typed model of the Redis serialization protocol
+*
+*   https://redis.io/docs/latest/develop/reference/protocol-spec/
+*
+* Replies are built as RespValue and only turned into bytes when written to a socket,
+* which is the point where we know whether the client speaks RESP2 or RESP3.
+* The RESP3 only types fall back to their closest RESP2 form when encoded for RESP2.
+*/
+pub mod decode;
+pub mod encode;
+pub mod inline;
+
+pub use decode::{decode, RespError};
+
+/// Protocol version spoken on a connection, RESP2 unless the client switches with HELLO
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespValue {
+    SimpleString(String),
+    Error(String),
+    Integer(i64),
+    BulkString(Vec<u8>),
+    /// Missing value, `$-1` in RESP2
+    Null,
+    /// Missing aggregate, `*-1` in RESP2
+    NullArray,
+    Array(Vec<RespValue>),
+    Map(Vec<(RespValue, RespValue)>),
+    Set(Vec<RespValue>),
+    Double(f64),
+    Boolean(bool),
+    /// Out of band data such as pub/sub messages
+    Push(Vec<RespValue>),
+    /// Bulk string tagged with a three letter format, e.g. `txt`
+    Verbatim {
+        format: String,
+        text: Vec<u8>,
+    },
+    BigNumber(String),
+}
+
+impl RespValue {
+    pub fn ok() -> Self {
+        RespValue::SimpleString("OK".to_string())
+    }
+
+    pub fn simple(s: &str) -> Self {
+        RespValue::SimpleString(s.to_string())
+    }
+
+    /// Error reply, `msg` starts with the error code e.g. "ERR syntax error"
+    pub fn error(msg: &str) -> Self {
+        RespValue::Error(msg.to_string())
+    }
+
+    pub fn bulk(s: impl AsRef<[u8]>) -> Self {
+        RespValue::BulkString(s.as_ref().to_vec())
+    }
+
+    pub fn empty_array() -> Self {
+        RespValue::Array(Vec::new())
+    }
+
+    /// Array of bulk strings, the shape of most multi value replies
+    pub fn bulk_array<T: AsRef<[u8]>>(elements: impl IntoIterator<Item = T>) -> Self {
+        RespValue::Array(elements.into_iter().map(RespValue::bulk).collect())
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, RespValue::Error(_))
+    }
+
+    /// Encodes the value on its own, for writes that bypass a connection's buffer
+    pub fn to_bytes(&self, proto: Protocol) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(proto, &mut out);
+        out
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::event_loop::{EventLoop, Stopper};
+use crate::handler::{Command, CommandRegistry, RegisterError, ServerContext, Session};
+use crate::redis_connection::broadcast_info::BroadCastInfo;
+use crate::redis_database::expiry::spawn_active_expiry;
+use crate::redis_database::{read_rdb_file, RedisDatabase};
+
+use crate::redis_channel::PubSub;
+
+use crate::utils::random_id_gen;
+
+use crate::constants::*;
+
+/// A server with its port bound and state loaded, ready to run here or on its own thread
+pub struct Server {
+    event_loop: EventLoop,
+    local_addr: SocketAddr,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Serves on the calling thread until the event loop fails
+    pub fn run(mut self) -> io::Result<()> {
+        self.event_loop.run()
+    }
+
+    /// Serves on a background thread, the handle stops it
+    pub fn spawn(self) -> ServerHandle {
+        let local_addr = self.local_addr;
+        let stopper = self.event_loop.stopper();
+        let thread = thread::spawn(move || self.run());
+        ServerHandle {
+            local_addr,
+            stopper,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// A server running on its own thread, dropping the handle shuts it down too
+#[derive(Debug)]
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    stopper: Stopper,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl ServerHandle {
+    /// The address the server listens on, with the real port when it was asked for port 0
+    pub fn addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Closes every connection and waits for the server thread to finish
+    pub fn shutdown(mut self) -> io::Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        self.stopper.stop();
+        match thread.join() {
+            Ok(res) => res,
+            Err(_) => Err(io::Error::other("server thread panicked")),
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop() {
+            eprintln!("server stopped with error: {e}");
+        }
+    }
+}
+
+pub struct ServerBuilder {
+    port: u16,
+    dir: Option<String>,
+    db_filename: Option<String>,
+    replica_of: Option<(String, u16)>,
+    commands: CommandRegistry,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        ServerBuilder {
+            port: DEFAULT_PORT.parse().unwrap(),
+            dir: None,
+            db_filename: None,
+            replica_of: None,
+            commands: CommandRegistry::new(),
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// Port to listen on, 0 picks a free one
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Directory the RDB file lives in
+    pub fn dir(mut self, dir: impl Into<String>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Name of the RDB file, loaded at startup when it exists and written by SAVE
+    pub fn dbfilename(mut self, db_filename: impl Into<String>) -> Self {
+        self.db_filename = Some(db_filename.into());
+        self
+    }
+
+    /// Runs as a replica of the master at host:port
+    pub fn replica_of(mut self, host: impl Into<String>, port: u16) -> Self {
+        self.replica_of = Some((host.into(), port));
+        self
+    }
+
+    /// Adds a command clients can call like any built in one
+    /// Fails when its name is invalid or already taken
+    pub fn command(mut self, command: impl Command + 'static) -> Result<Self, RegisterError> {
+        self.commands.register(command)?;
+        Ok(self)
+    }
+
+    /// Loads the RDB file, binds the port and connects to the master when replicating
+    pub fn build(self) -> io::Result<Server> {
+        let id = random_id_gen();
+        let mut info_fields: HashMap<String, String> = HashMap::new();
+        info_fields.insert(String::from(ROLE), MASTER.to_string());
+        //eprintln!("ID:{:?}", id);
+        //info_fields.insert("id", id);
+
+        let broadcast_info: Arc<Mutex<BroadCastInfo>> = Arc::new(Mutex::new(BroadCastInfo::new()));
+        // single keyspace holding every data type
+        let mut new_db = Arc::new(Mutex::new(RedisDatabase::new()));
+
+        let channels_db = Arc::new(Mutex::new(PubSub::default()));
+        // let subscribers_db: HashMap<String, Subscriber> = HashMap::new();
+        // let subscribers_db = Arc::new(Mutex::new(subscribers_db));
+
+        if let (Some(file), Some(directory)) = (&self.db_filename, &self.dir) {
+            let path = Path::new(directory).join(file);
+
+            // a missing or unreadable file starts the server empty
+            if let Ok(mut rdb) = read_rdb_file(path) {
+                if let Some(storage_db) = rdb.databases.remove(&0u8) {
+                    new_db = Arc::new(Mutex::new(storage_db));
+                }
+            }
+        }
+
+        let listener = TcpListener::bind(("127.0.0.1", self.port))?;
+        let local_addr = listener.local_addr()?;
+        eprintln!("Listening on port:{local_addr}");
+
+        let mut master_stream: Option<TcpStream> = None;
+        let mut master_port: Option<String> = None;
+        if let Some((host, port)) = &self.replica_of {
+            let curr_role = info_fields.get_mut(ROLE).unwrap();
+            *curr_role = SLAVE.to_string();
+
+            eprintln!("Running at:{local_addr} is replica of:{host} {port}");
+            eprintln!("connecting to master on {host}:{port}");
+            let conn = TcpStream::connect((host.as_str(), *port))?;
+            master_port = Some(port.to_string());
+            // the handshake runs once the event loop is set up
+            master_stream = Some(conn);
+        }
+
+        let rl = info_fields.get(ROLE);
+        if let Some(r) = rl {
+            if r == MASTER {
+                info_fields.insert(String::from(MASTER_REPL_ID), id.clone());
+                info_fields.insert(String::from(MASTER_REPL_OFFSET), "0".to_string());
+            } else {
+                info_fields.insert(String::from("repl_id"), id.clone());
+            }
+        }
+
+        let ctx = ServerContext {
+            dir: self.dir,
+            db_filename: self.db_filename,
+            info_fields,
+            broadcast_info,
+            replica_port: Some(local_addr.port().to_string()),
+            master_port,
+            new_db,
+            channels_db,
+            commands: Arc::new(self.commands),
+        };
+        let mut event_loop = EventLoop::new(listener, ctx.clone())?;
+
+        // reclaim expired keys nobody reads again, replicas learn about them through DEL and ZREM
+        let is_master = ctx.info_fields.get(ROLE).is_some_and(|r| r == MASTER);
+        let expiry_broadcast = Arc::clone(&ctx.broadcast_info);
+        // the loop queues the removals on the replica links, wake it up to send them
+        let waker = event_loop.waker();
+        spawn_active_expiry(&ctx.new_db, move |removal| {
+            if is_master {
+                expiry_broadcast.lock().unwrap().broadcast_command(removal);
+                if let Err(e) = waker.wake() {
+                    eprintln!("failed to wake event loop: {e}");
+                }
+            }
+        });
+
+        if let Some(stream) = master_stream {
+            let master_session = Session::new(stream, &ctx);
+            event_loop.register(master_session)?;
+        }
+
+        Ok(Server {
+            event_loop,
+            local_addr,
+        })
+    }
+}
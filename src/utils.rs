@@ -1,7 +1,6 @@
 use rand::Rng;
 use std::net::TcpStream;
-
-use crate::redis_database::RdbFile;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn get_port(stream: &TcpStream) -> Option<String> {
     if let Ok(peer_addr) = stream.peer_addr() {
@@ -12,26 +11,48 @@ pub fn get_port(stream: &TcpStream) -> Option<String> {
         None
     }
 }
-//pub fn get_bulk_string(res: &str) -> Vec<u8> {
-pub fn get_bulk_string(res: &str) -> String {
-    //fn get_bulk_string(res: &str) -> &[u8] {
-    let res_size = res.len();
-    // [
-    //     b"$",
-    //     res_size.to_string().as_bytes(),
-    //     b"\r\n",
-    //     res.as_bytes(),
-    //     b"\r\n",
-    // ]
-    // .concat()
+/// Text of an argument that must be ASCII, e.g. a number or an option
+/// Invalid UTF-8 maps to an empty string, which no option or number matches
+pub fn arg_str(arg: &[u8]) -> &str {
+    std::str::from_utf8(arg).unwrap_or("")
+}
+
+/// An argument quoted back in an error reply, which cannot hold a line break
+pub fn printable_arg(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).replace(['\r', '\n'], " ")
+}
+
+/// Parses an integer the way Redis does: no sign prefix, leading zeros or whitespace
+pub fn parse_redis_int(s: &[u8]) -> Option<i64> {
+    let digits = s.strip_prefix(b"-").unwrap_or(s);
+    let valid = match digits {
+        [b'0'] => !s.starts_with(b"-"),
+        [b'1'..=b'9', rest @ ..] => rest.iter().all(u8::is_ascii_digit),
+        _ => false,
+    };
+    if valid {
+        arg_str(s).parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Parses a float, rejecting NaN and anything with surrounding whitespace
+pub fn parse_redis_float(s: &[u8]) -> Option<f64> {
+    arg_str(s).parse::<f64>().ok().filter(|f| !f.is_nan())
+}
 
-    format!("${res_size}\r\n{res}\r\n")
+/// Formats a float the way INCRBYFLOAT replies, shortest form without exponent or trailing zeros
+pub fn format_redis_float(f: f64) -> String {
+    f.to_string()
 }
 
-//pub fn get_redis_int(n: i32) -> Vec<u8> {
-pub fn get_redis_int(n: i32) -> String {
-    //format!(":{n}\r\n").as_bytes().into()
-    format!(":{n}\r\n")
+/// Current unix time in milliseconds
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
 }
 
 pub fn random_id_gen() -> String {
@@ -50,41 +71,47 @@ pub fn random_id_gen() -> String {
     id
 }
 
-pub fn get_resp_from_string(elements: &[String]) -> String {
-    let mut resp = format!("*{}\r\n", elements.len()); //.into_bytes();
-    for element in elements {
-        //resp.extend(format!("${}\r\n{}\r\n", element.len(), element).into_bytes());
-        resp.push_str(&format!("${}\r\n{}\r\n", element.len(), element));
-    }
-    resp
-}
-
-pub fn read_rdb_keys(rdb: RdbFile, search_key: String) -> Vec<String> {
-    ////eprintln!("Successful rdb read");
-    let mut ret_keys = Vec::new();
-    //get by index
-    // TODO: instead of hardcoding, find the latest key, i.e largest num
-    if let Some(db) = rdb.databases.get(&0) {
-        ////eprintln!("GOT DB ROM RDB FILE {:?}", db);
-        match search_key.as_str() {
-            "*" => {
-                ////eprintln!("GOT * search");
-                db.data.clone().into_iter().for_each(|(k, _)| {
-                    ret_keys.push(k);
-                });
+/// Matches a key against a KEYS style glob pattern supporting `*`, `?`, `[...]` and `\` escapes
+/// Works on raw bytes since keys are binary safe
+pub fn glob_match(p: &[u8], k: &[u8]) -> bool {
+    match p.first() {
+        None => k.is_empty(),
+        Some(b'*') => (0..=k.len()).any(|i| glob_match(&p[1..], &k[i..])),
+        Some(b'?') => !k.is_empty() && glob_match(&p[1..], &k[1..]),
+        Some(b'[') => {
+            let Some(c) = k.first() else {
+                return false;
+            };
+            let mut i = 1;
+            let negate = p.get(i) == Some(&b'^');
+            if negate {
+                i += 1;
             }
-            _others => {
-                let search_strings: Vec<&str> = search_key.split("*").collect();
-                db.data.clone().into_iter().for_each(|(k, _)| {
-                    if search_strings.iter().all(|e| k.contains(e)) {
-                        ret_keys.push(k);
-                    }
-                });
+            let mut matched = false;
+            while i < p.len() && p[i] != b']' {
+                if p[i] == b'\\' && i + 1 < p.len() {
+                    i += 1;
+                    matched |= p[i] == *c;
+                } else if i + 2 < p.len() && p[i + 1] == b'-' && p[i + 2] != b']' {
+                    let (lo, hi) = if p[i] <= p[i + 2] {
+                        (p[i], p[i + 2])
+                    } else {
+                        (p[i + 2], p[i])
+                    };
+                    matched |= lo <= *c && *c <= hi;
+                    i += 2;
+                } else {
+                    matched |= p[i] == *c;
+                }
+                i += 1;
             }
+            // an unterminated class is matched as far as it goes
+            let rest = if i < p.len() { &p[i + 1..] } else { &p[i..] };
+            matched != negate && glob_match(rest, &k[1..])
         }
+        Some(b'\\') if p.len() > 1 => k.first() == Some(&p[1]) && glob_match(&p[2..], &k[1..]),
+        Some(c) => k.first() == Some(c) && glob_match(&p[1..], &k[1..]),
     }
-    ////eprintln!("All KEYS to return:{:?}", ret_keys);
-    ret_keys
 }
 
 /*
@@ -0,0 +1,87 @@
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use codecrafters_redis::resp::RespValue;
+
+use common::{bulk, start, Client};
+
+/// Sends a blocking command and gives the server time to park the client
+fn block(client: &mut Client, args: &[&str]) {
+    client.send(args);
+    thread::sleep(Duration::from_millis(50));
+}
+
+#[test]
+fn push_wakes_up_the_longest_waiting_client() {
+    let server = start();
+    let mut pusher = Client::connect(server.addr());
+    let mut first = Client::connect(server.addr());
+    let mut second = Client::connect(server.addr());
+    block(&mut first, &["BLPOP", "list", "0"]);
+    block(&mut second, &["BLPOP", "list", "0"]);
+
+    assert_eq!(pusher.call(&["RPUSH", "list", "a"]), RespValue::Integer(1));
+    assert_eq!(first.read(), RespValue::bulk_array(["list", "a"]));
+    assert!(second.is_quiet_for(Duration::from_millis(100)));
+
+    pusher.call(&["RPUSH", "list", "b"]);
+    assert_eq!(second.read(), RespValue::bulk_array(["list", "b"]));
+    assert_eq!(pusher.call(&["EXISTS", "list"]), RespValue::Integer(0));
+}
+
+#[test]
+fn one_push_serves_as_many_clients_as_it_has_elements() {
+    let server = start();
+    let mut pusher = Client::connect(server.addr());
+    let mut waiters: Vec<Client> = (0..3).map(|_| Client::connect(server.addr())).collect();
+    for waiter in waiters.iter_mut() {
+        block(waiter, &["BLPOP", "list", "0"]);
+    }
+
+    pusher.call(&["RPUSH", "list", "a", "b"]);
+    assert_eq!(waiters[0].read(), RespValue::bulk_array(["list", "a"]));
+    assert_eq!(waiters[1].read(), RespValue::bulk_array(["list", "b"]));
+    assert!(waiters[2].is_quiet_for(Duration::from_millis(100)));
+}
+
+#[test]
+fn blocked_clients_time_out() {
+    let server = start();
+    let mut client = Client::connect(server.addr());
+    let started = Instant::now();
+    assert_eq!(client.call(&["BLPOP", "list", "0.1"]), RespValue::NullArray);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    // the client runs commands again once it is unblocked
+    assert_eq!(client.call(&["PING"]), RespValue::simple("PONG"));
+}
+
+#[test]
+fn a_client_that_hung_up_is_not_served() {
+    let server = start();
+    let mut pusher = Client::connect(server.addr());
+    let mut gone = Client::connect(server.addr());
+    block(&mut gone, &["BLPOP", "list", "0"]);
+    drop(gone);
+    thread::sleep(Duration::from_millis(50));
+
+    pusher.call(&["RPUSH", "list", "a"]);
+    assert_eq!(
+        pusher.call(&["LRANGE", "list", "0", "-1"]),
+        RespValue::bulk_array(["a"])
+    );
+}
+
+#[test]
+fn commands_sent_while_blocked_run_after_the_reply() {
+    let server = start();
+    let mut pusher = Client::connect(server.addr());
+    let mut client = Client::connect(server.addr());
+    block(&mut client, &["BLPOP", "list", "0"]);
+    client.send(&["GET", "k"]);
+    pusher.call(&["SET", "k", "v"]);
+    pusher.call(&["LPUSH", "list", "a"]);
+    assert_eq!(client.read(), RespValue::bulk_array(["list", "a"]));
+    assert_eq!(client.read(), bulk("v"));
+}
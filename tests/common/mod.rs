@@ -0,0 +1,136 @@
+//! A bare RESP client and helpers to run servers in process
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use codecrafters_redis::resp::{decode, Protocol, RespValue};
+use codecrafters_redis::server::{Server, ServerBuilder, ServerHandle};
+
+/// How long a test waits on the server before giving up
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A master on a free port
+pub fn start() -> ServerHandle {
+    start_with(Server::builder())
+}
+
+pub fn start_with(builder: ServerBuilder) -> ServerHandle {
+    builder.port(0).build().unwrap().spawn()
+}
+
+pub struct Client {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    pub fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        Client {
+            stream,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn send(&mut self, args: &[&str]) {
+        let command = RespValue::bulk_array(args).to_bytes(Protocol::Resp2);
+        self.stream.write_all(&command).unwrap();
+    }
+
+    pub fn call(&mut self, args: &[&str]) -> RespValue {
+        self.send(args);
+        self.read()
+    }
+
+    /// The next value the server sent, failing the test when none comes in time
+    pub fn read(&mut self) -> RespValue {
+        loop {
+            if let Some((value, len)) = decode(&self.buf).unwrap() {
+                self.buf.drain(..len);
+                return value;
+            }
+            self.fill();
+        }
+    }
+
+    /// Whether the server sends anything within `wait`
+    pub fn is_quiet_for(&mut self, wait: Duration) -> bool {
+        if !self.buf.is_empty() {
+            return false;
+        }
+        self.stream.set_read_timeout(Some(wait)).unwrap();
+        let mut chunk = [0; 1024];
+        let quiet = match self.stream.read(&mut chunk) {
+            Ok(n) => {
+                self.buf.extend_from_slice(&chunk[..n]);
+                false
+            }
+            Err(_) => true,
+        };
+        self.stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        quiet
+    }
+
+    /// Whether the server hung up without sending anything else
+    pub fn is_closed(&mut self) -> bool {
+        self.buf.is_empty() && matches!(self.stream.read(&mut [0; 64]), Ok(0))
+    }
+
+    /// Becomes a replica by hand, so a test sees exactly what the master propagates
+    pub fn handshake_as_replica(&mut self) {
+        assert_eq!(self.call(&["PING"]), RespValue::simple("PONG"));
+        assert_eq!(
+            self.call(&["REPLCONF", "listening-port", "6380"]),
+            RespValue::ok()
+        );
+        assert_eq!(self.call(&["REPLCONF", "capa", "psync2"]), RespValue::ok());
+        self.send(&["PSYNC", "?", "-1"]);
+        let RespValue::SimpleString(resync) = self.read() else {
+            panic!("expected FULLRESYNC");
+        };
+        assert!(resync.starts_with("FULLRESYNC"));
+        // the RDB is a bulk string without the trailing CRLF
+        let header_end = loop {
+            if let Some(i) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                break i;
+            }
+            self.fill();
+        };
+        let len: usize = std::str::from_utf8(&self.buf[1..header_end])
+            .unwrap()
+            .parse()
+            .unwrap();
+        while self.buf.len() < header_end + 2 + len {
+            self.fill();
+        }
+        self.buf.drain(..header_end + 2 + len);
+    }
+
+    fn fill(&mut self) {
+        let mut chunk = [0; 16 * 1024];
+        let n = self.stream.read(&mut chunk).expect("no reply in time");
+        assert!(n > 0, "connection closed");
+        self.buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+pub fn bulk(s: &str) -> RespValue {
+    RespValue::bulk(s)
+}
+
+pub fn command(args: &[&str]) -> RespValue {
+    RespValue::bulk_array(args)
+}
+
+/// Retries `check` until it holds, for state that reaches another server eventually
+pub fn eventually(mut check: impl FnMut() -> bool) {
+    let deadline = Instant::now() + TIMEOUT;
+    while !check() {
+        assert!(Instant::now() < deadline, "condition not met in time");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
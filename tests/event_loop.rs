@@ -0,0 +1,118 @@
+mod common;
+
+use codecrafters_redis::handler::{Command, CommandContext, Reply};
+use codecrafters_redis::resp::RespValue;
+use codecrafters_redis::server::Server;
+
+use common::{bulk, start, start_with, Client};
+
+#[test]
+fn pipelined_commands_are_answered_in_order() {
+    let server = start();
+    let mut client = Client::connect(server.addr());
+    client.send(&["SET", "k", "1"]);
+    client.send(&["INCR", "k"]);
+    client.send(&["GET", "k"]);
+    assert_eq!(client.read(), RespValue::ok());
+    assert_eq!(client.read(), RespValue::Integer(2));
+    assert_eq!(client.read(), bulk("2"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn a_client_not_reading_holds_up_nobody_else() {
+    let server = start();
+    let big = "x".repeat(4 * 1024 * 1024);
+    let mut slow = Client::connect(server.addr());
+    assert_eq!(slow.call(&["SET", "big", &big]), RespValue::ok());
+    // more than the socket buffers hold, the rest waits in the write buffer
+    for _ in 0..4 {
+        slow.send(&["GET", "big"]);
+    }
+
+    let mut other = Client::connect(server.addr());
+    assert_eq!(other.call(&["PING"]), RespValue::simple("PONG"));
+
+    for _ in 0..4 {
+        assert_eq!(slow.read(), bulk(&big));
+    }
+}
+
+#[test]
+fn published_messages_reach_subscribers() {
+    let server = start();
+    let mut subscriber = Client::connect(server.addr());
+    subscriber.call(&["SUBSCRIBE", "news"]);
+    let mut gone = Client::connect(server.addr());
+    gone.call(&["SUBSCRIBE", "news"]);
+    drop(gone);
+
+    let mut publisher = Client::connect(server.addr());
+    common::eventually(|| publisher.call(&["PUBLISH", "news", "hi"]) == RespValue::Integer(1));
+    assert_eq!(
+        subscriber.read(),
+        RespValue::bulk_array(["message", "news", "hi"])
+    );
+}
+
+#[test]
+fn shutdown_hangs_up_on_clients() {
+    let server = start();
+    let mut client = Client::connect(server.addr());
+    assert_eq!(client.call(&["PING"]), RespValue::simple("PONG"));
+    server.shutdown().unwrap();
+    assert!(client.is_closed());
+}
+
+struct Double;
+
+impl Command for Double {
+    fn name(&self) -> &'static str {
+        "Double"
+    }
+
+    fn arity(&self) -> i32 {
+        2
+    }
+
+    fn execute(&self, _ctx: &mut CommandContext, args: &[Vec<u8>]) -> Reply {
+        RespValue::bulk(args[0].repeat(2))
+    }
+}
+
+struct Named(&'static str);
+
+impl Command for Named {
+    fn name(&self) -> &'static str {
+        self.0
+    }
+
+    fn arity(&self) -> i32 {
+        1
+    }
+
+    fn execute(&self, _ctx: &mut CommandContext, _args: &[Vec<u8>]) -> Reply {
+        RespValue::ok()
+    }
+}
+
+#[test]
+fn custom_commands_are_called_in_any_case() {
+    let builder = Server::builder().command(Double).unwrap();
+    let server = start_with(builder);
+    let mut client = Client::connect(server.addr());
+    assert_eq!(client.call(&["double", "ab"]), bulk("abab"));
+    assert_eq!(client.call(&["DOUBLE", "ab"]), bulk("abab"));
+    assert!(client.call(&["double"]).is_error());
+}
+
+#[test]
+fn custom_command_names_are_checked() {
+    assert!(Server::builder().command(Named("get")).is_err());
+    assert!(Server::builder().command(Named("GET")).is_err());
+    assert!(Server::builder().command(Named("two words")).is_err());
+    assert!(Server::builder().command(Named("")).is_err());
+    assert!(Server::builder().command(Named("my.cmd")).is_ok());
+    let twice = Server::builder().command(Named("mine")).unwrap();
+    assert!(twice.command(Named("MINE")).is_err());
+}
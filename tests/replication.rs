@@ -0,0 +1,149 @@
+mod common;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use codecrafters_redis::handler::{Command, CommandContext, Flag, Reply};
+use codecrafters_redis::redis_database::{RedisData, RedisValue};
+use codecrafters_redis::resp::RespValue;
+use codecrafters_redis::server::Server;
+
+use common::{bulk, command, start, start_with, Client};
+
+#[test]
+fn a_replica_follows_its_master() {
+    let master = start();
+    let replica = start_with(Server::builder().replica_of("127.0.0.1", master.addr().port()));
+    let mut client = Client::connect(master.addr());
+    let mut replica_client = Client::connect(replica.addr());
+
+    assert_eq!(client.call(&["SET", "k", "v"]), RespValue::ok());
+    client.call(&["RPUSH", "list", "a", "b"]);
+    common::eventually(|| client.call(&["WAIT", "1", "100"]) == RespValue::Integer(1));
+    assert_eq!(replica_client.call(&["GET", "k"]), bulk("v"));
+    assert_eq!(
+        replica_client.call(&["LRANGE", "list", "0", "-1"]),
+        RespValue::bulk_array(["a", "b"])
+    );
+
+    // a client that wrote nothing is told how many replicas are connected
+    let mut fresh = Client::connect(master.addr());
+    assert_eq!(fresh.call(&["WAIT", "0", "0"]), RespValue::Integer(1));
+    replica.shutdown().unwrap();
+    common::eventually(|| fresh.call(&["WAIT", "0", "0"]) == RespValue::Integer(0));
+}
+
+#[test]
+fn writes_are_propagated_with_absolute_times() {
+    let master = start();
+    let mut replica = Client::connect(master.addr());
+    replica.handshake_as_replica();
+    let mut client = Client::connect(master.addr());
+
+    client.call(&["SET", "k", "v", "PX", "100000"]);
+    let RespValue::Array(set) = replica.read() else {
+        panic!("expected a command");
+    };
+    assert_eq!(set[..4], [bulk("SET"), bulk("k"), bulk("v"), bulk("PXAT")]);
+    let RespValue::BulkString(at) = &set[4] else {
+        panic!("expected a time");
+    };
+    let at: u128 = std::str::from_utf8(at).unwrap().parse().unwrap();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    assert!(at > now.as_millis() + 90_000);
+
+    client.call(&["EXPIRE", "k", "-1"]);
+    assert_eq!(replica.read(), command(&["DEL", "k"]));
+}
+
+#[test]
+fn commands_that_change_nothing_are_not_propagated() {
+    let master = start();
+    let mut replica = Client::connect(master.addr());
+    replica.handshake_as_replica();
+    let mut client = Client::connect(master.addr());
+
+    client.call(&["SADD", "s", "a"]);
+    assert_eq!(replica.read(), command(&["SADD", "s", "a"]));
+    assert!(client.call(&["HINCRBYFLOAT", "h", "f", "inf"]).is_error());
+    assert_eq!(
+        client.call(&["SMOVE", "s", "s", "a"]),
+        RespValue::Integer(1)
+    );
+    assert_eq!(client.call(&["SADD", "s", "a"]), RespValue::Integer(0));
+    assert_eq!(client.call(&["DEL", "missing"]), RespValue::Integer(0));
+    client.call(&["SET", "a", "1"]);
+    assert_eq!(replica.read(), command(&["SET", "a", "1"]));
+    assert_eq!(client.call(&["EXISTS", "h"]), RespValue::Integer(0));
+}
+
+#[test]
+fn served_blocked_clients_are_propagated_as_plain_pops() {
+    let master = start();
+    let mut replica = Client::connect(master.addr());
+    replica.handshake_as_replica();
+    let mut client = Client::connect(master.addr());
+    let mut waiter = Client::connect(master.addr());
+
+    waiter.send(&["BLPOP", "list", "0"]);
+    std::thread::sleep(Duration::from_millis(50));
+    client.call(&["RPUSH", "list", "a"]);
+    assert_eq!(waiter.read(), RespValue::bulk_array(["list", "a"]));
+    assert_eq!(replica.read(), command(&["RPUSH", "list", "a"]));
+    assert_eq!(replica.read(), command(&["LPOP", "list"]));
+}
+
+#[test]
+fn wait_counts_replicas_that_acknowledged() {
+    let master = start();
+    let mut replica = Client::connect(master.addr());
+    replica.handshake_as_replica();
+    let mut client = Client::connect(master.addr());
+
+    client.call(&["SET", "k", "v"]);
+    client.send(&["WAIT", "1", "5000"]);
+    assert_eq!(replica.read(), command(&["SET", "k", "v"]));
+    assert_eq!(replica.read(), command(&["REPLCONF", "GETACK", "*"]));
+    assert!(client.is_quiet_for(Duration::from_millis(50)));
+    replica.send(&["REPLCONF", "ACK", "31"]);
+    assert_eq!(client.read(), RespValue::Integer(1));
+}
+
+/// SETNEW key value, sets key only when it does not exist yet
+struct SetNew;
+
+impl Command for SetNew {
+    fn name(&self) -> &'static str {
+        "setnew"
+    }
+
+    fn arity(&self) -> i32 {
+        3
+    }
+
+    fn flags(&self) -> &'static [Flag] {
+        &[Flag::Write]
+    }
+
+    fn execute(&self, ctx: &mut CommandContext, args: &[Vec<u8>]) -> Reply {
+        if ctx.db.contains_key(&args[0]) {
+            return RespValue::Integer(0);
+        }
+        let value = RedisValue::new(RedisData::String(args[1].clone()));
+        ctx.db.insert(args[0].clone(), value);
+        RespValue::Integer(1)
+    }
+}
+
+#[test]
+fn custom_commands_are_propagated_when_they_change_the_keyspace() {
+    let master = start_with(Server::builder().command(SetNew).unwrap());
+    let mut replica = Client::connect(master.addr());
+    replica.handshake_as_replica();
+    let mut client = Client::connect(master.addr());
+
+    assert_eq!(client.call(&["SETNEW", "k", "1"]), RespValue::Integer(1));
+    assert_eq!(client.call(&["SETNEW", "k", "2"]), RespValue::Integer(0));
+    client.call(&["SET", "done", "1"]);
+    assert_eq!(replica.read(), command(&["SETNEW", "k", "1"]));
+    assert_eq!(replica.read(), command(&["SET", "done", "1"]));
+}